@@ -0,0 +1,830 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use argh::FromArgs;
+use crossterm::{
+    cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    execute, queue,
+    style::Print,
+    terminal::{self, ClearType},
+};
+
+use crate::cookie::{
+    atomic,
+    collate::Collation,
+    compress, datfile,
+    layer::EMBED_LAYER,
+    parse_metadata,
+    search::{self, Query, SearchIndex},
+    walk::Walker,
+    Cookie, CookieCabinet, CookieJar, WeightMode, DEFAULT_DELIMITER, FLAGS_COMMENTS,
+};
+use crate::serve::filter_args;
+use crate::{generate_filters, prepare_shelves};
+
+#[derive(FromArgs)]
+/// Browse the fortune collections in the terminal: page through the cookies, search them,
+/// and mark them for deletion or tagging.
+pub struct BrowseArgs {
+    /// how the weights are given: percent (default), relative
+    #[argh(option)]
+    weights: Option<String>,
+
+    /// maximum depth of the fortune files to load from directories
+    #[argh(option)]
+    max_depth: Option<usize>,
+
+    /// follow symbolic links to directories
+    #[argh(switch)]
+    follow_symlinks: bool,
+
+    /// [[n%] file/directory/all]
+    #[argh(positional)]
+    paths: Vec<String>,
+}
+
+const HELP_JARS: &str = "↑↓ select  ⏎ open  / search  i ignore case  w write  q quit";
+const HELP_COOKIES: &str =
+    "←→ page  d delete  t tags  / search  i ignore case  w write  esc back  q quit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Jars,
+    Cookies,
+    Search, // typing the search pattern
+    Tags,   // typing the tags of the cookie
+}
+
+/// The file and the offset of an edited cookie.
+type EditKey = (PathBuf, u64);
+
+/// A pending edit of a cookie, written back to its text file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Edit {
+    content: String, // to check that the cookie is still the same in the file
+    delim: char,     // the delimiter of the file
+    delete: bool,
+    tags: Option<String>,
+}
+
+/// The state of the browser, apart from the terminal.
+struct Browser {
+    shelves: CookieCabinet, // the shelves before loading, to reload from after writing
+    cabinet: CookieCabinet,
+    jars: Vec<(usize, usize)>, // shelf and jar indexes of all jars
+    matches: Vec<Vec<usize>>,  // indexes of the cookies matching the search, for each jar
+    selected: usize,           // index of the selected jar, among the jars with matches
+    page: usize,               // index of the shown cookie, among the matches of the jar
+    mode: Mode,
+    previous: Mode, // the mode to return to from the input
+    input: String,
+    search: String,
+    search_before: String, // the search before typing, which Esc goes back to
+    ignore_case: bool,
+    edits: BTreeMap<EditKey, Edit>,
+    status: String,
+    confirm_quit: bool, // the unwritten edits are dropped by another q
+    quit: bool,
+}
+
+impl Browser {
+    fn new(shelves: CookieCabinet) -> Result<Self> {
+        let mut browser = Browser {
+            shelves,
+            cabinet: CookieCabinet::default(),
+            jars: Vec::new(),
+            matches: Vec::new(),
+            selected: 0,
+            page: 0,
+            mode: Mode::Jars,
+            previous: Mode::Jars,
+            input: String::new(),
+            search: String::new(),
+            search_before: String::new(),
+            ignore_case: false,
+            edits: BTreeMap::new(),
+            status: String::new(),
+            confirm_quit: false,
+            quit: false,
+        };
+        browser.reload()?;
+        Ok(browser)
+    }
+
+    /// Load all the jars, both normal and offensive, with the probabilities of `-f`.
+    fn reload(&mut self) -> Result<()> {
+        let mut cabinet = self.shelves.clone();
+        cabinet.load(true, true)?;
+        cabinet.calculate_prob(false);
+        self.jars = cabinet
+            .shelves
+            .iter()
+            .enumerate()
+            .flat_map(|(i, shelf)| (0..shelf.jars.len()).map(move |j| (i, j)))
+            .collect();
+        self.cabinet = cabinet;
+        let search = self.search.clone();
+        self.set_search(&search);
+        Ok(())
+    }
+
+    fn jar(&self, index: usize) -> &CookieJar {
+        let (i, j) = self.jars[index];
+        &self.cabinet.shelves[i].jars[j]
+    }
+
+    /// The text file of the jar, which the edits are written to.
+    fn jar_file(&self, index: usize) -> Result<PathBuf> {
+        let (i, j) = self.jars[index];
+        let shelf = &self.cabinet.shelves[i];
+        let jar = &shelf.jars[j];
+        let file = if jar.layer == EMBED_LAYER {
+            anyhow::bail!("{} is embedded, and cannot be edited", jar.location);
+        } else if !jar.layer.is_empty() {
+            Path::new(&jar.layer).join(&jar.location)
+        } else if Path::new(&shelf.location).is_file() {
+            PathBuf::from(&shelf.location)
+        } else {
+            Path::new(&shelf.location).join(&jar.location)
+        };
+        if compress::strip_suffix(&file.to_string_lossy()) != file.to_string_lossy() {
+            anyhow::bail!("{} is compressed, and cannot be edited", file.display());
+        }
+        if !file.is_file() {
+            anyhow::bail!("{} is not a file, and cannot be edited", file.display());
+        }
+        Ok(file)
+    }
+
+    /// The jars which have any cookie matching the search.
+    fn visible_jars(&self) -> Vec<usize> {
+        (0..self.jars.len())
+            .filter(|i| !self.matches[*i].is_empty())
+            .collect()
+    }
+
+    fn selected_cookie(&self) -> Option<(usize, &Cookie)> {
+        let jar = *self.visible_jars().get(self.selected)?;
+        let cookie = *self.matches[jar].get(self.page)?;
+        Some((jar, &self.jar(jar).cookies[cookie]))
+    }
+
+    /// Match the cookies with the pattern, the same as `fortune -m`. An invalid pattern keeps
+    /// the previous matches, as it is usually not typed completely yet.
+    fn set_search(&mut self, pattern: &str) {
+        let mut tokens = Vec::new();
+        if !pattern.is_empty() {
+            tokens.extend(["-m".to_string(), pattern.to_string()]);
+        }
+        if self.ignore_case {
+            tokens.push("-i".to_string());
+        }
+        let filters = filter_args(&tokens)
+            .and_then(|args| generate_filters(&args, None).map_err(anyhow::Error::from));
+        let filters = match filters {
+            Ok(filters) => filters,
+            Err(e) => {
+                // the first line only, without the position of the error
+                let message = format!("{:#}", e);
+                self.status = message.lines().next().unwrap_or_default().to_string();
+                return;
+            }
+        };
+        self.search = pattern.to_string();
+        self.status.clear();
+        self.matches = (0..self.jars.len())
+            .map(|i| {
+                let jar = self.jar(i);
+                (0..jar.cookies.len())
+                    .filter(|c| filters.filter(&jar.cookies[*c].content))
+                    .collect()
+            })
+            .collect();
+        self.selected = self
+            .selected
+            .min(self.visible_jars().len().saturating_sub(1));
+        self.page = 0;
+    }
+
+    fn handle(&mut self, key: KeyEvent) -> Result<()> {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return Ok(());
+        }
+        match self.mode {
+            Mode::Search | Mode::Tags => self.handle_input(key),
+            Mode::Jars | Mode::Cookies => self.handle_command(key),
+        }
+    }
+
+    fn handle_input(&mut self, key: KeyEvent) -> Result<()> {
+        match key.code {
+            KeyCode::Char(c) => self.input.push(c),
+            KeyCode::Backspace => {
+                self.input.pop();
+            }
+            KeyCode::Enter => {
+                if self.mode == Mode::Tags {
+                    let tags = std::mem::take(&mut self.input);
+                    if let Err(e) = self.set_tags(&tags) {
+                        self.status = format!("{:#}", e);
+                    }
+                }
+                self.mode = self.previous;
+                return Ok(());
+            }
+            KeyCode::Esc => {
+                if self.mode == Mode::Search {
+                    let search = std::mem::take(&mut self.search_before);
+                    self.set_search(&search);
+                }
+                self.mode = self.previous;
+                return Ok(());
+            }
+            _ => return Ok(()),
+        }
+        if self.mode == Mode::Search {
+            let search = self.input.clone();
+            self.set_search(&search);
+        }
+        Ok(())
+    }
+
+    fn handle_command(&mut self, key: KeyEvent) -> Result<()> {
+        let num_jars = self.visible_jars().len();
+        let num_cookies = match self.visible_jars().get(self.selected) {
+            Some(jar) => self.matches[*jar].len(),
+            None => 0,
+        };
+        let confirm_quit = std::mem::take(&mut self.confirm_quit);
+        self.status.clear();
+        match (self.mode, key.code) {
+            (_, KeyCode::Char('q')) => {
+                if self.edits.is_empty() || confirm_quit {
+                    self.quit = true;
+                } else {
+                    self.confirm_quit = true;
+                    self.status = format!(
+                        "{} unwritten edits, press q again to quit, or w to write them",
+                        self.edits.len()
+                    );
+                }
+            }
+            (_, KeyCode::Char('/')) => {
+                self.input = self.search.clone();
+                self.search_before = self.search.clone();
+                self.previous = self.mode;
+                self.mode = Mode::Search;
+            }
+            (_, KeyCode::Char('i')) => {
+                self.ignore_case = !self.ignore_case;
+                let search = self.search.clone();
+                self.set_search(&search);
+            }
+            (_, KeyCode::Char('w')) => match self.write() {
+                Ok(num_files) => self.status = format!("Written to {} files", num_files),
+                Err(e) => self.status = format!("{:#}", e),
+            },
+            (Mode::Jars, KeyCode::Up | KeyCode::Char('k')) => {
+                self.selected = self.selected.saturating_sub(1);
+            }
+            (Mode::Jars, KeyCode::Down | KeyCode::Char('j')) => {
+                self.selected = (self.selected + 1).min(num_jars.saturating_sub(1));
+            }
+            (Mode::Jars, KeyCode::Enter | KeyCode::Right | KeyCode::Char('l')) if num_jars > 0 => {
+                self.page = 0;
+                self.mode = Mode::Cookies;
+            }
+            (Mode::Cookies, KeyCode::Esc | KeyCode::Backspace | KeyCode::Char('h')) => {
+                self.mode = Mode::Jars;
+            }
+            (Mode::Cookies, KeyCode::Right | KeyCode::PageDown | KeyCode::Char(' ' | 'n')) => {
+                self.page = (self.page + 1).min(num_cookies.saturating_sub(1));
+            }
+            (Mode::Cookies, KeyCode::Left | KeyCode::PageUp | KeyCode::Char('p')) => {
+                self.page = self.page.saturating_sub(1);
+            }
+            (Mode::Cookies, KeyCode::Char('d')) => {
+                if let Err(e) = self.toggle_delete() {
+                    self.status = format!("{:#}", e);
+                }
+            }
+            (Mode::Cookies, KeyCode::Char('t')) => match self.tag_key() {
+                Ok(Some((key, _, tags))) => {
+                    // the tags typed before, if not written yet
+                    self.input = match self.edits.get(&key).and_then(|e| e.tags.clone()) {
+                        Some(edited) => edited,
+                        None => tags,
+                    };
+                    self.previous = self.mode;
+                    self.mode = Mode::Tags;
+                }
+                Ok(None) => {}
+                Err(e) => self.status = format!("{:#}", e),
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// The key of the edits of the shown cookie, and an edit without changes,
+    /// if the cookie can be edited.
+    fn edit_key(&self) -> Result<Option<(EditKey, Edit)>> {
+        let Some((jar, cookie)) = self.selected_cookie() else {
+            return Ok(None);
+        };
+        let file = self.jar_file(jar)?;
+        let edit = Edit {
+            content: cookie.content.clone(),
+            delim: self.jar(jar).delim,
+            ..Edit::default()
+        };
+        Ok(Some(((file, cookie.offset), edit)))
+    }
+
+    /// The same as `edit_key()` with the current tags, if the cookie can be tagged. The tags
+    /// are comment lines, which are only read from the files built with `strfile -C`.
+    fn tag_key(&self) -> Result<Option<(EditKey, Edit, String)>> {
+        let Some((jar, cookie)) = self.selected_cookie() else {
+            return Ok(None);
+        };
+        let jar = self.jar(jar);
+        if jar.flags & FLAGS_COMMENTS == 0 {
+            anyhow::bail!(
+                "{} has no comment lines, build its data file with 'strfile -C' to tag it",
+                jar.location
+            );
+        }
+        let tags = cookie.metadata.get("tags").cloned().unwrap_or_default();
+        Ok(self.edit_key()?.map(|(key, edit)| (key, edit, tags)))
+    }
+
+    fn toggle_delete(&mut self) -> Result<()> {
+        if let Some((key, unchanged)) = self.edit_key()? {
+            let edit = self.edits.entry(key.clone()).or_insert(unchanged);
+            edit.delete = !edit.delete;
+            if !edit.delete && edit.tags.is_none() {
+                self.edits.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    fn set_tags(&mut self, tags: &str) -> Result<()> {
+        if let Some((key, unchanged, current)) = self.tag_key()? {
+            let tags: Vec<&str> = tags
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .collect();
+            let tags = tags.join(", ");
+            let is_changed = current != tags;
+            let edit = self.edits.entry(key.clone()).or_insert(unchanged);
+            edit.tags = if is_changed { Some(tags) } else { None };
+            if !edit.delete && edit.tags.is_none() {
+                self.edits.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the edits back to the text files, and reload them. Returns the number of files.
+    fn write(&mut self) -> Result<usize> {
+        let mut files: BTreeMap<&Path, BTreeMap<u64, &Edit>> = BTreeMap::new();
+        for ((file, offset), edit) in self.edits.iter() {
+            files.entry(file).or_default().insert(*offset, edit);
+        }
+        for (file, edits) in files.iter() {
+            apply_edits(file, edits)?;
+            let delim = edits.values().next().map_or(DEFAULT_DELIMITER, |e| e.delim);
+            rebuild_indexes(file, delim)?;
+        }
+        let num_files = files.len();
+        self.edits.clear();
+        self.reload()?;
+        Ok(num_files)
+    }
+
+    /// The lines of the screen, each within the width.
+    fn render(&self, width: usize, height: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        let visible = self.visible_jars();
+        let num_matches: usize = visible.iter().map(|j| self.matches[*j].len()).sum();
+        let mut title = format!(
+            "fortune browse: {} cookies in {} jars",
+            num_matches,
+            visible.len()
+        );
+        if !self.search.is_empty() {
+            let flags = if self.ignore_case { "i" } else { "" };
+            title.push_str(&format!(", matching /{}/{}", self.search, flags));
+        }
+        if !self.edits.is_empty() {
+            title.push_str(&format!(", {} unwritten edits", self.edits.len()));
+        }
+        lines.push(title);
+        lines.push(String::new());
+
+        let body = height.saturating_sub(4);
+        let view = if self.mode == Mode::Search || self.mode == Mode::Tags {
+            self.previous
+        } else {
+            self.mode
+        };
+        match view {
+            Mode::Cookies => lines.extend(self.render_cookie(body)),
+            _ => lines.extend(self.render_jars(&visible, body)),
+        }
+
+        while lines.len() + 2 < height {
+            lines.push(String::new());
+        }
+        lines.push(String::new());
+        lines.push(match self.mode {
+            Mode::Search if !self.status.is_empty() => {
+                format!("/{}  ({})", self.input, self.status)
+            }
+            Mode::Search => format!("/{}", self.input),
+            Mode::Tags => format!("tags: {}", self.input),
+            _ if !self.status.is_empty() => self.status.clone(),
+            Mode::Jars => HELP_JARS.to_string(),
+            Mode::Cookies => HELP_COOKIES.to_string(),
+        });
+        lines
+            .into_iter()
+            .map(|line| line.chars().take(width).collect())
+            .collect()
+    }
+
+    /// The shelves and their jars with the number of the matching cookies, as `-f` lists them.
+    fn render_jars(&self, visible: &[usize], height: usize) -> Vec<String> {
+        let mut lines = Vec::new();
+        let mut selected_line = 0;
+        let mut shelf = usize::MAX;
+        for (n, jar) in visible.iter().enumerate() {
+            let (i, _) = self.jars[*jar];
+            if i != shelf {
+                shelf = i;
+                let shelf = &self.cabinet.shelves[i];
+                lines.push(format!("{:5.2}% {}", shelf.probability, shelf.location));
+            }
+            if n == self.selected {
+                selected_line = lines.len();
+            }
+            let jar_ref = self.jar(*jar);
+            lines.push(format!(
+                "  {} {:5.2}% {} ({}/{})",
+                if n == self.selected { ">" } else { " " },
+                jar_ref.probability,
+                jar_ref.location,
+                self.matches[*jar].len(),
+                jar_ref.num_of_cookies()
+            ));
+        }
+        if lines.is_empty() {
+            lines.push("No matching fortune cookies".to_string());
+        }
+        // scroll to keep the selected jar on the screen
+        let start = (selected_line + 1).saturating_sub(height);
+        lines.into_iter().skip(start).take(height).collect()
+    }
+
+    /// The shown cookie with its ID, location, edits and metadata.
+    fn render_cookie(&self, height: usize) -> Vec<String> {
+        let Some((jar, cookie)) = self.selected_cookie() else {
+            return vec!["No matching fortune cookies".to_string()];
+        };
+        let mut lines = vec![
+            format!(
+                "[{}/{}] ID: {}/{}",
+                self.page + 1,
+                self.matches[jar].len(),
+                cookie.location,
+                cookie.offset
+            ),
+            match self.jar_file(jar) {
+                Ok(file) => format!("File: {}", file.display()),
+                Err(_) => format!("Location: {}", cookie.location),
+            },
+        ];
+        let edit = self
+            .jar_file(jar)
+            .ok()
+            .and_then(|file| self.edits.get(&(file, cookie.offset)));
+        if let Some(edit) = edit {
+            let mut marks = Vec::new();
+            if edit.delete {
+                marks.push("DELETE".to_string());
+            }
+            if let Some(tags) = &edit.tags {
+                marks.push(format!("TAGS: {}", tags));
+            }
+            lines.push(format!("Marked: {}", marks.join(", ")));
+        }
+        lines.push(String::new());
+        let delim = self.jar(jar).delim;
+        for (key, value) in cookie.metadata.iter() {
+            lines.push(format!("{}{} {}: {}", delim, delim, key, value));
+        }
+        lines.extend(cookie.content.lines().map(String::from));
+        lines.truncate(height);
+        lines
+    }
+}
+
+/// Apply the edits to the cookies of the text file, by their offsets. Only the edited cookies
+/// are rewritten, and the rest of the file is kept as is, e.g. its line endings.
+fn apply_edits(file: &Path, edits: &BTreeMap<u64, &Edit>) -> Result<()> {
+    let location = file.to_string_lossy();
+    let raw =
+        std::fs::read_to_string(file).with_context(|| format!("Failed to read {}", location))?;
+    // the offsets are in the text with the normalized line endings, as the cookies are loaded
+    let (text, raw_offsets) = normalize_newlines(&raw);
+    let newline = if raw.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut written = String::new();
+    let mut copied = 0; // the end of the raw text copied so far
+    for (offset, edit) in edits.iter() {
+        let splitter = format!("\n{}\n", edit.delim);
+        let start = *offset as usize;
+        let is_start = start == 0 || text.get(..start).is_some_and(|t| t.ends_with(&splitter));
+        let Some(rest) = text
+            .get(start..)
+            .filter(|_| is_start && raw_offsets[start] >= copied)
+        else {
+            anyhow::bail!("{} has changed since it was loaded", location);
+        };
+        let end = start + rest.find(&splitter).unwrap_or(rest.len());
+        if !is_same_cookie(&text[start..end], edit) {
+            anyhow::bail!("{} has changed since it was loaded", location);
+        }
+        written.push_str(&raw[copied..raw_offsets[start]]);
+        if edit.delete {
+            // with the delimiter line after the cookie
+            copied = raw_offsets[(end + splitter.len()).min(text.len())];
+            continue;
+        }
+        let part = &raw[raw_offsets[start]..raw_offsets[end]];
+        let comment = format!("{}{}", edit.delim, edit.delim);
+        if let Some(tags) = edit.tags.as_deref().filter(|t| !t.is_empty()) {
+            written.push_str(&format!("{} tags: {}{}", comment, tags, newline));
+        }
+        for line in part.split_inclusive('\n') {
+            let is_tags = line
+                .trim_end()
+                .strip_prefix(comment.as_str())
+                .and_then(parse_metadata)
+                .is_some_and(|(key, _)| key == "tags");
+            if !(is_tags && edit.tags.is_some()) {
+                written.push_str(line);
+            }
+        }
+        copied = raw_offsets[end];
+    }
+    written.push_str(&raw[copied..]);
+    atomic::write_file(file, written.as_bytes())
+}
+
+/// Rebuild the data file and the search index of the edited file, if it has them, so that
+/// their offsets are of the edited cookies.
+fn rebuild_indexes(file: &Path, delim: char) -> Result<()> {
+    let datfile = datfile::dat_path(file);
+    if datfile.is_file() {
+        // the data file does not keep the case or the locale of its order, the default is used
+        let collation = Collation::Bsd { ignore_case: false };
+        datfile::rebuild(file, &datfile, &collation, false)?;
+        return Ok(());
+    }
+    let textfile = file.to_string_lossy();
+    if Path::new(&search::index_path(&textfile)).exists() {
+        let jar = CookieJar::from_text_file(&textfile, delim)?;
+        SearchIndex::from_jar(&jar).write(&textfile)?;
+    }
+    Ok(())
+}
+
+/// Whether the text of the file is still the cookie of the edit, either as is or without the
+/// comment lines, the same as the cookie is loaded.
+fn is_same_cookie(part: &str, edit: &Edit) -> bool {
+    let Ok(mut jar) = CookieJar::from_text(part, "", edit.delim) else {
+        return false;
+    };
+    if jar
+        .cookies
+        .first()
+        .is_some_and(|c| c.content == edit.content)
+    {
+        return true;
+    }
+    jar.parse_comments();
+    jar.cookies
+        .first()
+        .is_some_and(|c| c.content == edit.content)
+}
+
+/// The text with the line endings normalized to `\n`, the same as `CookieJar::from_text()`,
+/// and the offset in the original text of each byte of it, and of its end.
+fn normalize_newlines(raw: &str) -> (String, Vec<usize>) {
+    let mut text = String::with_capacity(raw.len());
+    let mut offsets = Vec::with_capacity(raw.len() + 1);
+    let mut chars = raw.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let c = match c {
+            '\r' => {
+                chars.next_if(|(_, c)| *c == '\n');
+                '\n'
+            }
+            c => c,
+        };
+        offsets.extend(std::iter::repeat_n(i, c.len_utf8()));
+        text.push(c);
+    }
+    offsets.push(raw.len());
+    (text, offsets)
+}
+
+/// Restore the terminal when the browser ends, even by an error.
+struct TerminalGuard;
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(
+            std::io::stdout(),
+            terminal::LeaveAlternateScreen,
+            cursor::Show
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+pub fn run(args: BrowseArgs) -> Result<()> {
+    let weight_mode = match &args.weights {
+        Some(mode) => mode.parse::<WeightMode>()?,
+        None => WeightMode::default(),
+    };
+    let mut shelves = CookieCabinet::from_string_list(&args.paths, weight_mode)?;
+    let walker = Walker::new(args.max_depth, args.follow_symlinks);
+    prepare_shelves(&mut shelves, &walker, &Query::All)?;
+    let mut browser = Browser::new(shelves)?;
+
+    let mut stdout = std::io::stdout();
+    terminal::enable_raw_mode()?;
+    let _guard = TerminalGuard;
+    execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+    while !browser.quit {
+        let (width, height) = terminal::size()?;
+        queue!(stdout, terminal::Clear(ClearType::All))?;
+        for (row, line) in browser
+            .render(width as usize, height as usize)
+            .iter()
+            .enumerate()
+        {
+            queue!(stdout, cursor::MoveTo(0, row as u16), Print(line))?;
+        }
+        stdout.flush()?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                browser.handle(key)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookie::serializer::Serializer;
+
+    fn press(browser: &mut Browser, keys: &[KeyCode]) {
+        for key in keys.iter() {
+            browser
+                .handle(KeyEvent::new(*key, KeyModifiers::NONE))
+                .unwrap();
+        }
+    }
+
+    fn type_text(browser: &mut Browser, text: &str) {
+        let keys: Vec<KeyCode> = text.chars().map(KeyCode::Char).collect();
+        press(browser, &keys);
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let dir = std::env::temp_dir().join(format!("fortune-rs-apply-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("fruits");
+        std::fs::write(
+            &file,
+            "%% a comment, not metadata\r\nApple pie.\r\n%\r\n%% tags: fruit\r\nBanana split.\r\n%\r\nCherry   tart.  \r\n%\r\n",
+        )
+        .unwrap();
+
+        let edit = |content: &str, delete: bool, tags: Option<&str>| Edit {
+            content: content.to_string(),
+            delim: '%',
+            delete,
+            tags: tags.map(String::from),
+        };
+        let tag = edit(
+            "%% a comment, not metadata\nApple pie.",
+            false,
+            Some("dessert, baked"),
+        );
+        let untag = edit("Banana split.", false, Some(""));
+        let delete = edit("Cherry   tart.  ", true, None);
+        let edits = BTreeMap::from([(0, &tag), (40, &untag), (71, &delete)]);
+        apply_edits(&file, &edits).unwrap();
+        // only the edited cookies are changed, and the line endings are kept
+        assert_eq!(
+            "%% tags: dessert, baked\r\n%% a comment, not metadata\r\nApple pie.\r\n%\r\nBanana split.\r\n%\r\n",
+            std::fs::read_to_string(&file).unwrap()
+        );
+
+        // the offsets have changed, so the same edits no longer apply
+        assert!(apply_edits(&file, &edits).is_err());
+
+        // the delimiter of the file
+        std::fs::write(&file, "One.\n@\nTwo.\n@\n").unwrap();
+        let delete = Edit {
+            delim: '@',
+            ..edit("One.", true, None)
+        };
+        apply_edits(&file, &BTreeMap::from([(0, &delete)])).unwrap();
+        assert_eq!("Two.\n@\n", std::fs::read_to_string(&file).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_browser() {
+        let dir = std::env::temp_dir().join(format!("fortune-rs-browse-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("fruits"),
+            "Apple pie.\n%\nBanana split.\n%\nCherry tart.\n%\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("nuts"), "Pecan pie.\n%\n").unwrap();
+        // the cookies can be tagged if the data file is built with strfile -C
+        let mut jar =
+            CookieJar::from_text_file(&dir.join("fruits").to_string_lossy(), '%').unwrap();
+        jar.parse_comments();
+        let bytes = Serializer::to_bytes(&jar, &Serializer::get_type_by_platform("linux"));
+        std::fs::write(dir.join("fruits.dat"), bytes).unwrap();
+        let location = dir.to_string_lossy().to_string();
+        let shelves = CookieCabinet::from_string_list(&[location], WeightMode::default()).unwrap();
+        let mut browser = Browser::new(shelves).unwrap();
+        let screen = |browser: &Browser| browser.render(80, 20).join("\n");
+        assert!(screen(&browser).contains("4 cookies in 2 jars"));
+        assert!(screen(&browser).contains("  > 75.00% fruits (3/3)"));
+
+        // incremental search, the same as -m
+        press(&mut browser, &[KeyCode::Char('/')]);
+        type_text(&mut browser, "pie");
+        assert!(screen(&browser).contains("2 cookies in 2 jars, matching /pie/"));
+        type_text(&mut browser, "(");
+        assert!(screen(&browser).contains("invalid pattern"));
+        press(&mut browser, &[KeyCode::Backspace, KeyCode::Enter]);
+        press(
+            &mut browser,
+            &[KeyCode::Char('/'), KeyCode::Char('x'), KeyCode::Esc],
+        );
+        assert_eq!("pie", browser.search);
+
+        // mark the cookies, and write them back
+        press(&mut browser, &[KeyCode::Down, KeyCode::Enter]);
+        assert!(screen(&browser).contains("[1/1] ID: nuts/0"));
+        press(&mut browser, &[KeyCode::Char('t')]);
+        assert!(screen(&browser).contains("nuts has no comment lines"));
+        press(&mut browser, &[KeyCode::Char('d')]);
+        assert!(screen(&browser).contains("Marked: DELETE"));
+        press(&mut browser, &[KeyCode::Esc, KeyCode::Up, KeyCode::Enter]);
+        press(&mut browser, &[KeyCode::Char('t')]);
+        type_text(&mut browser, "dessert,");
+        press(&mut browser, &[KeyCode::Enter]);
+        assert!(screen(&browser).contains("Marked: TAGS: dessert"));
+        press(&mut browser, &[KeyCode::Char('q')]);
+        assert!(!browser.quit);
+        assert!(screen(&browser).contains("2 unwritten edits"));
+        press(&mut browser, &[KeyCode::Char('w')]);
+        assert!(screen(&browser).contains("Written to 2 files"));
+        assert_eq!(
+            "%% tags: dessert\nApple pie.\n%\nBanana split.\n%\nCherry tart.\n%\n",
+            std::fs::read_to_string(dir.join("fruits")).unwrap()
+        );
+        assert_eq!("", std::fs::read_to_string(dir.join("nuts")).unwrap());
+        assert!(screen(&browser).contains("1 cookies in 1 jars"));
+        // the data file is rebuilt with the offsets of the edited file, and keeps its flags
+        let (dat, _) = datfile::read_dat(&dir.join("fruits.dat")).unwrap();
+        let jar = CookieJar::from_text_file(&dir.join("fruits").to_string_lossy(), '%').unwrap();
+        let offsets = |jar: &CookieJar| jar.iter().map(|c| c.offset).collect::<Vec<_>>();
+        assert_eq!(offsets(&jar), offsets(&dat));
+        assert_ne!(0, dat.flags & FLAGS_COMMENTS);
+
+        press(&mut browser, &[KeyCode::Char('q')]);
+        assert!(browser.quit);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
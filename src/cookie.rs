@@ -1,32 +1,59 @@
+pub mod atomic;
+pub mod collate;
+pub mod completions;
+pub mod compress;
+pub mod datfile;
+pub mod dedupe;
 pub mod embed;
+pub mod error;
+pub mod export;
+pub mod import;
+pub mod layer;
+pub mod lint;
+pub mod normalize;
+pub mod search;
 pub mod serializer;
+pub mod stats;
+pub mod walk;
+pub mod weights;
 
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use embed::{Embedded, EMBED_PREFIX};
-use glob::glob;
+use error::ArgError;
+use layer::EMBED_LAYER;
 use log::debug;
 use oxilangtag::LanguageTag;
 use rand::distributions::WeightedIndex;
 use rand::prelude::Distribution;
 use rand::seq::SliceRandom;
+use rand::Rng;
+use search::Query;
 use serializer::Serializer;
 use sys_locale::get_locale;
+use walk::Walker;
+use weights::JarWeights;
 
 /// Constants defining the data file format and flags
 pub const FLAGS_RANDOMIZED: u64 = 0x0001; /* randomized pointers */
 pub const FLAGS_ORDERED: u64 = 0x0002; /* ordered pointers */
 pub const FLAGS_ROTATED: u64 = 0x0004; /* rot-13'd pointers */
+pub const FLAGS_COMMENTS: u64 = 0x0008; /* embedded comments */
 
 pub const DEFAULT_DELIMITER: char = '%';
 
+// tolerance of the floating point error when comparing probabilities
+const PROB_EPSILON: f64 = 0.0001;
+
 /// Represents a single fortune cookie with its text.
 #[derive(Debug, Clone)]
 pub struct Cookie {
-    pub location: String, // Path to the source file
-    pub content: String,  // The actual cookie text
-    pub offset: u64,      // Offset of the cookie in the source file
+    pub location: String,                   // Path to the source file
+    pub content: String,                    // The actual cookie text
+    pub offset: u64,                        // Offset of the cookie in the source file
+    pub metadata: BTreeMap<String, String>, // Metadata from the comment lines, e.g. `%% author: ...`
 }
 
 /// Represents the header structure of a fortune cookie data file.
@@ -34,7 +61,9 @@ pub struct Cookie {
 #[derive(Debug, Clone)]
 pub struct CookieJar {
     pub location: String, // Path to the source file (relative to the shelf's location)
+    pub layer: String,    // Layer the jar was loaded from (data directory or embed), if any
     pub probability: f64, // Probability of selecting this jar
+    pub weight: f64,      // Relative weight of this jar in the shelf, given by weights.toml
     pub platform: String, // Platform to use for serialization, one of: homebrew, linux, freebsd
     pub version: u64,     // Data file format version
     pub max_length: u64,  // Length of longest string
@@ -51,7 +80,9 @@ impl Default for CookieJar {
     fn default() -> Self {
         Self {
             location: "".to_string(),
+            layer: "".to_string(),
             probability: 0.0,
+            weight: 1.0,
             platform: "".to_string(),
             version: 0,
             max_length: 0,
@@ -71,6 +102,7 @@ impl std::fmt::Display for CookieJar {
         write!(f, "CookieJar {{\n")?;
         write!(f, "  location: '{}'\n", self.location)?;
         write!(f, "  probability: {}\n", self.probability)?;
+        writeln!(f, "  weight: {}", self.weight)?;
         write!(f, "  platform: '{}'\n", self.platform)?;
         write!(f, "  version: {}\n", self.version)?;
         write!(f, "  num_cookies: {}\n", self.cookies.len())?;
@@ -87,6 +119,9 @@ impl std::fmt::Display for CookieJar {
         if self.flags & FLAGS_ROTATED != 0 {
             flags.push("ROTATED");
         }
+        if self.flags & FLAGS_COMMENTS != 0 {
+            flags.push("COMMENTS");
+        }
         write!(f, "  flags: [{}]\n", flags.join(", "))?;
 
         write!(f, "  delim: '{}'\n", self.delim)?;
@@ -119,9 +154,10 @@ impl CookieJar {
             anyhow::bail!("Error: Invalid data file: {}", filename);
         }
         let bytes = std::fs::read(filename)
-            .expect(format!("Error reading cookie database: {}", filename).as_str());
+            .with_context(|| format!("Failed to read data file: {}", filename))?;
 
         let t = Serializer::get_type_by_bytes(&bytes);
+        Serializer::check(&bytes, &t).with_context(|| filename.to_string())?;
         let mut data = Serializer::from_bytes(&bytes, &t);
         data.location = filename.trim_end_matches(".dat").to_string(); // Remove .dat extension
         Ok(data)
@@ -138,26 +174,22 @@ impl CookieJar {
         jar.delim = delim;
         // Split content by delimiter pattern
         let splitter = format!("\n{}\n", delim);
-        let parts: Vec<&str> = content
-            .split(splitter.as_str())
-            .map(|s| s.trim_end_matches(format!("\n{}", delim).as_str())) // remove the '\n%' for the last cookie
-            .collect();
-        jar.cookies = parts
-            .iter()
-            .filter(|part| !part.trim().is_empty())
-            .map(|part| Cookie {
+        let mut offset: u64 = 0;
+        for part in content.split(splitter.as_str()) {
+            let part_offset = offset;
+            offset += (part.len() + splitter.len()) as u64;
+            let part = part.trim_end_matches(format!("\n{}", delim).as_str()); // remove the '\n%' for the last cookie
+            if part.trim().is_empty() {
+                continue;
+            }
+            jar.cookies.push(Cookie {
                 location: jar.location.clone(),
                 content: part.to_string(),
-                offset: 0, // TODO: offset is not used for text files
-            })
-            .collect();
-        let lengths: Vec<u64> = jar
-            .cookies
-            .iter()
-            .map(|c| c.content.len() as u64 + 1)
-            .collect();
-        jar.max_length = *lengths.iter().max().unwrap_or(&0);
-        jar.min_length = *lengths.iter().min().unwrap_or(&0);
+                offset: part_offset,
+                metadata: BTreeMap::new(),
+            });
+        }
+        jar.update_lengths();
         jar.file_size = content.len() as u64;
 
         debug!("from_text(): -> (path: {:?}, platform: {:?}, max_length: {}, min_length: {}, num_cookies: {})",
@@ -166,9 +198,60 @@ impl CookieJar {
         Ok(jar)
     }
 
+    /// Move the comment lines with metadata out of the cookies, e.g. `%% author: Confucius`,
+    /// as `strfile -C` does. The other lines are kept, even if they start with the doubled
+    /// delimiter, so only the files which have the comments flag should be parsed.
+    pub fn parse_comments(&mut self) {
+        let comment = format!("{}{}", self.delim, self.delim);
+        for cookie in self.cookies.iter_mut() {
+            let mut lines: Vec<&str> = Vec::new();
+            for line in cookie.content.split('\n') {
+                match line.strip_prefix(comment.as_str()).and_then(parse_metadata) {
+                    Some((key, value)) => {
+                        cookie.metadata.insert(key.to_string(), value.to_string());
+                    }
+                    None => lines.push(line),
+                }
+            }
+            cookie.content = lines.join("\n");
+        }
+        self.cookies.retain(|c| !c.content.trim().is_empty());
+        self.flags |= FLAGS_COMMENTS;
+        self.update_lengths();
+    }
+
+    /// Update the longest and shortest lengths from the cookies.
+    pub fn update_lengths(&mut self) {
+        let lengths: Vec<u64> = self
+            .cookies
+            .iter()
+            .map(|c| c.content.len() as u64 + 1)
+            .collect();
+        self.max_length = *lengths.iter().max().unwrap_or(&0);
+        self.min_length = *lengths.iter().min().unwrap_or(&0);
+    }
+
+    /// Load a jar from the cookie file, which may be compressed (`.gz`, `.zst`).
+    /// The compression suffix is removed from the location of the jar.
     pub fn from_text_file(filename: &str, delim: char) -> Result<CookieJar> {
-        let content = std::fs::read_to_string(filename)?;
-        CookieJar::from_text(&content, filename, delim)
+        let content = compress::read_to_string(filename)?;
+        CookieJar::from_text(&content, compress::strip_suffix(filename), delim)
+    }
+
+    /// Format the jar as the text of a cookie file, which can be read by `from_text()`.
+    /// The metadata of each cookie is written as comment lines, e.g. `%% author: Confucius`.
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for cookie in self.cookies.iter() {
+            for (key, value) in cookie.metadata.iter() {
+                text.push_str(&format!(
+                    "{}{} {}: {}\n",
+                    self.delim, self.delim, key, value
+                ));
+            }
+            text.push_str(&format!("{}\n{}\n", cookie.content, self.delim));
+        }
+        text
     }
 
     pub fn filter(&mut self, filter: &CookieSieve) -> Result<()> {
@@ -182,10 +265,19 @@ impl CookieJar {
         Ok(())
     }
 
-    pub fn choose(&self, rng: &mut rand::rngs::ThreadRng) -> Option<&Cookie> {
+    pub fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&Cookie> {
         self.cookies.choose(rng)
     }
 
+    /// The weight of the jar when choosing a cookie, zero if it has nothing to choose from.
+    fn choice_weight(&self) -> f64 {
+        if self.cookies.is_empty() {
+            0.0
+        } else {
+            self.probability
+        }
+    }
+
     pub fn update_location(&mut self, parent_location: &str) {
         self.location = trim_parent_path(&self.location, parent_location);
         for c in &mut self.cookies {
@@ -200,6 +292,8 @@ pub struct CookieShelf {
     pub location: String,
     pub probability: f64,
     pub jars: Vec<CookieJar>,
+    pub walker: Walker,
+    pub query: Query, // narrows the cookies by the search index when loading
 }
 
 #[allow(dead_code)]
@@ -209,6 +303,8 @@ impl CookieShelf {
             location: location.to_string(),
             probability,
             jars: Vec::new(),
+            walker: Walker::default(),
+            query: Query::default(),
         }
     }
 
@@ -229,19 +325,14 @@ impl CookieShelf {
         if self.probability == 0.0 {
             return;
         }
-        if equal_size {
-            // if equal_size is given, set equal probability to each jar
-            let prob = self.probability / self.num_of_jars() as f64;
-            for jar in &mut self.jars {
-                jar.probability = prob;
-            }
-        } else {
-            // if equal_size is not given, set probability to each jar based on the number of cookies
-            let total_num_cookies: usize = self.num_of_cookies();
-            for jar in &mut self.jars {
-                jar.probability =
-                    jar.cookies.len() as f64 / total_num_cookies as f64 * self.probability;
-            }
+        let jars: Vec<(usize, f64)> = self
+            .jars
+            .iter()
+            .map(|j| (j.cookies.len(), j.weight))
+            .collect();
+        let probs = jar_probs(self.probability, &jars, equal_size);
+        for (jar, probability) in self.jars.iter_mut().zip(probs) {
+            jar.probability = probability;
         }
     }
 
@@ -251,61 +342,57 @@ impl CookieShelf {
         if self.location.starts_with(EMBED_PREFIX) {
             debug!("Loading embedded cookies from: '{}'", self.location);
 
+            let location = self.location.trim_start_matches(EMBED_PREFIX);
             let paths = Embedded::find(&self.location)?;
             for path in paths {
+                // an explicitly given file is always loaded, the same as the filesystem
+                if path != location
+                    && !is_selected(&trim_parent_path(&path, location), normal, offensive)
+                {
+                    continue;
+                }
                 let content = Embedded::read_to_string(&path)?;
-                let jar = CookieJar::from_text(&content, &path, DEFAULT_DELIMITER)?;
+                let mut jar = CookieJar::from_text(
+                    &content,
+                    compress::strip_suffix(&path),
+                    DEFAULT_DELIMITER,
+                )?;
+                jar.layer = EMBED_LAYER.to_string();
                 jars.push(jar);
             }
-        } else {
-            let p = PathBuf::from(&self.location);
-            if p.is_file() {
-                jars.push(CookieJar::from_text_file(
-                    &self.location,
-                    DEFAULT_DELIMITER,
-                )?);
-            } else {
-                let pattern_off_dir = glob::Pattern::new(&format!("{}/**/off/*", &self.location))?;
-                let pattern_off_file = glob::Pattern::new(&format!("{}/**/*-o", &self.location))?;
-
-                let pattern = format!("{}/**/*", &self.location);
-                let files: Vec<String> = glob(&pattern)
-                    .expect(&format!("Failed to read glob pattern {}", pattern))
-                    .filter_map(Result::ok)
-                    // only keep files
-                    .filter(|p| p.is_file())
-                    // filter out .dat files
-                    .filter(|p| p.extension().unwrap_or_default() != "dat")
-                    // filter out dot files
-                    .filter(|p| !p.file_name().unwrap().to_str().unwrap().starts_with("."))
-                    // filter by normal/offensive
-                    .filter(|p| {
-                        if normal && offensive {
-                            return true;
-                        }
-                        if normal {
-                            return !pattern_off_dir.matches_path(p)
-                                && !pattern_off_file.matches_path(p);
+
+            // overlay the data directories on top of the embedded data, from the lowest
+            // priority to the highest, so a jar with the same path shadows the lower one.
+            if !location.contains(['*', '?', '[']) {
+                for dir in layer::data_dirs().iter().rev() {
+                    let root = dir.join(location);
+                    if !root.exists() {
+                        continue;
+                    }
+                    debug!("Loading overlay cookies from: '{}'", root.display());
+                    let is_file = root.is_file();
+                    let root = root.to_string_lossy().to_string();
+                    for mut jar in load_jars(&root, &self.walker, &self.query, normal, offensive)? {
+                        jar.location = if is_file {
+                            location.to_string()
+                        } else if location.is_empty() {
+                            jar.location
+                        } else {
+                            format!("{}/{}", location.trim_end_matches('/'), jar.location)
+                        };
+                        jar.layer = dir.to_string_lossy().to_string();
+                        for c in &mut jar.cookies {
+                            c.location = jar.location.clone();
                         }
-                        if offensive {
-                            return pattern_off_dir.matches_path(p)
-                                || pattern_off_file.matches_path(p);
+                        match jars.iter_mut().find(|j| j.location == jar.location) {
+                            Some(shadowed) => *shadowed = jar,
+                            None => jars.push(jar),
                         }
-                        false
-                    })
-                    .map(|p| p.to_string_lossy().to_string())
-                    .collect();
-
-                jars = files
-                    .iter()
-                    .map(|f| {
-                        let mut jar = CookieJar::from_text_file(f, DEFAULT_DELIMITER)
-                            .expect(&format!("Failed to read cookie file: {}", f));
-                        jar.update_location(&self.location);
-                        jar
-                    })
-                    .collect();
+                    }
+                }
             }
+        } else {
+            jars = load_jars(&self.location, &self.walker, &self.query, normal, offensive)?;
         }
         self.jars = jars;
         Ok(())
@@ -319,17 +406,44 @@ impl CookieShelf {
         Ok(())
     }
 
-    pub fn choose(&self, rng: &mut rand::rngs::ThreadRng) -> Option<&Cookie> {
-        let index = WeightedIndex::new(
-            self.jars
-                .iter()
-                .map(|j| j.probability)
-                .collect::<Vec<f64>>(),
-        )
-        .unwrap()
-        .sample(rng);
-        let jar = &self.jars[index];
-        jar.choose(rng)
+    pub fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&Cookie> {
+        choose_weighted(&self.jars, CookieJar::choice_weight, rng)?.choose(rng)
+    }
+}
+
+/// How the weights of the sources are interpreted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WeightMode {
+    #[default]
+    Percent, // '30% a b': percentages, the sources without weight share the rest
+    Relative, // '3 a 1 b': relative weights, normalized to 100%
+}
+
+impl WeightMode {
+    fn is_weight(&self, item: &str) -> bool {
+        match self {
+            WeightMode::Percent => item.ends_with('%'),
+            // a number naming an existing file or directory is the path, e.g. a file named `10`
+            WeightMode::Relative => {
+                item.ends_with('%')
+                    || (item.parse::<f64>().is_ok() && !std::path::Path::new(item).exists())
+            }
+        }
+    }
+}
+
+impl std::str::FromStr for WeightMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "percent" => Ok(WeightMode::Percent),
+            "relative" => Ok(WeightMode::Relative),
+            _ => anyhow::bail!(
+                "Unknown weight mode: {} (expected one of: percent, relative)",
+                s
+            ),
+        }
     }
 }
 
@@ -363,23 +477,14 @@ impl CookieCabinet {
 
     pub fn calculate_prob(&mut self, equal_size: bool) {
         // caclulate probability for each shelf
-        let total_prob: f64 = self.shelves.iter().map(|s| s.probability).sum();
-        if total_prob == 0.0 {
-            // no probability is given
-            if equal_size {
-                // if equal_size is given, set same probability to each jar
-                let prob_per_jar = 100.0 / self.num_of_jars() as f64;
-                for shelf in &mut self.shelves {
-                    shelf.probability = prob_per_jar * shelf.num_of_jars() as f64;
-                }
-            } else {
-                // if equal_size is not given, set probability to each jar based on the number of cookies
-                let total_num_cookies: usize = self.num_of_cookies();
-                let prob_per_cookie = 100.0 / total_num_cookies as f64;
-                for shelf in &mut self.shelves {
-                    shelf.probability = shelf.num_of_cookies() as f64 * prob_per_cookie;
-                }
-            }
+        let shelves: Vec<(f64, usize, usize)> = self
+            .shelves
+            .iter()
+            .map(|s| (s.probability, s.num_of_jars(), s.num_of_cookies()))
+            .collect();
+        let probs = shelf_probs(&shelves, equal_size);
+        for (shelf, probability) in self.shelves.iter_mut().zip(probs) {
+            shelf.probability = probability;
         }
 
         // call shelf.calculate_prob() to calculate probability for each jar
@@ -395,7 +500,7 @@ impl CookieCabinet {
         Ok(())
     }
 
-    pub fn from_string_list(items: &[String]) -> Result<CookieCabinet> {
+    pub fn from_string_list(items: &[String], mode: WeightMode) -> Result<CookieCabinet> {
         let mut shelves: CookieCabinet = CookieCabinet::default();
         if items.is_empty() {
             //  use embedded cookies if no shelves are given
@@ -403,38 +508,66 @@ impl CookieCabinet {
             let lang = get_current_language();
 
             // check if the language is supported
-            let location = if Embedded::exists(&lang) {
+            let location = if Embedded::exists(&lang) || layer::exists(&lang) {
                 format!("{}{}", EMBED_PREFIX, lang)
             } else {
                 format!("{}{}", EMBED_PREFIX, "en")
             };
             shelves.push(CookieShelf::new(&location, 100.0));
-        } else {
-            let mut prob: f64 = 0.0;
-            for item in items {
-                if item.ends_with("%") {
-                    // this is the probability for the next shelf
-                    prob = item.strip_suffix("%").unwrap().parse::<f64>().unwrap();
-                } else {
-                    // check if an probability is given
-                    if prob > 0.0 {
-                        shelves.push(CookieShelf::new(item, prob));
-                        prob = 0.0;
-                    } else {
-                        // no probability given, default to 0.0, which to be calculated later
-                        shelves.push(CookieShelf::new(item, 0.0));
-                    }
+            return Ok(shelves);
+        }
+
+        let mut weights: Vec<Option<f64>> = Vec::new();
+        let mut weight: Option<(&str, f64)> = None;
+        for item in items {
+            if mode.is_weight(item) {
+                // a weight must be followed by a path, not another weight
+                if let Some((item, _)) = weight {
+                    return Err(ArgError::MissingPath(item.to_string()).into());
                 }
+                // this is the weight for the next shelf
+                weight = Some((item, error::parse_weight(item)?));
+            } else {
+                // the probability is calculated later
+                shelves.push(CookieShelf::new(item, 0.0));
+                weights.push(weight.take().map(|(_, w)| w));
             }
         }
-        // check if only partial probabilities are given
-        let total_prob: f64 = shelves.iter().map(|s| s.probability).sum();
-        if (total_prob - 100.0).abs() > 0.0001 && total_prob > 0.0 {
-            // partial probabilities are given
-            anyhow::bail!(
-                "Error: Partial probabilities are given. Total probability: {}",
-                total_prob
-            );
+        if let Some((item, _)) = weight {
+            return Err(ArgError::MissingPath(item.to_string()).into());
+        }
+
+        match mode {
+            WeightMode::Percent => {
+                // the shelves without weight share the residual percentage, as BSD fortune does,
+                // and 0% is the same as no weight given
+                let weights: Vec<Option<f64>> =
+                    weights.iter().map(|w| w.filter(|w| *w > 0.0)).collect();
+                let total: f64 = weights.iter().flatten().sum();
+                let has_residual_shelves = weights.iter().any(|w| w.is_none());
+                if total > 100.0 + PROB_EPSILON {
+                    return Err(ArgError::WeightsOverflow(total).into());
+                } else if total < 100.0 - PROB_EPSILON && !has_residual_shelves && total > 0.0 {
+                    return Err(ArgError::NoResidualPlace(100.0 - total).into());
+                } else if (total - 100.0).abs() <= PROB_EPSILON && has_residual_shelves {
+                    return Err(ArgError::NoResidualLeft.into());
+                }
+                for (shelf, weight) in shelves.shelves.iter_mut().zip(weights.iter()) {
+                    shelf.probability = weight.unwrap_or(0.0);
+                }
+            }
+            WeightMode::Relative => {
+                // the shelves without weight have the weight of 1, and zero weight disables the shelf
+                let weights: Vec<f64> = weights.iter().map(|w| w.unwrap_or(1.0)).collect();
+                let total: f64 = weights.iter().sum();
+                if total <= 0.0 {
+                    return Err(ArgError::ZeroWeights.into());
+                }
+                for (shelf, weight) in shelves.shelves.iter_mut().zip(weights.iter()) {
+                    shelf.probability = weight / total * 100.0;
+                }
+                shelves.shelves.retain(|s| s.probability > 0.0);
+            }
         }
         Ok(shelves)
     }
@@ -447,37 +580,296 @@ impl CookieCabinet {
         Ok(())
     }
 
-    pub fn choose(&self, rng: &mut rand::rngs::ThreadRng) -> Option<&Cookie> {
-        let index = WeightedIndex::new(
-            self.shelves
+    /// Select the matching cookies by reference, which leaves the cabinet as is,
+    /// e.g. to apply the filters of each request to a shared cabinet.
+    pub fn select(&self, filter: &CookieSieve) -> CookieSelection<'_> {
+        let shelves = self
+            .shelves
+            .iter()
+            .map(|shelf| SelectedShelf {
+                shelf,
+                probability: shelf.probability,
+                jars: shelf
+                    .jars
+                    .iter()
+                    .map(|jar| SelectedJar::new(jar, filter))
+                    .filter(|j| j.num_of_cookies() > 0)
+                    .collect(),
+            })
+            .filter(|s| !s.jars.is_empty())
+            .collect();
+        CookieSelection { shelves }
+    }
+
+    pub fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&Cookie> {
+        // a shelf of zero-weight jars has nothing to choose from, even with a probability
+        let weight = |shelf: &CookieShelf| {
+            if shelf.jars.iter().any(|j| j.choice_weight() > 0.0) {
+                shelf.probability
+            } else {
+                0.0
+            }
+        };
+        choose_weighted(&self.shelves, weight, rng)?.choose(rng)
+    }
+}
+
+/// The cookies of a cabinet selected by a sieve, by reference, e.g. to apply the filters
+/// of each request to a shared cabinet without copying it.
+#[derive(Debug, Clone, Default)]
+pub struct CookieSelection<'a> {
+    pub shelves: Vec<SelectedShelf<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SelectedShelf<'a> {
+    pub shelf: &'a CookieShelf,
+    pub probability: f64,
+    pub jars: Vec<SelectedJar<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct SelectedJar<'a> {
+    pub jar: &'a CookieJar,
+    pub probability: f64,
+    indexes: Option<Vec<usize>>, // the selected cookies of the jar, None if all
+}
+
+impl<'a> CookieSelection<'a> {
+    pub fn iter(&self) -> std::slice::Iter<'_, SelectedShelf<'a>> {
+        self.shelves.iter()
+    }
+
+    pub fn num_of_cookies(&self) -> usize {
+        self.shelves.iter().map(|s| s.num_of_cookies()).sum()
+    }
+
+    pub fn num_of_jars(&self) -> usize {
+        self.shelves.iter().map(|s| s.jars.len()).sum()
+    }
+
+    /// The same as `CookieCabinet::calculate_prob()`, by the selected cookies only.
+    pub fn calculate_prob(&mut self, equal_size: bool) {
+        let shelves: Vec<(f64, usize, usize)> = self
+            .shelves
+            .iter()
+            .map(|s| (s.probability, s.jars.len(), s.num_of_cookies()))
+            .collect();
+        let probs = shelf_probs(&shelves, equal_size);
+        for (shelf, probability) in self.shelves.iter_mut().zip(probs) {
+            shelf.probability = probability;
+            if probability == 0.0 {
+                continue;
+            }
+            let jars: Vec<(usize, f64)> = shelf
+                .jars
                 .iter()
-                .map(|s| s.probability)
-                .collect::<Vec<f64>>(),
-        )
-        .unwrap()
+                .map(|j| (j.num_of_cookies(), j.jar.weight))
+                .collect();
+            let probs = jar_probs(probability, &jars, equal_size);
+            for (jar, probability) in shelf.jars.iter_mut().zip(probs) {
+                jar.probability = probability;
+            }
+        }
+    }
+
+    pub fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&'a Cookie> {
+        // a shelf of zero-weight jars has nothing to choose from, even with a probability
+        let weight = |shelf: &SelectedShelf| {
+            if shelf.jars.iter().any(|j| j.choice_weight() > 0.0) {
+                shelf.probability
+            } else {
+                0.0
+            }
+        };
+        choose_weighted(&self.shelves, weight, rng)?.choose(rng)
+    }
+}
+
+impl<'a> SelectedShelf<'a> {
+    pub fn iter(&self) -> std::slice::Iter<'_, SelectedJar<'a>> {
+        self.jars.iter()
+    }
+
+    pub fn num_of_cookies(&self) -> usize {
+        self.jars.iter().map(|j| j.num_of_cookies()).sum()
+    }
+
+    pub fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&'a Cookie> {
+        choose_weighted(&self.jars, SelectedJar::choice_weight, rng)?.choose(rng)
+    }
+}
+
+impl<'a> SelectedJar<'a> {
+    fn new(jar: &'a CookieJar, filter: &CookieSieve) -> Self {
+        // nothing to filter, e.g. no pattern is given
+        let indexes = (filter.len() > 0).then(|| {
+            (0..jar.cookies.len())
+                .filter(|&i| filter.filter(&jar.cookies[i].content))
+                .collect()
+        });
+        Self {
+            jar,
+            probability: jar.probability,
+            indexes,
+        }
+    }
+
+    pub fn iter(&self) -> Box<dyn Iterator<Item = &'a Cookie> + '_> {
+        let jar = self.jar;
+        match &self.indexes {
+            Some(indexes) => Box::new(indexes.iter().map(move |&i| &jar.cookies[i])),
+            None => Box::new(jar.cookies.iter()),
+        }
+    }
+
+    pub fn num_of_cookies(&self) -> usize {
+        match &self.indexes {
+            Some(indexes) => indexes.len(),
+            None => self.jar.cookies.len(),
+        }
+    }
+
+    pub fn choose<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<&'a Cookie> {
+        let jar = self.jar;
+        match &self.indexes {
+            Some(indexes) => indexes.choose(rng).map(|&i| &jar.cookies[i]),
+            None => jar.choose(rng),
+        }
+    }
+
+    fn choice_weight(&self) -> f64 {
+        if self.num_of_cookies() == 0 {
+            0.0
+        } else {
+            self.probability
+        }
+    }
+}
+
+/// The probabilities of the shelves, given as `(probability, jars, cookies)`,
+/// where the shelves without given probability share the residual probability.
+fn shelf_probs(shelves: &[(f64, usize, usize)], equal_size: bool) -> Vec<f64> {
+    let mut probs: Vec<f64> = shelves.iter().map(|s| s.0).collect();
+    let total_prob: f64 = probs.iter().sum();
+    let residual_prob = 100.0 - total_prob;
+    if residual_prob > PROB_EPSILON {
+        let residual_shelves = || shelves.iter().filter(|s| s.0 == 0.0);
+        let residual_probs = probs.iter_mut().zip(shelves).filter(|(_, s)| s.0 == 0.0);
+        if equal_size {
+            // if equal_size is given, set same probability to each jar
+            let num_of_jars: usize = residual_shelves().map(|s| s.1).sum();
+            let prob_per_jar = residual_prob / num_of_jars.max(1) as f64;
+            for (prob, shelf) in residual_probs {
+                *prob = prob_per_jar * shelf.1 as f64;
+            }
+        } else {
+            // if equal_size is not given, set probability to each jar based on the number of cookies
+            let total_num_cookies: usize = residual_shelves().map(|s| s.2).sum();
+            let prob_per_cookie = residual_prob / total_num_cookies.max(1) as f64;
+            for (prob, shelf) in residual_probs {
+                *prob = shelf.2 as f64 * prob_per_cookie;
+            }
+        }
+    }
+    probs
+}
+
+/// The probabilities of the jars of a shelf, given as `(cookies, weight)`.
+fn jar_probs(probability: f64, jars: &[(usize, f64)], equal_size: bool) -> Vec<f64> {
+    // the weights of the jars are given by weights.toml, default 1
+    if equal_size {
+        // if equal_size is given, set probability to each jar based on the weight only
+        let total_weight: f64 = jars.iter().map(|j| j.1).sum();
+        jars.iter()
+            .map(|j| safe_ratio(j.1, total_weight) * probability)
+            .collect()
+    } else {
+        // if equal_size is not given, set probability to each jar based on the number of cookies times the weight
+        let total_weighted_cookies: f64 = jars.iter().map(|j| j.0 as f64 * j.1).sum();
+        jars.iter()
+            .map(|j| safe_ratio(j.0 as f64 * j.1, total_weighted_cookies) * probability)
+            .collect()
+    }
+}
+
+/// Choose an item by the weights, skipping the zero-weight ones, or None if all are zero.
+fn choose_weighted<'a, T, R: Rng + ?Sized>(
+    items: &'a [T],
+    weight: impl Fn(&T) -> f64,
+    rng: &mut R,
+) -> Option<&'a T> {
+    let candidates: Vec<&T> = items.iter().filter(|item| weight(item) > 0.0).collect();
+    let index = WeightedIndex::new(candidates.iter().map(|item| weight(item)))
+        .ok()?
         .sample(rng);
-        let shelf = &self.shelves[index];
-        shelf.choose(rng)
+    Some(candidates[index])
+}
+
+/// A node of the cookie filter tree, which can describe itself, e.g. `(/a/ OR /b/) AND NOT /c/`.
+pub enum Predicate {
+    Filter(String, Box<dyn Fn(&str) -> bool + Send + Sync>), // description and the filter function
+    All(Vec<Predicate>),
+    Any(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    pub fn new<F>(description: &str, filter: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        Predicate::Filter(description.to_string(), Box::new(filter))
+    }
+
+    pub fn matches(&self, cookie: &str) -> bool {
+        match self {
+            Predicate::Filter(_, f) => f(cookie),
+            Predicate::All(predicates) => predicates.iter().all(|p| p.matches(cookie)),
+            Predicate::Any(predicates) => predicates.iter().any(|p| p.matches(cookie)),
+            Predicate::Not(predicate) => !predicate.matches(cookie),
+        }
+    }
+}
+
+impl std::fmt::Display for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (predicates, operator) = match self {
+            Predicate::Filter(description, _) => return write!(f, "{}", description),
+            Predicate::Not(predicate) => return write!(f, "NOT {}", predicate),
+            Predicate::All(predicates) => (predicates, " AND "),
+            Predicate::Any(predicates) => (predicates, " OR "),
+        };
+        let terms: Vec<String> = predicates.iter().map(|p| p.to_string()).collect();
+        match terms.len() {
+            0 => write!(f, "{}", matches!(self, Predicate::All(_))),
+            1 => write!(f, "{}", terms[0]),
+            _ => write!(f, "({})", terms.join(operator)),
+        }
     }
 }
 
-// Cookie filtering mechanism
+// Cookie filtering mechanism, all the predicates must match
 #[allow(dead_code)]
 #[derive(Default)]
 pub struct CookieSieve {
-    filters: Vec<Box<dyn Fn(&str) -> bool>>,
+    filters: Vec<Predicate>,
 }
 
 impl CookieSieve {
     pub fn add_filter<F>(&mut self, filter: F)
     where
-        F: Fn(&str) -> bool + 'static,
+        F: Fn(&str) -> bool + Send + Sync + 'static,
     {
-        self.filters.push(Box::new(filter));
+        self.add(Predicate::new("filter", filter));
+    }
+
+    pub fn add(&mut self, predicate: Predicate) {
+        self.filters.push(predicate);
     }
 
     pub fn filter(&self, cookie: &str) -> bool {
-        self.filters.iter().all(|f| f(cookie))
+        self.filters.iter().all(|p| p.matches(cookie))
     }
 
     pub fn len(&self) -> usize {
@@ -485,6 +877,16 @@ impl CookieSieve {
     }
 }
 
+impl std::fmt::Display for CookieSieve {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.filters.is_empty() {
+            return write!(f, "all cookies");
+        }
+        let terms: Vec<String> = self.filters.iter().map(|p| p.to_string()).collect();
+        write!(f, "{}", terms.join(" AND "))
+    }
+}
+
 /////////////////////
 /// Helper functions
 /////////////////////
@@ -514,6 +916,127 @@ fn trim_parent_path(path: &str, parent: &str) -> String {
         .to_string()
 }
 
+/// Divide the numbers, or return 0 if the denominator is 0, e.g. all jars have the weight of 0.
+fn safe_ratio(numerator: f64, denominator: f64) -> f64 {
+    if denominator > 0.0 {
+        numerator / denominator
+    } else {
+        0.0
+    }
+}
+
+/// Parse a comment line as `key: value`, where the key is a single word, e.g. `author`.
+pub(crate) fn parse_metadata(comment: &str) -> Option<(&str, &str)> {
+    let (key, value) = comment.split_once(':')?;
+    let key = key.trim();
+    if key.is_empty()
+        || !key
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        return None;
+    }
+    Some((key, value.trim()))
+}
+
+/// Load the jar from the cookie file, and parse its comment lines if the data file next
+/// to it has the comments flag, i.e. it was built with `strfile -C`.
+fn load_jar(file: &str) -> Result<CookieJar> {
+    let mut jar = CookieJar::from_text_file(file, DEFAULT_DELIMITER)?;
+    let datfile = format!("{}.dat", compress::strip_suffix(file));
+    if Path::new(&datfile).is_file() {
+        match CookieJar::from_dat(&datfile) {
+            Ok(dat) if dat.flags & FLAGS_COMMENTS != 0 => jar.parse_comments(),
+            Ok(_) => {}
+            Err(e) => debug!("load_jar(): skip the data file: {:#}", e),
+        }
+    }
+    Ok(jar)
+}
+
+/// Load jars from the given file or directory in the filesystem.
+/// The location of each jar is relative to the given directory.
+fn load_jars(
+    location: &str,
+    walker: &Walker,
+    query: &Query,
+    normal: bool,
+    offensive: bool,
+) -> Result<Vec<CookieJar>> {
+    let p = PathBuf::from(location);
+    if p.is_file() {
+        let mut jar = load_jar(location)?;
+        search::narrow(&mut jar, location, query);
+        return Ok(vec![jar]);
+    }
+
+    if !p.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let files: Vec<String> = walker
+        .walk(&p)?
+        .iter()
+        .map(|p| p.to_string_lossy().to_string())
+        // filter by normal/offensive
+        .filter(|p| is_selected(&trim_parent_path(p, location), normal, offensive))
+        .collect();
+
+    let mut weights = JarWeights::new(&p);
+    files
+        .iter()
+        .map(|f| {
+            let mut jar = load_jar(f)?;
+            search::narrow(&mut jar, f, query);
+            jar.update_location(location);
+            jar.weight = weights.weight_of(Path::new(f))?;
+            Ok(jar)
+        })
+        .collect()
+}
+
+/// Check whether the given jar path is potentially offensive.
+/// The path is relative to the shelf location, and a jar is considered offensive
+/// if it is under an `off/` directory or its file name ends with `-o`.
+pub fn is_offensive(path: &str) -> bool {
+    let path = path.replace('\\', "/");
+    let mut components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    match components.pop() {
+        Some(file_name) => file_name.ends_with("-o") || components.contains(&"off"),
+        None => false,
+    }
+}
+
+/// Check whether the jar of the shelf is potentially offensive by its path under the shelf,
+/// the same as the jars are selected when loaded. A file given as the shelf is loaded either
+/// way, and is classified by its file name.
+pub fn is_offensive_jar(shelf: &str, jar: &str) -> bool {
+    // the embedded jars are located from the root of the embedded data, the others from the shelf
+    let (root, path) = match shelf.strip_prefix(EMBED_PREFIX) {
+        Some(root) => (root, trim_parent_path(jar, root)),
+        None => (shelf, jar.to_string()),
+    };
+    if jar == root {
+        let name = Path::new(jar).file_name().unwrap_or_default();
+        return is_offensive(&name.to_string_lossy());
+    }
+    is_offensive(&path)
+}
+
+/// Check whether the given jar path should be loaded for the normal/offensive selection.
+fn is_selected(path: &str, normal: bool, offensive: bool) -> bool {
+    if normal && offensive {
+        return true;
+    }
+    if normal {
+        return !is_offensive(path);
+    }
+    if offensive {
+        return is_offensive(path);
+    }
+    false
+}
+
 /// get the current language
 /// if the system locale is not supported, return "en"
 /// if the system locale is supported, return the primary language
@@ -534,9 +1057,12 @@ fn get_current_language() -> String {
 #[cfg(test)]
 mod tests {
     use crate::cookie::{FLAGS_ORDERED, FLAGS_RANDOMIZED, FLAGS_ROTATED};
-    use std::collections::{HashMap, HashSet};
+    use std::collections::{BTreeMap, HashMap, HashSet};
 
-    use super::{embed::EMBED_PREFIX, get_current_language, CookieShelf};
+    use super::{
+        embed::EMBED_PREFIX, error::ArgError, get_current_language, CookieShelf, Predicate,
+        WeightMode,
+    };
     const TEST_DATA_DIR: &str = "tests/data";
 
     // CookieJar tests
@@ -544,6 +1070,7 @@ mod tests {
     fn test_cookie_jar_default() {
         let jar = super::CookieJar::default();
         assert_eq!(jar.location, "");
+        assert_eq!(jar.layer, "");
         assert_eq!(jar.probability, 0.0);
         assert_eq!(jar.platform, "");
         assert_eq!(jar.version, 0);
@@ -629,6 +1156,66 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cookie_jar_from_text_offsets_and_comments() {
+        let content =
+            "apple\n%\n%% author: Alice\n%% tags: fruit, red\nbanana\n%\n\n%\ncherry\n%\n";
+        let mut jar = super::CookieJar::from_text(content, "valley", '%').unwrap();
+        // the comments are kept as they are, unless they are parsed
+        assert_eq!(
+            "%% author: Alice\n%% tags: fruit, red\nbanana",
+            jar.cookies[1].content
+        );
+        jar.parse_comments();
+        let cookies: Vec<(&str, u64)> =
+            jar.iter().map(|c| (c.content.as_str(), c.offset)).collect();
+        assert_eq!(vec![("apple", 0), ("banana", 8), ("cherry", 57)], cookies);
+        assert_eq!(super::FLAGS_COMMENTS, jar.flags);
+        assert!(jar.cookies[0].metadata.is_empty());
+        assert_eq!(
+            Some(&"Alice".to_string()),
+            jar.cookies[1].metadata.get("author")
+        );
+        assert_eq!(
+            Some(&"fruit, red".to_string()),
+            jar.cookies[1].metadata.get("tags")
+        );
+    }
+
+    #[test]
+    fn test_cookie_jar_to_text() {
+        let testcases = [
+            ("apple\n%\nbanana\n%\n", '%'),
+            ("apple\n#\n## author: Alice\nbanana\n#\n", '#'),
+            ("%% author: Bob\n%% tags: fruit\napple\nis red\n%\n", '%'),
+        ];
+
+        for (content, delim) in testcases.iter() {
+            let mut jar = super::CookieJar::from_text(content, "valley", *delim).unwrap();
+            assert_eq!(*content, jar.to_text());
+            jar.parse_comments();
+            assert_eq!(*content, jar.to_text());
+        }
+    }
+
+    #[test]
+    fn test_cookie_jar_parse_comments() {
+        // only the 'key: value' comments are metadata, the other lines are kept
+        let content =
+            "%% author: Bob\n%%%% a banner %%%%\n%% not a key: value\napple\n%\n%% tags: x\n%\n";
+        let mut jar = super::CookieJar::from_text(content, "valley", '%').unwrap();
+        jar.parse_comments();
+        assert_eq!(1, jar.num_of_cookies());
+        assert_eq!(
+            "%%%% a banner %%%%\n%% not a key: value\napple",
+            jar.cookies[0].content
+        );
+        assert_eq!(
+            BTreeMap::from([("author".to_string(), "Bob".to_string())]),
+            jar.cookies[0].metadata
+        );
+    }
+
     #[test]
     fn test_cookie_jar_filter() {
         let filters = [
@@ -765,7 +1352,9 @@ mod tests {
     fn test_cookie_jar_display() {
         let jar = super::CookieJar {
             location: "valley".to_string(),
+            layer: "".to_string(),
             probability: 12.345,
+            weight: 1.0,
             platform: "homebrew".to_string(),
             version: 1,
             max_length: 10,
@@ -778,11 +1367,13 @@ mod tests {
                     location: "valley".to_string(),
                     content: "apple".to_string(),
                     offset: 0,
+                    metadata: BTreeMap::new(),
                 },
                 super::Cookie {
                     location: "valley".to_string(),
                     content: "banana".to_string(),
                     offset: 10,
+                    metadata: BTreeMap::new(),
                 },
             ],
         };
@@ -864,6 +1455,7 @@ mod tests {
             (
                 "should set equal probability to each jar if equal_size is given",
                 [2, 3, 0],
+                [1.0, 1.0, 1.0],
                 true,
                 100.0,
                 vec![33.333333333333336, 33.333333333333336, 33.333333333333336],
@@ -871,6 +1463,7 @@ mod tests {
             (
                 "should set probability to each jar based on the number of cookies if equal_size is not given",
                 [2, 3, 0],
+                [1.0, 1.0, 1.0],
                 false,
                 100.0,
                 vec![40.0, 60.0, 0.0],
@@ -878,6 +1471,7 @@ mod tests {
             (
                 "should set equal probability to each jar if equal_size is given (total: 50.0%)",
                 [2, 3, 0],
+                [1.0, 1.0, 1.0],
                 true,
                 50.0,
                 vec![16.666666666666668, 16.666666666666668, 16.666666666666668],
@@ -885,22 +1479,51 @@ mod tests {
             (
                 "should set probability to each jar based on the number of cookies if equal_size is not given (total: 50.0%)",
                 [2, 3, 0],
+                [1.0, 1.0, 1.0],
                 false,
                 50.0,
                 vec![20.0, 30.0, 0.0],
-            )
+            ),
+            (
+                "should set probability to each jar based on the weight if equal_size is given",
+                [2, 3, 0],
+                [3.0, 1.0, 1.0],
+                true,
+                100.0,
+                vec![60.0, 20.0, 20.0],
+            ),
+            (
+                "should set probability to each jar based on the number of cookies times the weight if equal_size is not given",
+                [2, 3, 0],
+                [3.0, 1.0, 1.0],
+                false,
+                100.0,
+                vec![66.66666666666667, 33.333333333333336, 0.0],
+            ),
+            (
+                "should set zero probability to each jar if all weights are zero",
+                [2, 3, 0],
+                [0.0, 0.0, 0.0],
+                false,
+                100.0,
+                vec![0.0, 0.0, 0.0],
+            ),
         ];
 
-        for (msg, num_cookies, equal_size, total_prob, expected) in testcases.iter() {
+        for (msg, num_cookies, weights, equal_size, total_prob, expected) in testcases.iter() {
             // create a shelf with given number of cookies
             let mut shelf = super::CookieShelf::new("valley", *total_prob);
-            for num in num_cookies.iter() {
-                let mut jar = super::CookieJar::default();
+            for (num, weight) in num_cookies.iter().zip(weights.iter()) {
+                let mut jar = super::CookieJar {
+                    weight: *weight,
+                    ..Default::default()
+                };
                 for i in 0..*num {
                     jar.cookies.push(super::Cookie {
                         location: "valley".to_string(),
                         content: "apple".to_string(),
                         offset: i * 10,
+                        metadata: BTreeMap::new(),
                     });
                 }
                 shelf.jars.push(jar);
@@ -962,6 +1585,22 @@ mod tests {
                 1,
                 433,
             ),
+            (
+                "should load embedded directory (normal only)",
+                EMBED_PREFIX.to_string() + "en",
+                true,
+                false,
+                1,
+                433,
+            ),
+            (
+                "should load embedded directory (offensive only)",
+                EMBED_PREFIX.to_string() + "en",
+                false,
+                true,
+                0,
+                0,
+            ),
         ];
 
         for (msg, location, normal, offensive, num_jars, num_cookies) in testcases.iter() {
@@ -1155,6 +1794,18 @@ mod tests {
                     ("dog", 23.81),
                 ],
             ),
+            (
+                [("tests/data", 30.0), ("tests/data2", 0.0)],
+                (true, false, false), // equal_size = false
+                vec![
+                    ("apple", 13.64),
+                    ("orange", 13.64),
+                    ("one", 2.73),
+                    ("zero", 0.0),
+                    ("cat", 35.0),
+                    ("dog", 35.0),
+                ],
+            ),
             (
                 [("tests/data", 0.0), ("tests/data2", 0.0)],
                 (true, false, true), // equal_size = true
@@ -1228,26 +1879,53 @@ mod tests {
         let testcases = [
             (
                 "60% tests/data 40% tests/data2",
+                WeightMode::Percent,
                 vec![("tests/data", 60.0), ("tests/data2", 40.0)],
             ),
             (
                 "15% tests/data 85% tests/data2",
+                WeightMode::Percent,
                 vec![("tests/data", 15.0), ("tests/data2", 85.0)],
             ),
             (
                 "tests/data tests/data2",
+                WeightMode::Percent,
                 vec![("tests/data", 0.0), ("tests/data2", 0.0)],
             ),
-            ("", vec![(default_embed.as_str(), 100.0)]),
+            (
+                "30% tests/data tests/data2",
+                WeightMode::Percent,
+                vec![("tests/data", 30.0), ("tests/data2", 0.0)],
+            ),
+            (
+                "",
+                WeightMode::Percent,
+                vec![(default_embed.as_str(), 100.0)],
+            ),
+            (
+                "3 tests/data 1 tests/data2",
+                WeightMode::Relative,
+                vec![("tests/data", 75.0), ("tests/data2", 25.0)],
+            ),
+            (
+                "3% tests/data tests/data2",
+                WeightMode::Relative,
+                vec![("tests/data", 75.0), ("tests/data2", 25.0)],
+            ),
+            (
+                "0 tests/data tests/data2",
+                WeightMode::Relative,
+                vec![("tests/data2", 100.0)],
+            ),
         ];
 
-        for (line, expected) in testcases.iter() {
+        for (line, mode, expected) in testcases.iter() {
             let args = line
                 .split_whitespace()
                 .map(|s| s.to_string())
                 .collect::<Vec<String>>();
-            let cabinet = super::CookieCabinet::from_string_list(&args).unwrap();
-            assert_eq!(expected.len(), cabinet.shelves.len());
+            let cabinet = super::CookieCabinet::from_string_list(&args, *mode).unwrap();
+            assert_eq!(expected.len(), cabinet.shelves.len(), "{}", line);
             for (i, (location, prob)) in expected.iter().enumerate() {
                 assert_eq!(*location, cabinet.shelves[i].location);
                 assert_eq!(*prob, cabinet.shelves[i].probability);
@@ -1260,9 +1938,56 @@ mod tests {
             &args
                 .split_whitespace()
                 .map(|s| s.to_string())
-                .collect::<Vec<String>>()
+                .collect::<Vec<String>>(),
+            WeightMode::Percent
         )
         .is_err());
+
+        let testcases = [
+            (
+                "abc% tests/data",
+                ArgError::InvalidWeight("abc%".to_string()),
+            ),
+            (
+                "-5% tests/data",
+                ArgError::NegativeWeight("-5%".to_string()),
+            ),
+            ("tests/data 50%", ArgError::MissingPath("50%".to_string())),
+            (
+                "50% 50% tests/data",
+                ArgError::MissingPath("50%".to_string()),
+            ),
+            (
+                "60% tests/data 50% tests/data2",
+                ArgError::WeightsOverflow(110.0),
+            ),
+            (
+                "60% tests/data 30% tests/data2",
+                ArgError::NoResidualPlace(10.0),
+            ),
+            (
+                "60% tests/data 40% tests/data2 tests/data3",
+                ArgError::NoResidualLeft,
+            ),
+        ];
+        for (line, expected) in testcases.iter() {
+            let args = line
+                .split_whitespace()
+                .map(|s| s.to_string())
+                .collect::<Vec<String>>();
+            let error =
+                super::CookieCabinet::from_string_list(&args, WeightMode::Percent).unwrap_err();
+            assert_eq!(Some(expected), error.downcast_ref::<ArgError>(), "{}", line);
+        }
+
+        // all the sources are disabled by zero weights
+        let args = ["0", "tests/data", "0%", "tests/data2"].map(String::from);
+        let error =
+            super::CookieCabinet::from_string_list(&args, WeightMode::Relative).unwrap_err();
+        assert_eq!(
+            Some(&ArgError::ZeroWeights),
+            error.downcast_ref::<ArgError>()
+        );
     }
 
     #[test]
@@ -1329,6 +2054,94 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cookie_cabinet_select() {
+        let mut cabinet = super::CookieCabinet::default();
+        cabinet.push(super::CookieShelf::new("tests/data", 0.0));
+        cabinet.push(super::CookieShelf::new("tests/data2", 0.0));
+        cabinet.load(true, true).unwrap();
+
+        // nothing to filter, all the cookies are selected, but not the empty jars
+        let sieve = super::CookieSieve::default();
+        let selection = cabinet.select(&sieve);
+        let jars = cabinet.iter().flat_map(|s| s.iter());
+        assert_eq!(cabinet.num_of_cookies(), selection.num_of_cookies());
+        assert_eq!(
+            jars.filter(|j| j.num_of_cookies() > 0).count(),
+            selection.num_of_jars()
+        );
+
+        let mut sieve = super::CookieSieve::default();
+        sieve.add_filter(|q| q.contains("Apple") || q.contains("cat"));
+        let mut selection = cabinet.select(&sieve);
+        selection.calculate_prob(false);
+        // the same as filtering a copy of the cabinet
+        let mut filtered = cabinet.clone();
+        filtered.filter(&sieve).unwrap();
+        filtered.calculate_prob(false);
+        assert_eq!(filtered.num_of_cookies(), selection.num_of_cookies());
+        assert_eq!(filtered.num_of_jars(), selection.num_of_jars());
+        for (shelf, selected) in filtered.iter().zip(selection.iter()) {
+            assert_eq!(shelf.location, selected.shelf.location);
+            assert!((shelf.probability - selected.probability).abs() < 0.01);
+            for (jar, selected) in shelf.iter().zip(selected.iter()) {
+                assert_eq!(jar.location, selected.jar.location);
+                assert!((jar.probability - selected.probability).abs() < 0.01);
+                let contents: Vec<&str> = selected.iter().map(|c| c.content.as_str()).collect();
+                let expected: Vec<&str> = jar.iter().map(|c| c.content.as_str()).collect();
+                assert_eq!(expected, contents);
+            }
+        }
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let cookie = selection.choose(&mut rng).unwrap();
+            assert!(sieve.filter(&cookie.content));
+        }
+
+        // no matching cookies, nothing to choose
+        let mut sieve = super::CookieSieve::default();
+        sieve.add_filter(|_| false);
+        let selection = cabinet.select(&sieve);
+        assert_eq!(0, selection.num_of_jars());
+        assert!(selection.choose(&mut rng).is_none());
+        // the cabinet itself is left as is
+        assert_eq!(7, cabinet.num_of_jars());
+    }
+
+    #[test]
+    fn test_cookie_cabinet_shared() {
+        use rand::SeedableRng;
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<super::CookieCabinet>();
+        assert_send_sync::<super::CookieSieve>();
+
+        let mut cabinet = super::CookieCabinet::default();
+        cabinet.push(super::CookieShelf::new("tests/data", 100.0));
+        cabinet.load(true, false).unwrap();
+        cabinet.calculate_prob(false);
+        let cabinet = std::sync::Arc::new(cabinet);
+        let mut sieve = super::CookieSieve::default();
+        sieve.add_filter(|q| q.contains("Apple"));
+        let sieve = std::sync::Arc::new(sieve);
+
+        let handles: Vec<_> = (0..4)
+            .map(|seed| {
+                let cabinet = cabinet.clone();
+                let sieve = sieve.clone();
+                std::thread::spawn(move || {
+                    let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+                    assert!(cabinet.choose(&mut rng).is_some());
+                    let mut selection = cabinet.select(&sieve);
+                    selection.calculate_prob(false);
+                    selection.choose(&mut rng).unwrap().content.clone()
+                })
+            })
+            .collect();
+        for handle in handles {
+            assert!(handle.join().unwrap().contains("Apple"));
+        }
+    }
+
     #[test]
     fn test_cookie_cabinet_choose() {
         let testcases = [
@@ -1370,6 +2183,25 @@ mod tests {
                 );
             }
         }
+
+        // the jars weighted zero, e.g. by weights.toml, are never chosen
+        let mut cabinet = super::CookieCabinet::default();
+        for (location, weight) in [("zero", 0.0), ("valley", 1.0)] {
+            let mut shelf = super::CookieShelf::new(location, 50.0);
+            let mut jar = super::CookieJar::from_text(location, location, '%').unwrap();
+            jar.weight = weight;
+            shelf.jars.push(jar);
+            cabinet.push(shelf);
+        }
+        cabinet.calculate_prob(false);
+        for _ in 0..100 {
+            assert_eq!("valley", cabinet.choose(&mut rng).unwrap().content);
+        }
+        // nothing is chosen if all are weighted zero
+        cabinet.shelves[1].jars[0].weight = 0.0;
+        cabinet.calculate_prob(false);
+        assert!(cabinet.choose(&mut rng).is_none());
+        assert!(cabinet.shelves[0].choose(&mut rng).is_none());
     }
 
     // CookieSieve tests
@@ -1433,6 +2265,118 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_cookie_sieve_predicates() {
+        let contains =
+            |s: &'static str| Predicate::new(&format!("/{}/", s), move |q| q.contains(s));
+        let mut sieve = super::CookieSieve::default();
+        assert_eq!("all cookies", sieve.to_string());
+        sieve.add(Predicate::Any(vec![contains("apple"), contains("banana")]));
+        sieve.add(Predicate::Not(Box::new(contains("pie"))));
+        assert_eq!("(/apple/ OR /banana/) AND NOT /pie/", sieve.to_string());
+
+        let testcases = [
+            ("apple tart", true),
+            ("banana split", true),
+            ("apple pie", false),
+            ("cherry", false),
+        ];
+        for (content, expected) in testcases.iter() {
+            assert_eq!(*expected, sieve.filter(content), "{}", content);
+        }
+
+        assert!(Predicate::All(vec![]).matches("cherry"));
+        assert!(!Predicate::Any(vec![]).matches("cherry"));
+        assert_eq!(
+            "/apple/",
+            Predicate::All(vec![contains("apple")]).to_string()
+        );
+    }
+
+    // is_offensive tests
+    #[test]
+    fn test_is_offensive() {
+        let testcases = [
+            ("apple", false),
+            ("off/offensive", true),
+            ("fruits/off/apple", true),
+            ("off/fruits/apple", true),
+            ("apple-o", true),
+            ("fruits/apple-o", true),
+            ("offensive", false),
+            ("coffee/apple", false),
+            ("off", false),
+            ("", false),
+        ];
+
+        for (path, expected) in testcases.iter() {
+            assert_eq!(
+                *expected,
+                super::is_offensive(path),
+                "is_offensive({}): expected: {}",
+                path,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_offensive_jar() {
+        let testcases = [
+            ("/usr/share/games/fortunes", "off/jokes", true),
+            ("/usr/share/games/fortunes", "jokes-o", true),
+            ("/usr/share/games/fortunes", "jokes", false),
+            // the path of the shelf itself does not count
+            ("/usr/share/games/fortunes/off", "jokes", false),
+            (
+                "/home/off/fortunes/jokes",
+                "/home/off/fortunes/jokes",
+                false,
+            ),
+            ("/home/fortunes/jokes-o", "/home/fortunes/jokes-o", true),
+            // the embedded jars are located from the root of the embedded data
+            ("embed:", "off/jokes", true),
+            ("embed:off", "off/jokes", false),
+            ("embed:off/jokes", "off/jokes", false),
+        ];
+        for (shelf, jar, expected) in testcases.iter() {
+            assert_eq!(
+                *expected,
+                super::is_offensive_jar(shelf, jar),
+                "{} {}",
+                shelf,
+                jar
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_selected() {
+        let testcases = [
+            ("apple", true, false, true),
+            ("apple", false, true, false),
+            ("apple", true, true, true),
+            ("off/apple", true, false, false),
+            ("off/apple", false, true, true),
+            ("off/apple", true, true, true),
+            ("apple-o", true, false, false),
+            ("apple-o", false, true, true),
+            ("apple", false, false, false),
+        ];
+
+        for (path, normal, offensive, expected) in testcases.iter() {
+            assert_eq!(
+                *expected,
+                super::is_selected(path, *normal, *offensive),
+                "is_selected({}, normal: {}, offensive: {}): expected: {}",
+                path,
+                normal,
+                offensive,
+                expected
+            );
+        }
+    }
+
     // trim_parent_path tests
     #[test]
     fn test_trim_parent_path() {
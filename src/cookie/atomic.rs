@@ -0,0 +1,102 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+
+/// Replace the file with the bytes atomically, so a reader sees either the old file or the
+/// complete new one, even if the writing is interrupted or the disk is full.
+///
+/// The bytes are written to a temporary file in the same directory, synced to the disk, and
+/// renamed over the file. The permissions of the replaced file are kept.
+pub fn write_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path
+        .file_name()
+        .with_context(|| format!("Invalid output file: {}", path.display()))?;
+    // a dot file, so it is skipped when the directory is loaded or indexed meanwhile
+    let temp = dir.join(format!(
+        ".{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    let result = write_temp(&temp, path, bytes).and_then(|_| {
+        std::fs::rename(&temp, path)?;
+        sync_dir(dir);
+        Ok(())
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result.with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn write_temp(temp: &Path, path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(temp)?;
+    file.write_all(bytes)?;
+    if let Ok(metadata) = std::fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Make the rename durable as well.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) {
+    if let Ok(dir) = std::fs::File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+/// Directories cannot be opened as files on Windows, where the rename is written through.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_file() {
+        let dir = std::env::temp_dir().join(format!("fortune-rs-atomic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("apple.dat");
+
+        write_file(&path, b"first").unwrap();
+        assert_eq!(b"first".to_vec(), std::fs::read(&path).unwrap());
+
+        // the permissions of the replaced file are kept
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let permissions = std::fs::Permissions::from_mode(0o640);
+            std::fs::set_permissions(&path, permissions).unwrap();
+            write_file(&path, b"second").unwrap();
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(0o640, mode & 0o777);
+        }
+        write_file(&path, b"third").unwrap();
+        assert_eq!(b"third".to_vec(), std::fs::read(&path).unwrap());
+
+        // no temporary file is left behind
+        let names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(vec![std::ffi::OsString::from("apple.dat")], names);
+
+        // the error tells the path
+        let missing = dir.join("missing/apple.dat");
+        let error = format!("{:#}", write_file(&missing, b"first").unwrap_err());
+        assert!(error.contains(&missing.display().to_string()), "{}", error);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
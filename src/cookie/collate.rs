@@ -0,0 +1,195 @@
+use std::cmp::Ordering;
+
+use anyhow::Result;
+
+use crate::cookie::Cookie;
+
+/// How the strings are compared when `strfile -o` orders them.
+pub enum Collation {
+    /// The key rules of BSD strfile: the leading non-alphanumeric characters are skipped, and
+    /// the rest is compared byte by byte, ignoring the ASCII case with `-i`.
+    Bsd { ignore_case: bool },
+    /// The collation of a locale, e.g. `de` or `zh-u-co-pinyin`, ignoring the case with `-i`.
+    #[cfg(feature = "collate")]
+    Locale(Box<icu_collator::Collator>),
+}
+
+impl Collation {
+    pub fn new(locale: Option<&str>, ignore_case: bool) -> Result<Self> {
+        match locale {
+            None => Ok(Collation::Bsd { ignore_case }),
+            #[cfg(feature = "collate")]
+            Some(locale) => {
+                use icu_collator::{Collator, CollatorOptions, Strength};
+                let data_locale: icu_provider::DataLocale = locale
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid locale: {} ({})", locale, e))?;
+                let mut options = CollatorOptions::new();
+                if ignore_case {
+                    options.strength = Some(Strength::Secondary);
+                }
+                let collator = Collator::try_new(&data_locale, options)
+                    .map_err(|e| anyhow::anyhow!("No collation for {}: {}", locale, e))?;
+                Ok(Collation::Locale(Box::new(collator)))
+            }
+            #[cfg(not(feature = "collate"))]
+            Some(_) => {
+                anyhow::bail!(
+                    "--collate is not built in, rebuild with: cargo build --features collate"
+                )
+            }
+        }
+    }
+
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        match self {
+            Collation::Bsd { ignore_case: false } => bsd_key(a).cmp(bsd_key(b)),
+            Collation::Bsd { ignore_case: true } => {
+                let lower = |key: &[u8]| key.to_ascii_lowercase();
+                lower(bsd_key(a)).cmp(&lower(bsd_key(b)))
+            }
+            #[cfg(feature = "collate")]
+            Collation::Locale(collator) => collator.compare(locale_key(a), locale_key(b)),
+        }
+    }
+
+    /// Sort the cookies, keeping the original order of the equal ones.
+    pub fn sort(&self, cookies: &mut [Cookie]) {
+        cookies.sort_by(|a, b| self.compare(&a.content, &b.content));
+    }
+}
+
+/// The sort key of BSD strfile, which starts from the first ASCII alphanumeric character.
+/// As the bytes are tested one by one, the leading non-ASCII characters are skipped as well.
+fn bsd_key(text: &str) -> &[u8] {
+    let bytes = text.as_bytes();
+    let start = bytes
+        .iter()
+        .position(u8::is_ascii_alphanumeric)
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+/// The sort key of the locale collation, without the leading punctuation, e.g. the quotes.
+#[cfg(feature = "collate")]
+fn locale_key(text: &str) -> &str {
+    text.trim_start_matches(|c: char| !c.is_alphanumeric())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(collation: &Collation, texts: &[&str]) -> Vec<String> {
+        let mut cookies: Vec<Cookie> = texts
+            .iter()
+            .map(|text| Cookie {
+                location: String::new(),
+                content: text.to_string(),
+                offset: 0,
+                metadata: Default::default(),
+            })
+            .collect();
+        collation.sort(&mut cookies);
+        cookies.into_iter().map(|c| c.content).collect()
+    }
+
+    #[test]
+    fn test_bsd_key() {
+        let testcases = [
+            ("apple", "apple"),
+            ("\"Zebra,\" he said.", "Zebra,\" he said."),
+            ("  -- 42", "42"),
+            ("...", ""),
+            ("«Éclair»", "clair»"),
+        ];
+        for (text, expected) in testcases.iter() {
+            assert_eq!(expected.as_bytes(), bsd_key(text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_collation_bsd() {
+        // the same strings as test_strfile_flag_o_and_i, which compares the data files of
+        // strfile -o and -o -i with the reference implementation
+        let texts = [
+            "\"Zebra,\" he said.",
+            "apple",
+            "...and then",
+            "Banana",
+            "  -- 42",
+            "banana split",
+            "Apple",
+        ];
+        let testcases = [
+            (
+                false,
+                vec![
+                    "  -- 42",
+                    "Apple",
+                    "Banana",
+                    "\"Zebra,\" he said.",
+                    "...and then",
+                    "apple",
+                    "banana split",
+                ],
+            ),
+            (
+                true,
+                vec![
+                    "  -- 42",
+                    "...and then",
+                    "apple",
+                    "Apple",
+                    "Banana",
+                    "banana split",
+                    "\"Zebra,\" he said.",
+                ],
+            ),
+        ];
+        for (ignore_case, expected) in testcases.iter() {
+            let collation = Collation::new(None, *ignore_case).unwrap();
+            assert_eq!(*expected, sorted(&collation, &texts), "-i: {}", ignore_case);
+        }
+    }
+
+    #[cfg(not(feature = "collate"))]
+    #[test]
+    fn test_collation_locale_not_built() {
+        assert!(Collation::new(Some("de"), false).is_err());
+    }
+
+    #[cfg(feature = "collate")]
+    #[test]
+    fn test_collation_locale() {
+        let texts = ["Zürich", "zebra", "Äpfel", "apple", "«Österreich»", "Ofen"];
+        let testcases = [
+            (
+                "de",
+                vec!["Äpfel", "apple", "Ofen", "«Österreich»", "zebra", "Zürich"],
+            ),
+            (
+                "sv",
+                vec!["apple", "Ofen", "zebra", "Zürich", "Äpfel", "«Österreich»"],
+            ),
+        ];
+        for (locale, expected) in testcases.iter() {
+            let collation = Collation::new(Some(locale), false).unwrap();
+            assert_eq!(*expected, sorted(&collation, &texts), "{}", locale);
+        }
+
+        // the equal strings ignoring the case keep their order
+        let collation = Collation::new(Some("en"), true).unwrap();
+        assert_eq!(vec!["A", "a", "b"], sorted(&collation, &["b", "A", "a"]));
+        let collation = Collation::new(Some("en"), false).unwrap();
+        assert_eq!(vec!["a", "A", "b"], sorted(&collation, &["b", "A", "a"]));
+
+        // the pinyin order of Chinese
+        let collation = Collation::new(Some("zh"), false).unwrap();
+        assert_eq!(
+            vec!["安", "北", "中"],
+            sorted(&collation, &["中", "北", "安"])
+        );
+        assert!(Collation::new(Some("not a locale!"), false).is_err());
+    }
+}
@@ -0,0 +1,384 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use argh::FromArgs;
+
+use crate::cookie::{embed::Embedded, layer, walk::Walker};
+
+/// Shells which the completion scripts are generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => anyhow::bail!("Unknown shell: {} (expected one of: bash, zsh, fish)", s),
+        }
+    }
+}
+
+/// A flag of the command, as listed by `--help`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flag {
+    pub short: Option<char>,
+    pub long: String,
+    pub takes_value: bool,
+    pub description: String,
+}
+
+/// The completions of a command: its flags, and the words completed as positional arguments
+/// besides the file names.
+#[derive(Debug, Clone)]
+pub struct Completions {
+    pub command: String,
+    pub flags: Vec<Flag>,
+    // the command printing the words one per line, run at completion time so the words are
+    // never out of date, e.g. `fortune --complete-sources`
+    pub words_command: Option<String>,
+}
+
+impl Completions {
+    /// The completions of the command parsed by the `Args` struct.
+    pub fn from_args<T: FromArgs>(command: &str, words_command: Option<&str>) -> Self {
+        let help = match T::from_args(&[command], &["--help"]) {
+            Ok(_) => String::new(),
+            Err(early_exit) => early_exit.output,
+        };
+        Completions::from_help(command, &help, words_command)
+    }
+
+    /// Take the flags from the `--help` output, so they never fall behind the `Args` struct.
+    pub fn from_help(command: &str, help: &str, words_command: Option<&str>) -> Self {
+        Completions {
+            command: command.to_string(),
+            flags: parse_help(help),
+            words_command: words_command.map(String::from),
+        }
+    }
+
+    pub fn generate(&self, shell: Shell) -> String {
+        match shell {
+            Shell::Bash => self.bash(),
+            Shell::Zsh => self.zsh(),
+            Shell::Fish => self.fish(),
+        }
+    }
+
+    fn bash(&self) -> String {
+        let function = format!("_{}", self.command.replace('-', "_"));
+        let names = |takes_value: bool| -> Vec<String> {
+            self.flags
+                .iter()
+                .filter(|f| f.takes_value == takes_value)
+                .flat_map(|f| {
+                    let long = format!("--{}", f.long);
+                    f.short.map(|c| format!("-{}", c)).into_iter().chain([long])
+                })
+                .collect()
+        };
+        let mut script = format!("{}() {{\n", function);
+        script.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+        script.push_str("    local prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+        script.push_str("    COMPREPLY=()\n");
+        let options = names(true);
+        if !options.is_empty() {
+            // the values of the options are not completed
+            script.push_str(&format!(
+                "    case \"$prev\" in\n        {})\n            return 0\n            ;;\n    esac\n",
+                options.join("|")
+            ));
+        }
+        let mut flags = names(false);
+        flags.extend(options);
+        script.push_str(&format!(
+            "    if [[ \"$cur\" == -* ]]; then\n        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n",
+            flags.join(" ")
+        ));
+        script.push_str("    else\n        COMPREPLY=(");
+        if let Some(words_command) = &self.words_command {
+            script.push_str(&format!(
+                "$(compgen -W \"$({} 2>/dev/null)\" -- \"$cur\") ",
+                words_command
+            ));
+        }
+        script.push_str("$(compgen -f -- \"$cur\"))\n    fi\n}\n");
+        script.push_str(&format!(
+            "complete -o filenames -F {} {}\n",
+            function, self.command
+        ));
+        script
+    }
+
+    fn zsh(&self) -> String {
+        let function = format!("_{}", self.command.replace('-', "_"));
+        let mut script = format!("#compdef {}\n\n", self.command);
+        if let Some(words_command) = &self.words_command {
+            script.push_str(&format!(
+                "{}_sources() {{\n    local -a sources\n",
+                function
+            ));
+            script.push_str(&format!(
+                "    sources=(${{(f)\"$({} 2>/dev/null)\"}})\n",
+                words_command
+            ));
+            script.push_str(
+                "    _alternative 'sources:source:compadd -a sources' 'files:file:_files'\n}\n\n",
+            );
+        }
+        script.push_str(&format!("{}() {{\n    _arguments -s \\\n", function));
+        for flag in self.flags.iter() {
+            let description = flag
+                .description
+                .replace('\\', "\\\\")
+                .replace('[', "\\[")
+                .replace(']', "\\]")
+                .replace(':', "\\:");
+            let value = if flag.takes_value {
+                format!(":{}:", flag.long)
+            } else {
+                String::new()
+            };
+            let spec = single_quote(&format!("[{}]{}", description, value));
+            match flag.short {
+                Some(c) => script.push_str(&format!(
+                    "        '*'{{-{},--{}}}{} \\\n",
+                    c, flag.long, spec
+                )),
+                None => script.push_str(&format!("        '*--{}'{} \\\n", flag.long, spec)),
+            }
+        }
+        if self.words_command.is_none() {
+            script.push_str("        '*:file:_files'\n}\n\n");
+        } else {
+            script.push_str(&format!("        '*:source:{}_sources'\n}}\n\n", function));
+        }
+        script.push_str(&format!("compdef {} {}\n", function, self.command));
+        script
+    }
+
+    fn fish(&self) -> String {
+        let mut script = String::new();
+        for flag in self.flags.iter() {
+            script.push_str(&format!("complete -c {}", self.command));
+            if let Some(c) = flag.short {
+                script.push_str(&format!(" -s {}", c));
+            }
+            script.push_str(&format!(" -l {}", flag.long));
+            if flag.takes_value {
+                // the values of the options are not completed
+                script.push_str(" -x");
+            }
+            script.push_str(&format!(" -d {}\n", single_quote(&flag.description)));
+        }
+        if let Some(words_command) = &self.words_command {
+            script.push_str(&format!(
+                "complete -c {} -a {}\n",
+                self.command,
+                single_quote(&format!("({} 2>/dev/null)", words_command))
+            ));
+        }
+        script
+    }
+}
+
+/// Parse the flags from the `--help` output of argh. Whether a flag takes a value is taken
+/// from the usage line, e.g. `[-m <pattern...>]`.
+pub fn parse_help(help: &str) -> Vec<Flag> {
+    let usage = help.lines().next().unwrap_or_default();
+    let mut flags: Vec<Flag> = Vec::new();
+    for line in help.lines().skip_while(|l| *l != "Options:").skip(1) {
+        if line.trim().is_empty() {
+            break;
+        }
+        if !line.starts_with("  -") {
+            // a wrapped description
+            if let Some(flag) = flags.last_mut() {
+                if !flag.description.is_empty() {
+                    flag.description.push(' ');
+                }
+                flag.description.push_str(line.trim());
+            }
+            continue;
+        }
+        let line = line.trim();
+        let (short, rest) = match line.strip_prefix('-').and_then(|r| r.split_once(", ")) {
+            Some((c, rest)) if c.chars().count() == 1 => (c.chars().next(), rest),
+            _ => (None, line),
+        };
+        let (long, description) = rest.split_once(' ').unwrap_or((rest, ""));
+        let long = long.trim_start_matches("--").to_string();
+        let name = match short {
+            Some(c) => format!("-{}", c),
+            None => format!("--{}", long),
+        };
+        flags.push(Flag {
+            short,
+            takes_value: usage.contains(&format!("[{} <", name)),
+            long,
+            description: description.trim().to_string(),
+        });
+    }
+    flags
+}
+
+/// The names which `fortune` accepts as the sources: the embedded packs and jars, and the
+/// files in the data directories.
+pub fn source_names() -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    names.extend(
+        Embedded::list()
+            .iter()
+            .map(|entry| entry.trim_end_matches('/').to_string()),
+    );
+    let walker = Walker::default();
+    for dir in layer::data_dirs().iter().filter(|d| d.is_dir()) {
+        for file in walker.walk(dir).unwrap_or_default() {
+            if let Ok(name) = file.strip_prefix(dir) {
+                names.push(to_slash(name));
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn to_slash(path: &Path) -> String {
+    path.iter()
+        .map(|p| p.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Quote the text for the shell, e.g. `don't` => `'don'\''t'`.
+fn single_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELP: &str =
+        "Usage: fortune [<paths...>] [-a] [-m <pattern...>] [--any] [--socket <socket>]
+
+A Rust implementation of the classic fortune program
+
+Positional Arguments:
+  paths             [[n%] file/directory/all]
+
+Options:
+  -a, --all         choose from all lists of maxims
+  -m, --pattern     print out all fortunes which match the pattern (repeatable,
+                    all must match)
+  --any             match any of the -m patterns
+  -u, --no-utf8-translate
+                    don't translate UTF-8 fortunes to the locale
+  --socket          path of the daemon socket
+  --help            display usage information
+";
+
+    #[test]
+    fn test_shell_from_str() {
+        assert_eq!(Shell::Bash, "bash".parse().unwrap());
+        assert_eq!(Shell::Zsh, "zsh".parse().unwrap());
+        assert_eq!(Shell::Fish, "fish".parse().unwrap());
+        assert!("tcsh".parse::<Shell>().is_err());
+    }
+
+    #[test]
+    fn test_parse_help() {
+        let flag = |short: Option<char>, long: &str, takes_value: bool, description: &str| Flag {
+            short,
+            long: long.to_string(),
+            takes_value,
+            description: description.to_string(),
+        };
+        let expected = vec![
+            flag(Some('a'), "all", false, "choose from all lists of maxims"),
+            flag(
+                Some('m'),
+                "pattern",
+                true,
+                "print out all fortunes which match the pattern (repeatable, all must match)",
+            ),
+            flag(None, "any", false, "match any of the -m patterns"),
+            flag(
+                Some('u'),
+                "no-utf8-translate",
+                false,
+                "don't translate UTF-8 fortunes to the locale",
+            ),
+            flag(None, "socket", true, "path of the daemon socket"),
+            flag(None, "help", false, "display usage information"),
+        ];
+        assert_eq!(expected, parse_help(HELP));
+    }
+
+    #[test]
+    fn test_completions_generate() {
+        let completions =
+            Completions::from_help("fortune", HELP, Some("fortune --complete-sources"));
+        let testcases = [
+            (Shell::Bash, "-a --all --any -u"),
+            (Shell::Bash, "-m|--pattern|--socket)"),
+            (
+                Shell::Bash,
+                "compgen -W \"$(fortune --complete-sources 2>/dev/null)\"",
+            ),
+            (Shell::Bash, "complete -o filenames -F _fortune fortune"),
+            (
+                Shell::Zsh,
+                "'*'{-a,--all}'[choose from all lists of maxims]'",
+            ),
+            (
+                Shell::Zsh,
+                "'*--socket''[path of the daemon socket]:socket:'",
+            ),
+            (
+                Shell::Zsh,
+                "sources=(${(f)\"$(fortune --complete-sources 2>/dev/null)\"})",
+            ),
+            (Shell::Zsh, "[don'\\''t translate"),
+            (Shell::Fish, "complete -c fortune -s m -l pattern -x -d"),
+            (Shell::Fish, "complete -c fortune -l any -d 'match any"),
+            (
+                Shell::Fish,
+                "complete -c fortune -a '(fortune --complete-sources 2>/dev/null)'",
+            ),
+        ];
+        for (shell, expected) in testcases.iter() {
+            let script = completions.generate(*shell);
+            assert!(
+                script.contains(expected),
+                "{:?}:\n{}\n cannot find '{}'",
+                shell,
+                script,
+                expected
+            );
+        }
+
+        // only the files are completed without the words
+        let completions = Completions::from_help("strfile", HELP, None);
+        assert!(completions.generate(Shell::Zsh).contains("'*:file:_files'"));
+        assert!(!completions.generate(Shell::Fish).contains(" -a "));
+    }
+
+    #[test]
+    fn test_source_names() {
+        let names = source_names();
+        assert!(!names.contains(&"all".to_string()));
+        assert!(names.contains(&"en".to_string()));
+        assert!(names.iter().all(|n| !n.ends_with('/')));
+    }
+}
@@ -0,0 +1,134 @@
+use std::io::Read;
+
+use anyhow::{Context, Result};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Compression formats of the cookie files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detect the compression format by the file extension, then by the magic bytes.
+    pub fn detect(path: &str, bytes: &[u8]) -> Compression {
+        if path.ends_with(".gz") || bytes.starts_with(&GZIP_MAGIC) {
+            Compression::Gzip
+        } else if path.ends_with(".zst") || bytes.starts_with(&ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    pub fn decompress(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        let mut content = Vec::new();
+        match self {
+            Compression::None => content.extend_from_slice(bytes),
+            Compression::Gzip => {
+                flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut content)?;
+            }
+            Compression::Zstd => {
+                ruzstd::decoding::StreamingDecoder::new(bytes)
+                    .map_err(|e| anyhow::anyhow!("{}", e))?
+                    .read_to_end(&mut content)?;
+            }
+        }
+        Ok(content)
+    }
+}
+
+/// Decode the bytes of a cookie file as UTF-8 text, decompressing it if necessary.
+pub fn decode(path: &str, bytes: &[u8]) -> Result<String> {
+    let compression = Compression::detect(path, bytes);
+    let content = compression.decompress(bytes).with_context(|| {
+        format!(
+            "Failed to decompress {:?} cookie file: {}",
+            compression, path
+        )
+    })?;
+    String::from_utf8(content).with_context(|| format!("Invalid UTF-8 in cookie file: {}", path))
+}
+
+/// Read a cookie file as UTF-8 text, decompressing it if necessary.
+pub fn read_to_string(path: &str) -> Result<String> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("Failed to read cookie file: {}", path))?;
+    decode(path, &bytes)
+}
+
+/// Remove the compression suffix (`.gz`, `.zst`) from the path, if any.
+pub fn strip_suffix(path: &str) -> &str {
+    path.strip_suffix(".gz")
+        .or_else(|| path.strip_suffix(".zst"))
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const CONTENT: &str = "apple\n%\nbanana\n%\n";
+
+    fn gzip(content: &str) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zstd(content: &str) -> Vec<u8> {
+        ruzstd::encoding::compress_to_vec(
+            content.as_bytes(),
+            ruzstd::encoding::CompressionLevel::Fastest,
+        )
+    }
+
+    #[test]
+    fn test_compression_detect() {
+        let testcases = [
+            ("apple", CONTENT.as_bytes().to_vec(), Compression::None),
+            ("apple.gz", CONTENT.as_bytes().to_vec(), Compression::Gzip),
+            ("apple.zst", CONTENT.as_bytes().to_vec(), Compression::Zstd),
+            ("apple", gzip(CONTENT), Compression::Gzip),
+            ("apple", zstd(CONTENT), Compression::Zstd),
+        ];
+        for (path, bytes, expected) in testcases.iter() {
+            assert_eq!(*expected, Compression::detect(path, bytes), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let testcases = [
+            ("apple", CONTENT.as_bytes().to_vec()),
+            ("apple.gz", gzip(CONTENT)),
+            ("apple.zst", zstd(CONTENT)),
+            ("apple", gzip(CONTENT)),
+            ("apple", zstd(CONTENT)),
+        ];
+        for (path, bytes) in testcases.iter() {
+            assert_eq!(CONTENT, decode(path, bytes).unwrap(), "{}", path);
+        }
+
+        assert!(decode("apple", &[0xff, 0xfe, 0x00]).is_err());
+        assert!(decode("apple.gz", CONTENT.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_strip_suffix() {
+        let testcases = [
+            ("apple", "apple"),
+            ("apple.gz", "apple"),
+            ("fruits/apple.zst", "fruits/apple"),
+            ("apple.dat", "apple.dat"),
+        ];
+        for (path, expected) in testcases.iter() {
+            assert_eq!(*expected, strip_suffix(path));
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+
+use crate::cookie::collate::Collation;
+use crate::cookie::search::{self, SearchIndex};
+use crate::cookie::serializer::{Serializer, SerializerType};
+use crate::cookie::{self, atomic, compress, CookieJar};
+
+/// The data file of the cookie file, e.g. `fortunes.gz` => `fortunes.dat`.
+pub fn dat_path(file: &Path) -> PathBuf {
+    let file = file.to_string_lossy();
+    PathBuf::from(format!("{}.dat", compress::strip_suffix(&file)))
+}
+
+/// Order the strings by the collation (`-o`), or randomize them (`-r`), and set the flags of
+/// the data file accordingly.
+pub fn order(jar: &mut CookieJar, collation: Option<&Collation>, randomized: bool) {
+    if let Some(collation) = collation {
+        collation.sort(&mut jar.cookies);
+        jar.flags |= cookie::FLAGS_ORDERED;
+    }
+    if randomized {
+        jar.cookies.shuffle(&mut thread_rng());
+        jar.flags |= cookie::FLAGS_RANDOMIZED;
+    }
+}
+
+/// Rebuild the data file from the cookie file, with the flags, delimiter and platform format
+/// of the existing data file. The search index is rebuilt as well, if there is one.
+/// The strings are ordered by the collation if the data file was ordered, or `ordered` is set.
+pub fn rebuild(
+    file: &Path,
+    datfile: &Path,
+    collation: &Collation,
+    ordered: bool,
+) -> Result<CookieJar> {
+    let (old, format) = read_dat(datfile)?;
+    let mut jar = CookieJar::from_text_file(&file.to_string_lossy(), old.delim)?;
+    if old.flags & cookie::FLAGS_COMMENTS != 0 {
+        jar.parse_comments();
+    }
+    let textfile = file.to_string_lossy();
+    if Path::new(&search::index_path(&textfile)).exists() {
+        SearchIndex::from_jar(&jar).write(&textfile)?;
+    }
+    // neither the case nor the locale of the ordering is kept in the data file, so they are
+    // given by the caller, e.g. `strfile --watch DIR -i`
+    order(
+        &mut jar,
+        (ordered || old.flags & cookie::FLAGS_ORDERED != 0).then_some(collation),
+        old.flags & cookie::FLAGS_RANDOMIZED != 0,
+    );
+    jar.flags |= old.flags & cookie::FLAGS_ROTATED;
+    atomic::write_file(datfile, &Serializer::to_bytes(&jar, &format))?;
+    Ok(jar)
+}
+
+/// Read the data file, and detect its platform format.
+pub fn read_dat(datfile: &Path) -> Result<(CookieJar, SerializerType)> {
+    let bytes =
+        std::fs::read(datfile).with_context(|| format!("Failed to read {}", datfile.display()))?;
+    let format = Serializer::get_type_by_bytes(&bytes);
+    Serializer::check(&bytes, &format).with_context(|| datfile.display().to_string())?;
+    Ok((Serializer::from_bytes(&bytes, &format), format))
+}
@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::cookie::{CookieCabinet, CookieJar};
+
+/// Kinds of the duplicated cookies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKind {
+    Exact, // the same content
+    Near,  // the same content after normalizing whitespace, case and punctuation
+}
+
+/// Location of a cookie in the cabinet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieLocation {
+    pub shelf: String,
+    pub jar: String,
+    pub offset: u64,
+}
+
+/// A group of cookies which are duplicates of each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    pub content: String, // content of the first cookie
+    pub locations: Vec<CookieLocation>,
+}
+
+pub struct Deduper;
+
+impl Deduper {
+    /// Normalize the content for finding near-duplicates: lowercase it, remove the
+    /// punctuation, and collapse the whitespace.
+    pub fn normalize(content: &str) -> String {
+        let text: String = content
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphanumeric() || c.is_whitespace())
+            .collect();
+        text.split_whitespace().collect::<Vec<&str>>().join(" ")
+    }
+
+    /// Find the duplicated cookies in the cabinet, in the order of their first occurrence.
+    /// A group is an exact duplicate if all its cookies have the same content, otherwise
+    /// it is a near-duplicate, which is preceded by the groups of its exact duplicates.
+    /// The cookies without any words, e.g. `...`, are not compared.
+    pub fn find(cabinet: &CookieCabinet) -> Vec<DuplicateGroup> {
+        // group the cookies by the normalized content, keeping the order of first occurrence
+        let mut order: Vec<String> = Vec::new();
+        let mut groups: BTreeMap<String, Vec<(CookieLocation, &str)>> = BTreeMap::new();
+        for shelf in cabinet.iter() {
+            for jar in shelf.iter() {
+                for cookie in jar.iter() {
+                    let key = Deduper::normalize(&cookie.content);
+                    if key.is_empty() {
+                        continue;
+                    }
+                    let location = CookieLocation {
+                        shelf: shelf.location.clone(),
+                        jar: jar.location.clone(),
+                        offset: cookie.offset,
+                    };
+                    let group = groups.entry(key.clone()).or_default();
+                    if group.is_empty() {
+                        order.push(key);
+                    }
+                    group.push((location, &cookie.content));
+                }
+            }
+        }
+
+        let mut duplicates = Vec::new();
+        for key in order.iter() {
+            let group = match groups.remove(key) {
+                Some(group) if group.len() > 1 => group,
+                _ => continue,
+            };
+            // the cookies of the same content, in the order of their first occurrence
+            let mut contents: Vec<(&str, Vec<CookieLocation>)> = Vec::new();
+            for (location, content) in group.iter() {
+                match contents.iter_mut().find(|(c, _)| c == content) {
+                    Some((_, locations)) => locations.push(location.clone()),
+                    None => contents.push((content, vec![location.clone()])),
+                }
+            }
+            // a near-duplicate group reports its exact duplicates separately, too
+            let near = contents.len() > 1;
+            for (content, locations) in contents {
+                if locations.len() > 1 {
+                    duplicates.push(DuplicateGroup {
+                        kind: DuplicateKind::Exact,
+                        content: content.to_string(),
+                        locations,
+                    });
+                }
+            }
+            if near {
+                duplicates.push(DuplicateGroup {
+                    kind: DuplicateKind::Near,
+                    content: group[0].1.to_string(),
+                    locations: group.into_iter().map(|(l, _)| l).collect(),
+                });
+            }
+        }
+        duplicates
+    }
+
+    /// Drop the duplicated cookies (exact or near) from the jar, keeping the first one.
+    /// The cookies without any words are dropped only if exactly the same, the same as `find()`.
+    /// Return the number of dropped cookies.
+    pub fn dedupe_jar(jar: &mut CookieJar) -> usize {
+        let before = jar.cookies.len();
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut seen_exact: HashSet<String> = HashSet::new();
+        jar.cookies.retain(|c| {
+            let key = Deduper::normalize(&c.content);
+            if key.is_empty() {
+                seen_exact.insert(c.content.clone())
+            } else {
+                seen.insert(key)
+            }
+        });
+        jar.update_lengths();
+        before - jar.cookies.len()
+    }
+
+    /// Write the report of the duplicated cookies.
+    pub fn report<W: Write>(groups: &[DuplicateGroup], writer: &mut W) -> Result<()> {
+        for group in groups.iter() {
+            let kind = match group.kind {
+                DuplicateKind::Exact => "exact",
+                DuplicateKind::Near => "near",
+            };
+            let first_line = group.content.lines().next().unwrap_or_default();
+            writeln!(
+                writer,
+                "{} duplicate ({}): {}",
+                kind,
+                group.locations.len(),
+                first_line
+            )?;
+            for location in group.locations.iter() {
+                writeln!(
+                    writer,
+                    "    {}: {} @ {}",
+                    location.shelf, location.jar, location.offset
+                )?;
+            }
+        }
+        let num_exact = groups
+            .iter()
+            .filter(|g| g.kind == DuplicateKind::Exact)
+            .count();
+        writeln!(
+            writer,
+            "Found {} exact and {} near duplicate groups",
+            num_exact,
+            groups.len() - num_exact
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookie::CookieShelf;
+
+    #[test]
+    fn test_normalize() {
+        let testcases = [
+            ("Hello, World!", "hello world"),
+            ("  hello\n\tworld  ", "hello world"),
+            ("Don't panic.", "dont panic"),
+            ("學而時習之，不亦說乎？", "學而時習之不亦說乎"),
+            ("...", ""),
+        ];
+        for (content, expected) in testcases.iter() {
+            assert_eq!(*expected, Deduper::normalize(content), "{}", content);
+        }
+    }
+
+    #[test]
+    fn test_find() {
+        let mut cabinet = CookieCabinet::default();
+        cabinet.push(CookieShelf::new("valley", 0.0));
+        cabinet.shelves[0].jars.push(
+            CookieJar::from_text(
+                "apple\n%\nDon't panic.\n%\nbanana\n%\napple\n%\n",
+                "fruits",
+                '%',
+            )
+            .unwrap(),
+        );
+        cabinet.shelves[0].jars.push(
+            CookieJar::from_text("don't  PANIC!\n%\napple\n%\ncherry\n%\n", "more", '%').unwrap(),
+        );
+
+        let groups = Deduper::find(&cabinet);
+        assert_eq!(2, groups.len());
+
+        assert_eq!(DuplicateKind::Exact, groups[0].kind);
+        assert_eq!("apple", groups[0].content);
+        assert_eq!(
+            vec![("fruits", 0), ("fruits", 32), ("more", 16)],
+            groups[0]
+                .locations
+                .iter()
+                .map(|l| (l.jar.as_str(), l.offset))
+                .collect::<Vec<_>>()
+        );
+
+        assert_eq!(DuplicateKind::Near, groups[1].kind);
+        assert_eq!("Don't panic.", groups[1].content);
+        assert_eq!(2, groups[1].locations.len());
+
+        let mut output: Vec<u8> = Vec::new();
+        Deduper::report(&groups, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("exact duplicate (3): apple\n    valley: fruits @ 0\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("near duplicate (2): Don't panic.\n"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("Found 1 exact and 1 near duplicate groups"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_dedupe_jar() {
+        let mut jar = CookieJar::from_text(
+            "apple\n%\nA long banana\n%\nApple!\n%\ncherry\n%\n",
+            "fruits",
+            '%',
+        )
+        .unwrap();
+        assert_eq!(1, Deduper::dedupe_jar(&mut jar));
+        assert_eq!(
+            vec!["apple", "A long banana", "cherry"],
+            jar.iter().map(|c| c.content.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(14, jar.max_length);
+        assert_eq!(6, jar.min_length);
+
+        // the cookies without any words are different unless exactly the same
+        let mut jar = CookieJar::from_text("!!!\n%\n...\n%\n!!!\n%\n", "marks", '%').unwrap();
+        assert_eq!(1, Deduper::dedupe_jar(&mut jar));
+        assert_eq!(
+            vec!["!!!", "..."],
+            jar.iter().map(|c| c.content.as_str()).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_find_exact_and_near() {
+        let mut cabinet = CookieCabinet::default();
+        cabinet.push(CookieShelf::new("valley", 0.0));
+        cabinet.shelves[0]
+            .jars
+            .push(CookieJar::from_text("apple\n%\nApple!\n%\napple\n%\n", "fruits", '%').unwrap());
+
+        let groups = Deduper::find(&cabinet);
+        assert_eq!(2, groups.len());
+        assert_eq!(DuplicateKind::Exact, groups[0].kind);
+        assert_eq!("apple", groups[0].content);
+        assert_eq!(
+            vec![0, 17],
+            groups[0]
+                .locations
+                .iter()
+                .map(|l| l.offset)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(DuplicateKind::Near, groups[1].kind);
+        assert_eq!(3, groups[1].locations.len());
+    }
+}
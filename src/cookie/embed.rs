@@ -1,4 +1,7 @@
 use anyhow::{Error, Result};
+use glob::{MatchOptions, Pattern};
+
+use crate::cookie::compress;
 use rust_embed::Embed;
 
 pub const EMBED_PREFIX: &str = "embed:";
@@ -11,26 +14,72 @@ pub struct Embedded;
 
 impl Embedded {
     pub fn exists(path: &str) -> bool {
-        !Embedded::find(path).unwrap().is_empty()
+        Embedded::find(path)
+            .map(|matches| !matches.is_empty())
+            .unwrap_or(false)
     }
 
     pub fn read_to_string(path: &str) -> Result<String> {
         let path = Embedded::trim_prefix(path);
         let file =
             Embedded::get(path).ok_or(Error::msg(format!("{}{} not found", EMBED_PREFIX, path)))?;
-        let content = std::str::from_utf8(file.data.as_ref())?;
-        Ok(content.to_string())
+        compress::decode(path, file.data.as_ref())
     }
 
+    /// Find the embedded files under the given path.
+    /// The path is matched by whole path segments, so `embed:en` matches `en/fortunes`
+    /// but `embed:e` does not. Glob patterns (e.g. `embed:*/fortunes`) are supported,
+    /// and a pattern matching a directory selects every file under it.
     pub fn find(path: &str) -> Result<Vec<String>> {
-        let path = Embedded::trim_prefix(path);
-        let matches = Embedded::iter()
-            .map(|entry| entry.to_string())
-            .filter(|entry| entry.starts_with(path))
-            .collect();
+        let path = Embedded::trim_prefix(path).trim_end_matches('/');
+        let mut matches: Vec<String> = if is_glob(path) {
+            let pattern = Pattern::new(path)?;
+            let options = MatchOptions {
+                require_literal_separator: true,
+                ..MatchOptions::new()
+            };
+            Embedded::iter()
+                .map(|entry| entry.to_string())
+                .filter(|entry| {
+                    ancestors(entry).any(|parent| pattern.matches_with(parent, options))
+                })
+                .collect()
+        } else {
+            Embedded::iter()
+                .map(|entry| entry.to_string())
+                .filter(|entry| {
+                    path.is_empty()
+                        || entry == path
+                        || entry
+                            .strip_prefix(path)
+                            .is_some_and(|rest| rest.starts_with('/'))
+                })
+                .collect()
+        };
+        matches.sort();
         Ok(matches)
     }
 
+    /// List the whole embedded tree, including the intermediate directories.
+    /// Directories are suffixed with '/', e.g. `en/` is a pack and `en/fortunes` is a jar.
+    pub fn list() -> Vec<String> {
+        let mut entries: Vec<String> = Vec::new();
+        for entry in Embedded::iter() {
+            for parent in ancestors(&entry) {
+                let item = if parent.len() < entry.len() {
+                    format!("{}/", parent)
+                } else {
+                    parent.to_string()
+                };
+                if !entries.contains(&item) {
+                    entries.push(item);
+                }
+            }
+        }
+        entries.sort();
+        entries
+    }
+
     pub fn format_path(path: &str) -> String {
         if path.starts_with(EMBED_PREFIX) {
             path.to_string()
@@ -44,6 +93,18 @@ impl Embedded {
     }
 }
 
+/// Check whether the given path contains glob wildcards.
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Iterate over the path and its ancestors, e.g. `a/b/c` yields `a`, `a/b` and `a/b/c`.
+fn ancestors(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/')
+        .map(move |(i, _)| &path[..i])
+        .chain(std::iter::once(path))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -68,6 +129,42 @@ mod tests {
             "find(embed:) should return more than 1 entry"
         );
 
+        let testcases = [
+            ("embed:en", vec!["en/fortunes"]),
+            ("embed:en/", vec!["en/fortunes"]),
+            ("embed:e", vec![]),
+            ("embed:en/fort", vec![]),
+            ("embed:*/fortunes", vec!["en/fortunes"]),
+            ("embed:*/fort*", vec!["en/fortunes"]),
+            ("embed:z?", vec!["zh/lunyu"]),
+            ("embed:*", vec!["en/fortunes", "zh/lunyu"]),
+            ("embed:fortunes", vec![]),
+        ];
+        for (path, expected) in testcases.iter() {
+            assert_eq!(
+                *expected,
+                Embedded::find(path).unwrap(),
+                "find({}) should return {:?}",
+                path,
+                expected
+            );
+        }
+        assert!(!Embedded::exists("embed:e"), "should not found embed:e");
+        assert!(
+            !Embedded::exists("embed:[invalid"),
+            "should not found embed:[invalid"
+        );
+
+        let entries = Embedded::list();
+        for expected in ["en/", "en/fortunes", "zh/", "zh/lunyu"] {
+            assert!(
+                entries.contains(&expected.to_string()),
+                "list() should contain {}, got: {:?}",
+                expected,
+                entries
+            );
+        }
+
         assert_eq!(
             "embed:file1",
             Embedded::format_path("file1"),
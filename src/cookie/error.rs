@@ -0,0 +1,157 @@
+use std::fmt;
+
+/// Errors of the command-line arguments, each with its own exit code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ArgError {
+    InvalidWeight(String),  // 'abc%' is not a number
+    NegativeWeight(String), // '-5%' is less than zero
+    MissingPath(String),    // '50%' is not followed by a file or directory
+    WeightsOverflow(f64),   // the percentages sum to more than 100%
+    NoResidualPlace(f64),   // the percentages sum to less than 100%, and no source takes the rest
+    NoResidualLeft, // the percentages sum to 100%, and nothing is left for the other sources
+    ZeroWeights,    // the relative weights are all zero, and no source is left
+    BadPattern {
+        pattern: String,
+        position: Option<usize>, // character position of the error in the pattern
+        message: String,
+    },
+}
+
+impl ArgError {
+    /// Exit code of the program for the error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ArgError::InvalidWeight(_) => 3,
+            ArgError::NegativeWeight(_) => 4,
+            ArgError::MissingPath(_) => 5,
+            ArgError::BadPattern { .. } => 6,
+            ArgError::WeightsOverflow(_)
+            | ArgError::NoResidualPlace(_)
+            | ArgError::NoResidualLeft
+            | ArgError::ZeroWeights => 7,
+        }
+    }
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgError::InvalidWeight(weight) => write!(
+                f,
+                "invalid weight '{}': expected a number followed by '%', e.g. '30%'",
+                weight
+            ),
+            ArgError::NegativeWeight(weight) => {
+                write!(f, "invalid weight '{}': weight cannot be negative", weight)
+            }
+            ArgError::MissingPath(weight) => write!(
+                f,
+                "weight '{}' is not followed by a file or directory, e.g. '{} fortunes'",
+                weight, weight
+            ),
+            ArgError::WeightsOverflow(total) => {
+                write!(f, "probabilities sum to {}% > 100%", total)
+            }
+            ArgError::NoResidualPlace(residual) => write!(
+                f,
+                "no place to put residual probability ({}%), add a file or directory without weight",
+                residual
+            ),
+            ArgError::NoResidualLeft => write!(
+                f,
+                "no probability left to put in residual files (100%), or use '--weights relative'"
+            ),
+            ArgError::ZeroWeights => write!(
+                f,
+                "all weights are zero, give a positive weight to at least one file or directory"
+            ),
+            ArgError::BadPattern {
+                pattern,
+                position,
+                message,
+            } => {
+                write!(f, "invalid pattern '{}': {}", pattern, message)?;
+                if let Some(position) = position {
+                    write!(
+                        f,
+                        "\n    {}\n    {}^ at position {}",
+                        pattern,
+                        " ".repeat(*position),
+                        position
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+/// Parse a weight argument like `30%`.
+pub fn parse_weight(item: &str) -> Result<f64, ArgError> {
+    let weight = item
+        .strip_suffix('%')
+        .unwrap_or(item)
+        .trim()
+        .parse::<f64>()
+        .ok()
+        .filter(|w| w.is_finite())
+        .ok_or_else(|| ArgError::InvalidWeight(item.to_string()))?;
+    if weight < 0.0 {
+        return Err(ArgError::NegativeWeight(item.to_string()));
+    }
+    Ok(weight)
+}
+
+/// Build the error of an invalid regular expression, locating the error in the pattern if possible.
+pub fn bad_pattern(pattern: &str, message: &str) -> ArgError {
+    let position = match regex_syntax::ast::parse::Parser::new().parse(pattern) {
+        Err(e) => Some(pattern[..e.span().start.offset].chars().count()),
+        Ok(_) => None,
+    };
+    ArgError::BadPattern {
+        pattern: pattern.to_string(),
+        position,
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_weight() {
+        let testcases = [
+            ("30%", Ok(30.0)),
+            ("0%", Ok(0.0)),
+            ("12.5%", Ok(12.5)),
+            ("abc%", Err(ArgError::InvalidWeight("abc%".to_string()))),
+            ("%", Err(ArgError::InvalidWeight("%".to_string()))),
+            ("nan%", Err(ArgError::InvalidWeight("nan%".to_string()))),
+            ("-5%", Err(ArgError::NegativeWeight("-5%".to_string()))),
+        ];
+        for (item, expected) in testcases.iter() {
+            assert_eq!(*expected, parse_weight(item), "{}", item);
+        }
+    }
+
+    #[test]
+    fn test_bad_pattern() {
+        let testcases = [
+            ("(", Some(0)),
+            ("abc)", Some(3)),
+            ("a{2,1}", Some(1)),
+            ("蘋果(", Some(2)),
+        ];
+        for (pattern, position) in testcases.iter() {
+            let error = bad_pattern(pattern, "error");
+            assert_eq!(6, error.exit_code());
+            match error {
+                ArgError::BadPattern { position: p, .. } => assert_eq!(*position, p, "{}", pattern),
+                _ => panic!("unexpected error: {:?}", error),
+            }
+        }
+    }
+}
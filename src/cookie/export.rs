@@ -0,0 +1,159 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use serde_json::{json, Value};
+
+use crate::cookie::{Cookie, CookieCabinet};
+
+/// Output formats of the exported cookies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,   // a single JSON array
+    Ndjson, // one JSON object per line
+    Csv,    // table with a header row
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(ExportFormat::Json),
+            "ndjson" | "jsonl" => Ok(ExportFormat::Ndjson),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => anyhow::bail!(
+                "Unknown export format: {} (expected one of: json, ndjson, csv)",
+                s
+            ),
+        }
+    }
+}
+
+const CSV_HEADER: [&str; 7] = [
+    "shelf", "jar", "layer", "offset", "length", "content", "metadata",
+];
+
+pub struct Exporter;
+
+impl Exporter {
+    /// Export all cookies of the cabinet in the given format.
+    /// The cookies are walked in the same order as `-m` prints them.
+    pub fn export<W: Write>(
+        cabinet: &CookieCabinet,
+        format: ExportFormat,
+        writer: &mut W,
+    ) -> Result<()> {
+        let records = cabinet.iter().flat_map(|shelf| {
+            shelf.iter().flat_map(move |jar| {
+                jar.iter()
+                    .map(move |cookie| Exporter::to_value(&shelf.location, &jar.layer, cookie))
+            })
+        });
+
+        match format {
+            ExportFormat::Json => {
+                let records: Vec<Value> = records.collect();
+                serde_json::to_writer_pretty(&mut *writer, &records)?;
+                writeln!(writer)?;
+            }
+            ExportFormat::Ndjson => {
+                for record in records {
+                    serde_json::to_writer(&mut *writer, &record)?;
+                    writeln!(writer)?;
+                }
+            }
+            ExportFormat::Csv => {
+                let mut csv_writer = csv::Writer::from_writer(&mut *writer);
+                csv_writer.write_record(CSV_HEADER)?;
+                for record in records {
+                    let fields: Vec<String> = CSV_HEADER
+                        .iter()
+                        .map(|key| match &record[*key] {
+                            Value::String(s) => s.to_string(),
+                            Value::Object(m) if m.is_empty() => "".to_string(),
+                            v => v.to_string(),
+                        })
+                        .collect();
+                    csv_writer.write_record(&fields)?;
+                }
+                csv_writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn to_value(shelf: &str, layer: &str, cookie: &Cookie) -> Value {
+        json!({
+            "shelf": shelf,
+            "jar": cookie.location,
+            "layer": layer,
+            "offset": cookie.offset,
+            "length": cookie.content.len(),
+            "content": cookie.content,
+            "metadata": cookie.metadata,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookie::{CookieJar, CookieShelf};
+
+    fn get_cabinet() -> CookieCabinet {
+        let mut cabinet = CookieCabinet::default();
+        cabinet.push(CookieShelf::new("valley", 0.0));
+        let mut jar = CookieJar::from_text(
+            "apple\n%\n%% author: Alice\nbanana, \"yellow\"\n%\n",
+            "fruits",
+            '%',
+        )
+        .unwrap();
+        jar.parse_comments();
+        cabinet.shelves[0].jars.push(jar);
+        cabinet
+    }
+
+    fn export(format: ExportFormat) -> String {
+        let mut output: Vec<u8> = Vec::new();
+        Exporter::export(&get_cabinet(), format, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_export_format_from_str() {
+        assert_eq!(ExportFormat::Json, "json".parse().unwrap());
+        assert_eq!(ExportFormat::Ndjson, "ndjson".parse().unwrap());
+        assert_eq!(ExportFormat::Csv, "csv".parse().unwrap());
+        assert!("xml".parse::<ExportFormat>().is_err());
+    }
+
+    #[test]
+    fn test_export() {
+        let output = export(ExportFormat::Json);
+        let values: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(
+            json!([
+                {"shelf": "valley", "jar": "fruits", "layer": "", "offset": 0, "length": 5, "content": "apple", "metadata": {}},
+                {"shelf": "valley", "jar": "fruits", "layer": "", "offset": 8, "length": 16, "content": "banana, \"yellow\"", "metadata": {"author": "Alice"}},
+            ]),
+            values
+        );
+
+        let output = export(ExportFormat::Ndjson);
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(values.as_array().unwrap(), &lines);
+
+        let output = export(ExportFormat::Csv);
+        assert_eq!(
+            "shelf,jar,layer,offset,length,content,metadata\n\
+             valley,fruits,,0,5,apple,\n\
+             valley,fruits,,8,16,\"banana, \"\"yellow\"\"\",\"{\"\"author\"\":\"\"Alice\"\"}\"\n",
+            output
+        );
+    }
+}
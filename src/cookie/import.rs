@@ -0,0 +1,451 @@
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use serde_json::Value;
+
+use crate::cookie::{Cookie, CookieJar, FLAGS_COMMENTS};
+
+/// Input formats which can be imported as fortune cookies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Text,         // '%' delimited fortune file
+    Json,         // array of strings or objects
+    Csv,          // table with a header row
+    Yaml,         // sequence of strings or mappings
+    MarkdownList, // '-', '*', '+' or '1.' list items
+    Lines,        // one cookie per line
+}
+
+impl FromStr for InputFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(InputFormat::Text),
+            "json" => Ok(InputFormat::Json),
+            "csv" => Ok(InputFormat::Csv),
+            "yaml" | "yml" => Ok(InputFormat::Yaml),
+            "markdown-list" | "markdown" | "md" => Ok(InputFormat::MarkdownList),
+            "lines" => Ok(InputFormat::Lines),
+            _ => anyhow::bail!(
+                "Unknown input format: {} (expected one of: text, json, csv, yaml, markdown-list, lines)",
+                s
+            ),
+        }
+    }
+}
+
+/// Fields of a record which are mapped to a cookie.
+/// Only used by the structured formats (JSON, CSV and YAML).
+#[derive(Debug, Clone)]
+pub struct FieldMapping {
+    pub text: String,           // Field of the cookie text
+    pub author: Option<String>, // Field of the author, written as '-- author' and metadata
+    pub tags: Option<String>,   // Field of the tags, written as metadata
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        Self {
+            text: "text".to_string(),
+            author: None,
+            tags: None,
+        }
+    }
+}
+
+/// A record imported from the input, before it is converted to a cookie.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Record {
+    text: String,
+    author: Option<String>,
+    tags: Vec<String>,
+}
+
+pub struct Importer;
+
+impl Importer {
+    /// Import the content in the given format as a jar.
+    /// The cookies are normalized, so the jar can be written by `CookieJar::to_text()`.
+    pub fn import(
+        content: &str,
+        format: InputFormat,
+        mapping: &FieldMapping,
+        location: &str,
+        delim: char,
+    ) -> Result<CookieJar> {
+        if format == InputFormat::Text {
+            return CookieJar::from_text(content, location, delim);
+        }
+
+        let records = match format {
+            InputFormat::Json => {
+                let value: Value = serde_json::from_str(content)?;
+                Importer::from_value(&value, mapping)?
+            }
+            InputFormat::Yaml => {
+                let value: Value = serde_yaml::from_str(content)?;
+                Importer::from_value(&value, mapping)?
+            }
+            InputFormat::Csv => Importer::from_csv(content, mapping)?,
+            InputFormat::MarkdownList => Importer::from_markdown_list(content),
+            InputFormat::Lines => Importer::from_lines(content),
+            InputFormat::Text => unreachable!(),
+        };
+
+        // convert records to cookies
+        let mut jar = CookieJar::from_text("", location, delim)?;
+        let delim_line = delim.to_string();
+        for (i, record) in records.into_iter().enumerate() {
+            let mut content = normalize_text(&record.text);
+            if content.is_empty() {
+                continue;
+            }
+            if content.lines().any(|l| l == delim_line) {
+                anyhow::bail!(
+                    "Record {} contains a delimiter line '{}': {:?}",
+                    i + 1,
+                    delim,
+                    record.text
+                );
+            }
+            let mut metadata = BTreeMap::new();
+            if let Some(author) = record.author.filter(|a| !a.is_empty()) {
+                content = format!("{}\n\t\t-- {}", content, author);
+                metadata.insert("author".to_string(), author);
+            }
+            if !record.tags.is_empty() {
+                metadata.insert("tags".to_string(), record.tags.join(", "));
+            }
+            if !metadata.is_empty() {
+                jar.flags |= FLAGS_COMMENTS;
+            }
+            jar.cookies.push(Cookie {
+                location: jar.location.clone(),
+                content,
+                offset: 0,
+                metadata,
+            });
+        }
+
+        // re-parse the normalized text, so the offsets and lengths match the text file
+        let mut normalized = CookieJar::from_text(&jar.to_text(), location, delim)?;
+        if jar.flags & FLAGS_COMMENTS != 0 {
+            normalized.parse_comments();
+        }
+        normalized.flags = jar.flags;
+        Ok(normalized)
+    }
+
+    /// Import records from a JSON/YAML value, which is an array of strings or objects.
+    fn from_value(value: &Value, mapping: &FieldMapping) -> Result<Vec<Record>> {
+        let items = match value {
+            Value::Array(items) => items,
+            _ => anyhow::bail!("Expected an array of records"),
+        };
+        let mut records = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            let record =
+                match item {
+                    Value::String(text) => Record {
+                        text: text.to_string(),
+                        ..Default::default()
+                    },
+                    Value::Object(fields) => {
+                        let text = fields.get(&mapping.text).and_then(value_to_string).ok_or(
+                            Error::msg(format!(
+                                "Record {} has no text field '{}'",
+                                i + 1,
+                                mapping.text
+                            )),
+                        )?;
+                        Record {
+                            text,
+                            author: mapping
+                                .author
+                                .as_ref()
+                                .and_then(|f| fields.get(f))
+                                .and_then(value_to_string),
+                            tags: mapping
+                                .tags
+                                .as_ref()
+                                .and_then(|f| fields.get(f))
+                                .map(value_to_tags)
+                                .unwrap_or_default(),
+                        }
+                    }
+                    _ => anyhow::bail!("Record {} is neither a string nor an object", i + 1),
+                };
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Import records from a CSV table; the first row is the header.
+    fn from_csv(content: &str, mapping: &FieldMapping) -> Result<Vec<Record>> {
+        let mut reader = csv::Reader::from_reader(content.as_bytes());
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| headers.iter().position(|h| h.trim() == name);
+        let text_column = column(&mapping.text).ok_or(Error::msg(format!(
+            "No text column '{}' in the header: {:?}",
+            mapping.text,
+            headers.iter().collect::<Vec<&str>>()
+        )))?;
+        let author_column = match &mapping.author {
+            Some(name) => {
+                Some(column(name).ok_or(Error::msg(format!("No author column '{}'", name)))?)
+            }
+            None => None,
+        };
+        let tags_column = match &mapping.tags {
+            Some(name) => {
+                Some(column(name).ok_or(Error::msg(format!("No tag column '{}'", name)))?)
+            }
+            None => None,
+        };
+
+        let mut records = Vec::new();
+        for row in reader.records() {
+            let row = row?;
+            let field = |c: Option<usize>| c.and_then(|c| row.get(c)).map(|v| v.trim().to_string());
+            records.push(Record {
+                text: field(Some(text_column)).unwrap_or_default(),
+                author: field(author_column),
+                tags: field(tags_column)
+                    .map(|t| split_tags(&t))
+                    .unwrap_or_default(),
+            });
+        }
+        Ok(records)
+    }
+
+    /// Import records from the items of a Markdown list.
+    /// The indented lines following an item are its continuation lines.
+    fn from_markdown_list(content: &str) -> Vec<Record> {
+        let mut records: Vec<Record> = Vec::new();
+        let mut in_item = false;
+        for line in content.lines() {
+            if let Some(text) = strip_list_marker(line) {
+                records.push(Record {
+                    text: text.to_string(),
+                    ..Default::default()
+                });
+                in_item = true;
+            } else if in_item && line.starts_with([' ', '\t']) && !line.trim().is_empty() {
+                let record = records.last_mut().unwrap();
+                record.text.push('\n');
+                record.text.push_str(line.trim());
+            } else {
+                in_item = false;
+            }
+        }
+        records
+    }
+
+    /// Import records from the lines, one cookie per non-empty line.
+    fn from_lines(content: &str) -> Vec<Record> {
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| Record {
+                text: line.to_string(),
+                ..Default::default()
+            })
+            .collect()
+    }
+}
+
+/// Normalize the text of a cookie: unify newlines, remove trailing spaces and blank lines.
+fn normalize_text(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .replace('\r', "\n")
+        .lines()
+        .map(|line| line.trim_end())
+        .collect::<Vec<&str>>()
+        .join("\n")
+        .trim_matches('\n')
+        .to_string()
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.trim().to_string()),
+        v => Some(v.to_string()),
+    }
+}
+
+fn value_to_tags(value: &Value) -> Vec<String> {
+    match value {
+        Value::Array(items) => items.iter().filter_map(value_to_string).collect(),
+        v => value_to_string(v)
+            .map(|t| split_tags(&t))
+            .unwrap_or_default(),
+    }
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.split([',', ';'])
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// Strip the Markdown list marker ('-', '*', '+' or '1.') from the line, if any.
+fn strip_list_marker(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    if let Some(text) = line
+        .strip_prefix("- ")
+        .or_else(|| line.strip_prefix("* "))
+        .or_else(|| line.strip_prefix("+ "))
+    {
+        return Some(text.trim());
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    if digits > 0 {
+        if let Some(text) = line[digits..]
+            .strip_prefix(". ")
+            .or_else(|| line[digits..].strip_prefix(") "))
+        {
+            return Some(text.trim());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contents(jar: &CookieJar) -> Vec<&str> {
+        jar.iter().map(|c| c.content.as_str()).collect()
+    }
+
+    #[test]
+    fn test_input_format_from_str() {
+        let testcases = [
+            ("text", InputFormat::Text),
+            ("json", InputFormat::Json),
+            ("csv", InputFormat::Csv),
+            ("yaml", InputFormat::Yaml),
+            ("markdown-list", InputFormat::MarkdownList),
+            ("lines", InputFormat::Lines),
+        ];
+        for (name, expected) in testcases.iter() {
+            assert_eq!(*expected, name.parse::<InputFormat>().unwrap());
+        }
+        assert!("xml".parse::<InputFormat>().is_err());
+    }
+
+    #[test]
+    fn test_import() {
+        let mapping = FieldMapping {
+            text: "quote".to_string(),
+            author: Some("author".to_string()),
+            tags: Some("tags".to_string()),
+        };
+        let expected_text = "Apples are red.\n%\n%% author: Alice\n%% tags: fruit, red\nCherries are red too.\n\t\t-- Alice\n%\n";
+        let testcases = [
+            (
+                "should import JSON array of objects",
+                InputFormat::Json,
+                r#"[{"quote": "Apples are red."}, {"quote": "Cherries are red too.", "author": "Alice", "tags": ["fruit", "red"]}]"#,
+            ),
+            (
+                "should import CSV with header",
+                InputFormat::Csv,
+                "quote,author,tags\nApples are red.,,\nCherries are red too.,Alice,\"fruit, red\"\n",
+            ),
+            (
+                "should import YAML sequence of mappings",
+                InputFormat::Yaml,
+                "- quote: Apples are red.\n- quote: Cherries are red too.\n  author: Alice\n  tags: [fruit, red]\n",
+            ),
+        ];
+
+        for (msg, format, content) in testcases.iter() {
+            let jar = Importer::import(content, *format, &mapping, "valley", '%').unwrap();
+            assert_eq!(expected_text, jar.to_text(), "{}", msg);
+            assert_eq!(FLAGS_COMMENTS, jar.flags, "{}", msg);
+            assert_eq!(
+                vec![0, 18],
+                jar.iter().map(|c| c.offset).collect::<Vec<u64>>(),
+                "{}",
+                msg
+            );
+        }
+    }
+
+    #[test]
+    fn test_import_strings() {
+        let mapping = FieldMapping::default();
+        let testcases = [
+            (
+                "should import JSON array of strings",
+                InputFormat::Json,
+                r#"["apple", "banana\r\nsplit  ", ""]"#,
+                vec!["apple", "banana\nsplit"],
+            ),
+            (
+                "should import Markdown list",
+                InputFormat::MarkdownList,
+                "# Fruits\n\n- apple\n* banana\n  split\n1. cherry\n\nNot an item.\n",
+                vec!["apple", "banana\nsplit", "cherry"],
+            ),
+            (
+                "should import lines",
+                InputFormat::Lines,
+                "apple\n\nbanana\ncherry\n",
+                vec!["apple", "banana", "cherry"],
+            ),
+            (
+                "should import text",
+                InputFormat::Text,
+                "apple\n%\nbanana\n%\n",
+                vec!["apple", "banana"],
+            ),
+        ];
+
+        for (msg, format, content, expected) in testcases.iter() {
+            let jar = Importer::import(content, *format, &mapping, "valley", '%').unwrap();
+            assert_eq!(*expected, contents(&jar), "{}", msg);
+            assert_eq!(0, jar.flags, "{}", msg);
+        }
+    }
+
+    #[test]
+    fn test_import_errors() {
+        let mapping = FieldMapping::default();
+        let testcases = [
+            (
+                "should reject non-array JSON",
+                InputFormat::Json,
+                r#"{"text": "apple"}"#,
+            ),
+            (
+                "should reject missing text field",
+                InputFormat::Json,
+                r#"[{"quote": "apple"}]"#,
+            ),
+            (
+                "should reject missing text column",
+                InputFormat::Csv,
+                "quote\napple\n",
+            ),
+            (
+                "should reject delimiter line",
+                InputFormat::Json,
+                r#"["apple\n%\nbanana"]"#,
+            ),
+        ];
+
+        for (msg, format, content) in testcases.iter() {
+            assert!(
+                Importer::import(content, *format, &mapping, "valley", '%').is_err(),
+                "{}",
+                msg
+            );
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::path::{Path, PathBuf};
+
+/// Name of the directory under each data directory which holds the fortune packs,
+/// e.g. `~/.local/share/fortune-rs/en/`.
+pub const DATA_DIR_NAME: &str = "fortune-rs";
+
+/// Layer name of the jars loaded from the embedded data.
+pub const EMBED_LAYER: &str = "embed";
+
+const DEFAULT_DATA_HOME: &str = ".local/share";
+const DEFAULT_DATA_DIRS: &str = "/usr/local/share:/usr/share";
+
+/// Return the data directories which are overlaid on top of the embedded packs,
+/// from the highest priority to the lowest.
+///
+/// The user data directory (`$XDG_DATA_HOME`, default `~/.local/share`) comes first,
+/// then the system data directories (`$XDG_DATA_DIRS`, default `/usr/local/share:/usr/share`).
+pub fn data_dirs() -> Vec<PathBuf> {
+    data_dirs_from(
+        std::env::var("XDG_DATA_HOME").ok(),
+        std::env::var("HOME")
+            .or_else(|_| std::env::var("USERPROFILE"))
+            .ok(),
+        std::env::var("XDG_DATA_DIRS").ok(),
+    )
+}
+
+/// Check whether the given pack path exists in any of the data directories.
+pub fn exists(path: &str) -> bool {
+    data_dirs().iter().any(|dir| dir.join(path).exists())
+}
+
+fn data_dirs_from(
+    data_home: Option<String>,
+    home: Option<String>,
+    data_dirs: Option<String>,
+) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+
+    // empty values are treated as unset, as the XDG spec says
+    let data_home = data_home
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            home.filter(|h| !h.is_empty())
+                .map(|h| Path::new(&h).join(DEFAULT_DATA_HOME))
+        });
+    if let Some(data_home) = data_home {
+        dirs.push(data_home.join(DATA_DIR_NAME));
+    }
+
+    let data_dirs = data_dirs
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| DEFAULT_DATA_DIRS.to_string());
+    for dir in std::env::split_paths(&data_dirs) {
+        if dir.as_os_str().is_empty() {
+            continue;
+        }
+        let dir = dir.join(DATA_DIR_NAME);
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn test_data_dirs_from() {
+        let testcases = [
+            (
+                "should use XDG_DATA_HOME and XDG_DATA_DIRS",
+                Some("/data/home"),
+                Some("/home/user"),
+                Some("/data/a:/data/b"),
+                vec![
+                    "/data/home/fortune-rs",
+                    "/data/a/fortune-rs",
+                    "/data/b/fortune-rs",
+                ],
+            ),
+            (
+                "should fallback to ~/.local/share and default system directories",
+                None,
+                Some("/home/user"),
+                None,
+                vec![
+                    "/home/user/.local/share/fortune-rs",
+                    "/usr/local/share/fortune-rs",
+                    "/usr/share/fortune-rs",
+                ],
+            ),
+            (
+                "should treat empty values as unset",
+                Some(""),
+                Some("/home/user"),
+                Some(""),
+                vec![
+                    "/home/user/.local/share/fortune-rs",
+                    "/usr/local/share/fortune-rs",
+                    "/usr/share/fortune-rs",
+                ],
+            ),
+            (
+                "should skip user data directory if HOME is unknown",
+                None,
+                None,
+                Some("/data/a::/data/a"),
+                vec!["/data/a/fortune-rs"],
+            ),
+        ];
+
+        for (msg, data_home, home, data_dirs, expected) in testcases.iter() {
+            let dirs = data_dirs_from(
+                data_home.map(String::from),
+                home.map(String::from),
+                data_dirs.map(String::from),
+            );
+            let expected: Vec<PathBuf> = expected.iter().map(PathBuf::from).collect();
+            assert_eq!(expected, dirs, "{}", msg);
+        }
+    }
+}
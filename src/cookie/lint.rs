@@ -0,0 +1,421 @@
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+
+use crate::cookie::CookieJar;
+
+/// Checks of the cookie file hygiene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Check {
+    InvalidUtf8,         // bytes which are not valid UTF-8
+    MixedLineEndings,    // both CRLF and LF line endings in the same file
+    DelimiterWhitespace, // '% ' is not a delimiter line, so the cookies around it are merged
+    EmptyCookie,         // a cookie without content, which is dropped by the parser
+    MissingDelimiter,    // the last cookie is not followed by a delimiter line
+    MissingNewline,      // the file does not end with a newline
+    Tab,                 // tab characters
+    ControlChar,         // control characters other than tab and newline
+    LongLine,            // lines longer than the maximum length
+}
+
+impl Check {
+    pub const ALL: [Check; 9] = [
+        Check::InvalidUtf8,
+        Check::MixedLineEndings,
+        Check::DelimiterWhitespace,
+        Check::EmptyCookie,
+        Check::MissingDelimiter,
+        Check::MissingNewline,
+        Check::Tab,
+        Check::ControlChar,
+        Check::LongLine,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Check::InvalidUtf8 => "invalid-utf8",
+            Check::MixedLineEndings => "mixed-line-endings",
+            Check::DelimiterWhitespace => "delimiter-whitespace",
+            Check::EmptyCookie => "empty-cookie",
+            Check::MissingDelimiter => "missing-delimiter",
+            Check::MissingNewline => "missing-newline",
+            Check::Tab => "tab",
+            Check::ControlChar => "control-char",
+            Check::LongLine => "long-line",
+        }
+    }
+
+    /// Whether the problem can be fixed without changing the content of any cookie.
+    pub fn is_fixable(&self) -> bool {
+        matches!(
+            self,
+            Check::MixedLineEndings
+                | Check::DelimiterWhitespace
+                | Check::EmptyCookie
+                | Check::MissingDelimiter
+                | Check::MissingNewline
+        )
+    }
+}
+
+impl FromStr for Check {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Check::ALL
+            .iter()
+            .find(|c| c.name() == s)
+            .copied()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Unknown check: {} (expected one of: {})",
+                    s,
+                    Check::ALL.map(|c| c.name()).join(", ")
+                )
+            })
+    }
+}
+
+/// A problem found in a cookie file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub line: usize, // 1-based line number
+    pub check: Check,
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {}", self.check.name(), self.message)?;
+        if self.check.is_fixable() {
+            write!(f, " (fixable)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Linter of the cookie files.
+#[derive(Debug, Clone)]
+pub struct Linter {
+    pub delim: char,
+    pub max_line_length: usize, // in characters
+    pub allowed: Vec<Check>,    // checks which are not reported
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self {
+            delim: '%',
+            max_line_length: 80,
+            allowed: Vec::new(),
+        }
+    }
+}
+
+impl Linter {
+    /// Find the problems in the content of a cookie file, sorted by line.
+    pub fn lint(&self, bytes: &[u8]) -> Vec<Problem> {
+        let mut problems: Vec<Problem> = Vec::new();
+
+        let content = match std::str::from_utf8(bytes) {
+            Ok(content) => content.to_string(),
+            Err(e) => {
+                let line = bytes[..e.valid_up_to()]
+                    .iter()
+                    .filter(|b| **b == b'\n')
+                    .count()
+                    + 1;
+                problems.push(Problem {
+                    line,
+                    check: Check::InvalidUtf8,
+                    message: format!("invalid UTF-8 at byte {}", e.valid_up_to()),
+                });
+                String::from_utf8_lossy(bytes).to_string()
+            }
+        };
+
+        let lines: Vec<&str> = content.split('\n').collect();
+        // the last element is empty if the file ends with a newline
+        let num_lines = if content.ends_with('\n') {
+            lines.len() - 1
+        } else {
+            lines.len()
+        };
+
+        // line endings
+        let num_crlf = lines[..num_lines]
+            .iter()
+            .filter(|l| l.ends_with('\r'))
+            .count();
+        if num_crlf > 0 && num_crlf < num_lines {
+            let minority_is_crlf = num_crlf * 2 <= num_lines;
+            let line = lines[..num_lines]
+                .iter()
+                .position(|l| l.ends_with('\r') == minority_is_crlf)
+                .unwrap_or(0)
+                + 1;
+            problems.push(Problem {
+                line,
+                check: Check::MixedLineEndings,
+                message: format!(
+                    "{} CRLF and {} LF line endings",
+                    num_crlf,
+                    num_lines - num_crlf
+                ),
+            });
+        }
+
+        let mut has_content = false;
+        for (i, line) in lines[..num_lines].iter().enumerate() {
+            let line = line.trim_end_matches('\r');
+            let number = i + 1;
+            if self.is_delimiter(line) {
+                if line.len() != self.delim.len_utf8() {
+                    problems.push(Problem {
+                        line: number,
+                        check: Check::DelimiterWhitespace,
+                        message: "delimiter line with trailing whitespace".to_string(),
+                    });
+                }
+                if !has_content {
+                    problems.push(Problem {
+                        line: number,
+                        check: Check::EmptyCookie,
+                        message: "empty cookie before this delimiter".to_string(),
+                    });
+                }
+                has_content = false;
+                continue;
+            }
+            // comments are not shown, so only the control characters matter
+            let is_comment = self.is_comment(line);
+            if !is_comment && !line.trim().is_empty() {
+                has_content = true;
+            }
+
+            if !is_comment && line.contains('\t') {
+                problems.push(Problem {
+                    line: number,
+                    check: Check::Tab,
+                    message: "tab character".to_string(),
+                });
+            }
+            if let Some(c) = line.chars().find(|c| c.is_control() && *c != '\t') {
+                problems.push(Problem {
+                    line: number,
+                    check: Check::ControlChar,
+                    message: format!("control character {:?}", c),
+                });
+            }
+            let length = line.chars().count();
+            if !is_comment && length > self.max_line_length {
+                problems.push(Problem {
+                    line: number,
+                    check: Check::LongLine,
+                    message: format!(
+                        "line is {} characters long (max: {})",
+                        length, self.max_line_length
+                    ),
+                });
+            }
+        }
+
+        if has_content {
+            problems.push(Problem {
+                line: num_lines,
+                check: Check::MissingDelimiter,
+                message: "the last cookie is not followed by a delimiter".to_string(),
+            });
+        }
+        if !content.is_empty() && !content.ends_with('\n') {
+            problems.push(Problem {
+                line: num_lines,
+                check: Check::MissingNewline,
+                message: "no newline at the end of the file".to_string(),
+            });
+        }
+
+        problems.retain(|p| !self.allowed.contains(&p.check));
+        problems.sort_by_key(|p| (p.line, p.check));
+        problems
+    }
+
+    /// Fix the safe problems of the content, without changing the content of any cookie.
+    pub fn fix(&self, content: &str) -> Result<String> {
+        let content = content.replace("\r\n", "\n");
+        let mut fixed: Vec<String> = Vec::new();
+        // lines of the current cookie, which are dropped if the cookie is empty
+        let mut cookie: Vec<&str> = Vec::new();
+        let mut num_cookies = 0;
+        for line in content.lines() {
+            if self.is_delimiter(line) {
+                if cookie
+                    .iter()
+                    .any(|l| !self.is_comment(l) && !l.trim().is_empty())
+                {
+                    fixed.extend(cookie.iter().map(|l| l.to_string()));
+                    fixed.push(self.delim.to_string());
+                    num_cookies += 1;
+                }
+                cookie.clear();
+            } else {
+                cookie.push(line);
+            }
+        }
+        if cookie
+            .iter()
+            .any(|l| !self.is_comment(l) && !l.trim().is_empty())
+        {
+            fixed.extend(cookie.iter().map(|l| l.to_string()));
+            fixed.push(self.delim.to_string());
+            num_cookies += 1;
+        }
+        let mut fixed = fixed.join("\n");
+        if !fixed.is_empty() {
+            fixed.push('\n');
+        }
+
+        // the parser should read the same cookies as the linter sees
+        let jar = CookieJar::from_text(&fixed, "", self.delim)?;
+        if jar.cookies.len() != num_cookies {
+            anyhow::bail!(
+                "the fixed content has {} cookies, but {} expected",
+                jar.cookies.len(),
+                num_cookies
+            );
+        }
+        Ok(fixed)
+    }
+
+    fn is_delimiter(&self, line: &str) -> bool {
+        let mut chars = line.trim_end().chars();
+        chars.next() == Some(self.delim) && chars.next().is_none()
+    }
+
+    fn is_comment(&self, line: &str) -> bool {
+        let mut chars = line.chars();
+        chars.next() == Some(self.delim) && chars.next() == Some(self.delim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lint(linter: &Linter, content: &[u8]) -> Vec<(usize, Check)> {
+        linter
+            .lint(content)
+            .iter()
+            .map(|p| (p.line, p.check))
+            .collect()
+    }
+
+    #[test]
+    fn test_check_from_str() {
+        for check in Check::ALL.iter() {
+            assert_eq!(*check, check.name().parse().unwrap());
+        }
+        assert!("typo".parse::<Check>().is_err());
+    }
+
+    #[test]
+    fn test_linter_lint() {
+        let linter = Linter {
+            max_line_length: 10,
+            ..Default::default()
+        };
+        type TestCase<'a> = (&'a str, &'a [u8], Vec<(usize, Check)>);
+        let testcases: [TestCase; 10] = [
+            (
+                "should accept a clean file",
+                b"apple\n%\nbanana\n%\n",
+                vec![],
+            ),
+            (
+                "should accept a comment in a cookie",
+                b"%% author: A\napple\n%\n",
+                vec![],
+            ),
+            (
+                "should find the missing final delimiter",
+                b"apple\n%\nbanana\n",
+                vec![(3, Check::MissingDelimiter)],
+            ),
+            (
+                "should find the missing final newline",
+                b"apple\n%",
+                vec![(2, Check::MissingNewline)],
+            ),
+            (
+                "should find the delimiter with trailing spaces",
+                b"apple\n% \nbanana\n%\n",
+                vec![(2, Check::DelimiterWhitespace)],
+            ),
+            (
+                "should find the mixed line endings",
+                b"apple\r\n%\r\nbanana\n%\r\n",
+                vec![(3, Check::MixedLineEndings)],
+            ),
+            (
+                "should find the empty cookies",
+                b"%\napple\n%\n\n%\n%% tag: a\n%\n",
+                vec![
+                    (1, Check::EmptyCookie),
+                    (5, Check::EmptyCookie),
+                    (7, Check::EmptyCookie),
+                ],
+            ),
+            (
+                "should find the tabs, control characters and long lines",
+                b"\t-- A\nb\x07ll\n0123456789X\n%\n",
+                vec![
+                    (1, Check::Tab),
+                    (2, Check::ControlChar),
+                    (3, Check::LongLine),
+                ],
+            ),
+            (
+                "should find the invalid UTF-8",
+                b"apple\n%\nban\xffana\n%\n",
+                vec![(3, Check::InvalidUtf8)],
+            ),
+            (
+                "should count the characters of long lines",
+                "學而時習之，不亦說乎\n%\n".as_bytes(),
+                vec![],
+            ),
+        ];
+        for (msg, content, expected) in testcases.iter() {
+            assert_eq!(*expected, lint(&linter, content), "{}", msg);
+        }
+
+        let linter = Linter {
+            allowed: vec![Check::Tab],
+            ..Default::default()
+        };
+        assert!(linter.lint(b"\t-- A\n%\n").is_empty());
+    }
+
+    #[test]
+    fn test_linter_fix() {
+        let linter = Linter::default();
+        let testcases = [
+            ("apple\n%\nbanana\n%\n", "apple\n%\nbanana\n%\n"),
+            ("apple\n%\nbanana", "apple\n%\nbanana\n%\n"),
+            ("apple\r\n%\nbanana\r\n%\n", "apple\n%\nbanana\n%\n"),
+            ("apple\n%  \nbanana\n%\n", "apple\n%\nbanana\n%\n"),
+            ("%\napple\n%\n\n%\n%% tag: a\n%\n", "apple\n%\n"),
+            ("%% tag: a\n\tapple\n%\n", "%% tag: a\n\tapple\n%\n"),
+            ("", ""),
+        ];
+        for (content, expected) in testcases.iter() {
+            let fixed = linter.fix(content).unwrap();
+            assert_eq!(*expected, fixed, "{:?}", content);
+            // only the unfixable problems should be left
+            assert!(linter
+                .lint(fixed.as_bytes())
+                .iter()
+                .all(|p| !p.check.is_fixable()));
+        }
+    }
+}
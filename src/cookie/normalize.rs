@@ -0,0 +1,61 @@
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use unicode_normalization::UnicodeNormalization;
+
+/// Unicode normalization forms, applied to both the patterns and the cookies when matching.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    Nfc,  // canonical composition, e.g. 'e' + U+0301 => 'é'
+    Nfkc, // compatibility composition as well, e.g. 'ﬁ' => 'fi', full-width 'Ａ' => 'A'
+}
+
+impl FromStr for Normalization {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "nfc" => Ok(Normalization::Nfc),
+            "nfkc" => Ok(Normalization::Nfkc),
+            _ => anyhow::bail!(
+                "Unknown normalization form: {} (expected one of: nfc, nfkc)",
+                s
+            ),
+        }
+    }
+}
+
+impl Normalization {
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            Normalization::Nfc => text.nfc().collect(),
+            Normalization::Nfkc => text.nfkc().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalization_from_str() {
+        assert_eq!(Normalization::Nfc, "nfc".parse().unwrap());
+        assert_eq!(Normalization::Nfkc, "nfkc".parse().unwrap());
+        assert!("nfd".parse::<Normalization>().is_err());
+    }
+
+    #[test]
+    fn test_normalization_apply() {
+        let testcases = [
+            (Normalization::Nfc, "cafe\u{301}", "café"),
+            (Normalization::Nfc, "\u{fb01}le", "\u{fb01}le"),
+            (Normalization::Nfkc, "\u{fb01}le", "file"),
+            (Normalization::Nfkc, "ＡＢＣ１２３", "ABC123"),
+            (Normalization::Nfkc, "學而時習之", "學而時習之"),
+        ];
+        for (form, text, expected) in testcases.iter() {
+            assert_eq!(*expected, form.apply(text), "{:?}: {}", form, text);
+        }
+    }
+}
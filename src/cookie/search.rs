@@ -0,0 +1,474 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{Context, Result};
+use log::debug;
+use regex_syntax::ast::{Ast, RepetitionKind, RepetitionRange};
+
+use crate::cookie::{atomic, compress, CookieJar};
+
+/// Suffix of the search index file, which is next to the cookie file, e.g. `fortunes.idx`.
+pub const INDEX_SUFFIX: &str = ".idx";
+
+const INDEX_MAGIC: &[u8; 4] = b"FIDX";
+const INDEX_VERSION: u32 = 2;
+
+/// Get the path of the search index of the given cookie file.
+pub fn index_path(file: &str) -> String {
+    format!("{}{}", compress::strip_suffix(file), INDEX_SUFFIX)
+}
+
+/// Lowercase the text character by character, the same for the index and the query.
+/// The characters which are only equal by Unicode case folding, e.g. 'ſ' and 's', are
+/// mapped to the same character as well, for the Unicode regex engine.
+fn fold(text: &str) -> Vec<char> {
+    text.chars()
+        .flat_map(char::to_lowercase)
+        .map(|c| match c {
+            'ſ' => 's',
+            'µ' => 'μ',
+            'ς' => 'σ',
+            'ϐ' => 'β',
+            'ϑ' => 'θ',
+            'ϕ' => 'φ',
+            'ϖ' => 'π',
+            'ϰ' => 'κ',
+            'ϱ' => 'ρ',
+            'ϵ' => 'ε',
+            '\u{345}' | '\u{1fbe}' => 'ι',
+            'ẛ' => 'ṡ',
+            '\u{1c80}' => 'в',
+            '\u{1c81}' => 'д',
+            '\u{1c82}' => 'о',
+            '\u{1c83}' => 'с',
+            '\u{1c84}' | '\u{1c85}' => 'т',
+            '\u{1c86}' => 'ъ',
+            '\u{1c87}' => 'ѣ',
+            '\u{1c88}' => 'ꙋ',
+            _ => c,
+        })
+        .collect()
+}
+
+/// Trigrams (3 consecutive characters) of the folded text.
+fn trigrams(chars: &[char]) -> impl Iterator<Item = String> + '_ {
+    chars.windows(3).map(|w| w.iter().collect())
+}
+
+/// Trigrams which a cookie must contain to match a pattern.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Query {
+    #[default]
+    All, // any cookie may match
+    Trigram(String),
+    And(Vec<Query>),
+    Or(Vec<Query>),
+}
+
+impl Query {
+    /// Build the query from a `-m` pattern. The literals are folded, so the same query
+    /// works with or without `-i`. An invalid pattern matches all cookies, and is left
+    /// for the regex to report.
+    pub fn from_pattern(pattern: &str) -> Query {
+        match regex_syntax::ast::parse::Parser::new().parse(pattern) {
+            Ok(ast) => Query::from_ast(&ast),
+            Err(_) => Query::All,
+        }
+    }
+
+    fn from_ast(ast: &Ast) -> Query {
+        match ast {
+            Ast::Literal(literal) => Query::from_literal(&fold(&literal.c.to_string())),
+            Ast::Group(group) => Query::from_ast(&group.ast),
+            Ast::Repetition(repetition) => {
+                let min = match &repetition.op.kind {
+                    RepetitionKind::ZeroOrOne | RepetitionKind::ZeroOrMore => 0,
+                    RepetitionKind::OneOrMore => 1,
+                    RepetitionKind::Range(RepetitionRange::Exactly(m))
+                    | RepetitionKind::Range(RepetitionRange::AtLeast(m))
+                    | RepetitionKind::Range(RepetitionRange::Bounded(m, _)) => *m,
+                };
+                if min > 0 {
+                    Query::from_ast(&repetition.ast)
+                } else {
+                    Query::All
+                }
+            }
+            Ast::Alternation(alternation) => {
+                Query::or(alternation.asts.iter().map(Query::from_ast).collect())
+            }
+            Ast::Concat(concat) => {
+                // consecutive literals are joined, so their trigrams span the characters
+                let mut queries = Vec::new();
+                let mut literal: Vec<char> = Vec::new();
+                for ast in concat.asts.iter() {
+                    if let Ast::Literal(l) = ast {
+                        literal.extend(fold(&l.c.to_string()));
+                        continue;
+                    }
+                    queries.push(Query::from_literal(&literal));
+                    literal.clear();
+                    queries.push(Query::from_ast(ast));
+                }
+                queries.push(Query::from_literal(&literal));
+                Query::and(queries)
+            }
+            _ => Query::All,
+        }
+    }
+
+    fn from_literal(chars: &[char]) -> Query {
+        Query::and(trigrams(chars).map(Query::Trigram).collect())
+    }
+
+    /// All the queries must match.
+    pub fn and(queries: Vec<Query>) -> Query {
+        let mut queries: Vec<Query> = queries.into_iter().filter(|q| *q != Query::All).collect();
+        match queries.len() {
+            0 => Query::All,
+            1 => queries.remove(0),
+            _ => Query::And(queries),
+        }
+    }
+
+    /// Any of the queries may match.
+    pub fn or(mut queries: Vec<Query>) -> Query {
+        if queries.is_empty() || queries.contains(&Query::All) {
+            Query::All
+        } else if queries.len() == 1 {
+            queries.remove(0)
+        } else {
+            Query::Or(queries)
+        }
+    }
+}
+
+/// Inverted trigram index of the cookies in a cookie file.
+///
+/// The index is written next to the cookie file by `strfile --index`, and records the size
+/// and modification time of the cookie file, so a stale index is never used.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchIndex {
+    pub num_cookies: usize,
+    postings: BTreeMap<String, Vec<u8>>, // Delta-encoded varint positions of the cookies
+}
+
+impl SearchIndex {
+    pub fn from_jar(jar: &CookieJar) -> SearchIndex {
+        let mut positions: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+        for (i, cookie) in jar.iter().enumerate() {
+            let chars = fold(&cookie.content);
+            for trigram in trigrams(&chars).collect::<BTreeSet<String>>() {
+                positions.entry(trigram).or_default().push(i as u32);
+            }
+        }
+        SearchIndex {
+            num_cookies: jar.num_of_cookies(),
+            postings: positions
+                .into_iter()
+                .map(|(trigram, positions)| (trigram, encode_positions(&positions)))
+                .collect(),
+        }
+    }
+
+    /// Get the positions of the cookies which may match the query, or `None` for all cookies.
+    pub fn candidates(&self, query: &Query) -> Option<Vec<u32>> {
+        match query {
+            Query::All => None,
+            Query::Trigram(trigram) => Some(
+                self.postings
+                    .get(trigram)
+                    .and_then(|p| decode_positions(p))
+                    .unwrap_or_default(),
+            ),
+            Query::And(queries) => {
+                queries
+                    .iter()
+                    .filter_map(|q| self.candidates(q))
+                    .reduce(|a, b| {
+                        let b: BTreeSet<u32> = b.into_iter().collect();
+                        a.into_iter().filter(|i| b.contains(i)).collect()
+                    })
+            }
+            Query::Or(queries) => {
+                let mut union: BTreeSet<u32> = BTreeSet::new();
+                for q in queries.iter() {
+                    union.extend(self.candidates(q)?);
+                }
+                Some(union.into_iter().collect())
+            }
+        }
+    }
+
+    /// Write the index of the given cookie file next to it.
+    pub fn write(&self, file: &str) -> Result<String> {
+        let (size, mtime) = stamp(file)?;
+        let mut bytes: Vec<u8> = Vec::new();
+        bytes.extend_from_slice(INDEX_MAGIC);
+        bytes.extend_from_slice(&INDEX_VERSION.to_be_bytes());
+        bytes.extend_from_slice(&size.to_be_bytes());
+        bytes.extend_from_slice(&mtime.to_be_bytes());
+        bytes.extend_from_slice(&(self.num_cookies as u32).to_be_bytes());
+        bytes.extend_from_slice(&(self.postings.len() as u32).to_be_bytes());
+        for (trigram, positions) in self.postings.iter() {
+            bytes.push(trigram.len() as u8);
+            bytes.extend_from_slice(trigram.as_bytes());
+            bytes.extend_from_slice(&(positions.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(positions);
+        }
+        let path = index_path(file);
+        atomic::write_file(Path::new(&path), &bytes).context("Failed to write the search index")?;
+        Ok(path)
+    }
+
+    /// Read the index of the given cookie file. Return `None` if there is no index, or it
+    /// is stale or broken.
+    pub fn read(file: &str) -> Option<SearchIndex> {
+        let path = index_path(file);
+        let bytes = std::fs::read(&path).ok()?;
+        let index = SearchIndex::from_bytes(&bytes, stamp(file).ok()?);
+        if index.is_none() {
+            debug!("SearchIndex::read(): skip stale or broken index: {}", path);
+        }
+        index
+    }
+
+    fn from_bytes(bytes: &[u8], stamp: (u64, u128)) -> Option<SearchIndex> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.take(4)? != INDEX_MAGIC
+            || u32::from_be_bytes(reader.take(4)?.try_into().ok()?) != INDEX_VERSION
+        {
+            return None;
+        }
+        let size = u64::from_be_bytes(reader.take(8)?.try_into().ok()?);
+        let mtime = u128::from_be_bytes(reader.take(16)?.try_into().ok()?);
+        if (size, mtime) != stamp {
+            return None;
+        }
+        let num_cookies = u32::from_be_bytes(reader.take(4)?.try_into().ok()?) as usize;
+        let num_trigrams = u32::from_be_bytes(reader.take(4)?.try_into().ok()?);
+        let mut postings = BTreeMap::new();
+        for _ in 0..num_trigrams {
+            let len = reader.take(1)?[0] as usize;
+            let trigram = String::from_utf8(reader.take(len)?.to_vec()).ok()?;
+            let len = u32::from_be_bytes(reader.take(4)?.try_into().ok()?) as usize;
+            let positions = reader.take(len)?;
+            // a broken index could select the cookies which do not exist
+            if decode_positions(positions)?
+                .iter()
+                .any(|&i| i as usize >= num_cookies)
+            {
+                return None;
+            }
+            postings.insert(trigram, positions.to_vec());
+        }
+        Some(SearchIndex {
+            num_cookies,
+            postings,
+        })
+    }
+}
+
+/// Narrow the cookies of a jar, loaded from the given file, to the candidates of the query
+/// by its search index. The jar is unchanged if there is no usable index.
+pub fn narrow(jar: &mut CookieJar, file: &str, query: &Query) {
+    if *query == Query::All {
+        return;
+    }
+    let Some(index) = SearchIndex::read(file) else {
+        return;
+    };
+    if index.num_cookies != jar.num_of_cookies() {
+        debug!(
+            "narrow(): skip index of {} cookies: {}",
+            index.num_cookies, file
+        );
+        return;
+    }
+    let Some(candidates) = index.candidates(query) else {
+        return;
+    };
+    let mut selected = vec![false; jar.num_of_cookies()];
+    for i in candidates {
+        selected[i as usize] = true;
+    }
+    let mut i = 0;
+    jar.cookies.retain(|_| {
+        i += 1;
+        selected[i - 1]
+    });
+    debug!(
+        "narrow(): [{}] candidates: {} => {}",
+        file,
+        index.num_cookies,
+        jar.num_of_cookies()
+    );
+}
+
+/// Size and modification time (in nanoseconds) of the cookie file.
+fn stamp(file: &str) -> Result<(u64, u128)> {
+    let metadata =
+        std::fs::metadata(file).with_context(|| format!("Failed to read cookie file: {}", file))?;
+    let mtime = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+    Ok((metadata.len(), mtime))
+}
+
+fn encode_positions(positions: &[u32]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut last = 0;
+    for &position in positions.iter() {
+        let mut delta = position - last;
+        last = position;
+        loop {
+            let byte = (delta & 0x7f) as u8;
+            delta >>= 7;
+            if delta == 0 {
+                bytes.push(byte);
+                break;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+    bytes
+}
+
+/// Decode the positions, or `None` if the bytes are not the positions as encoded.
+fn decode_positions(bytes: &[u8]) -> Option<Vec<u32>> {
+    let mut positions = Vec::new();
+    let mut last: u32 = 0;
+    let mut delta: u32 = 0;
+    let mut shift = 0;
+    for &byte in bytes.iter() {
+        delta |= ((byte & 0x7f) as u32).checked_shl(shift)?;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            last = last.checked_add(delta)?;
+            positions.push(last);
+            delta = 0;
+            shift = 0;
+        }
+    }
+    (shift == 0).then_some(positions)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.bytes.get(self.pos..self.pos + len)?;
+        self.pos += len;
+        Some(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trigram(t: &str) -> Query {
+        Query::Trigram(t.to_string())
+    }
+
+    #[test]
+    fn test_query_from_pattern() {
+        let testcases = [
+            ("ab", Query::All),
+            ("abc", trigram("abc")),
+            ("ABcd", Query::And(vec![trigram("abc"), trigram("bcd")])),
+            ("a.cde", trigram("cde")),
+            ("(abc)+x?", trigram("abc")),
+            ("(abc)*", Query::All),
+            ("abc|xyz", Query::Or(vec![trigram("abc"), trigram("xyz")])),
+            ("abc|x", Query::All),
+            (
+                "abc\\s+xyz",
+                Query::And(vec![trigram("abc"), trigram("xyz")]),
+            ),
+            (
+                "學而時習",
+                Query::And(vec![trigram("學而時"), trigram("而時習")]),
+            ),
+            ("[abc", Query::All),
+        ];
+        for (pattern, expected) in testcases.iter() {
+            assert_eq!(*expected, Query::from_pattern(pattern), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_positions_encoding() {
+        let positions = vec![0, 1, 127, 128, 300, 70000];
+        assert_eq!(
+            Some(positions.clone()),
+            decode_positions(&encode_positions(&positions))
+        );
+        // an unfinished position
+        assert_eq!(None, decode_positions(&[0x01, 0x80]));
+    }
+
+    #[test]
+    fn test_search_index_candidates() {
+        let jar = CookieJar::from_text(
+            "Apple pie\n%\nbanana split\n%\npineapple\n%\ncherry\n%\n",
+            "fruits",
+            '%',
+        )
+        .unwrap();
+        let index = SearchIndex::from_jar(&jar);
+        assert_eq!(4, index.num_cookies);
+
+        let testcases = [
+            ("apple", Some(vec![0, 2])),
+            ("APPLE PIE", Some(vec![0])),
+            ("apple|split", Some(vec![0, 1, 2])),
+            ("durian", Some(vec![])),
+            ("SPLıT", Some(vec![])),
+            ("ſplit", Some(vec![1])),
+            ("e", None),
+        ];
+        for (pattern, expected) in testcases.iter() {
+            let query = Query::from_pattern(pattern);
+            assert_eq!(*expected, index.candidates(&query), "{}", pattern);
+        }
+    }
+
+    #[test]
+    fn test_search_index_read_write() {
+        let dir = std::env::temp_dir().join(format!("fortune-rs-search-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("fruits").to_string_lossy().to_string();
+        std::fs::write(&file, "apple\n%\nbanana\n%\ncherry\n%\n").unwrap();
+
+        let mut jar = CookieJar::from_text_file(&file, '%').unwrap();
+        let index = SearchIndex::from_jar(&jar);
+        assert_eq!(format!("{}.idx", file), index.write(&file).unwrap());
+        assert_eq!(Some(index.clone()), SearchIndex::read(&file));
+
+        narrow(&mut jar, &file, &Query::from_pattern("ban(an)+a"));
+        assert_eq!(
+            vec!["banana"],
+            jar.iter().map(|c| c.content.as_str()).collect::<Vec<_>>()
+        );
+
+        // a tampered index, which selects a cookie beyond the jar, is not used
+        let mut tampered = index.clone();
+        tampered
+            .postings
+            .insert("ban".to_string(), encode_positions(&[1, 7]));
+        tampered.write(&file).unwrap();
+        assert_eq!(None, SearchIndex::read(&file));
+        let mut jar = CookieJar::from_text_file(&file, '%').unwrap();
+        narrow(&mut jar, &file, &Query::from_pattern("banana"));
+        assert_eq!(3, jar.num_of_cookies());
+
+        // the index is stale after the cookie file is changed
+        std::fs::write(&file, "apple\n%\nbanana\n%\ncherry\n%\ndurian\n%\n").unwrap();
+        assert_eq!(None, SearchIndex::read(&file));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
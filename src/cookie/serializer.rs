@@ -1,3 +1,7 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+
 use crate::cookie::{Cookie, CookieJar};
 
 const VERSION_HOMEBREW: u64 = 1;
@@ -35,10 +39,11 @@ impl Serialize for SerializerHomebrew {
         // padding
         bytes.extend_from_slice(&[0; 7]);
         //  offset fields
+        let has_offsets = has_offsets(data);
         let mut offset = 0;
         for cookie in &data.cookies {
-            if cookie.offset != 0 {
-                // If the offset is already set, use it
+            if has_offsets {
+                // If the offsets are already set, use them
                 bytes.extend_from_slice(&u64_htonl_to_bytes(cookie.offset));
             } else {
                 // Otherwise, calculate the offset based on the current position
@@ -54,7 +59,9 @@ impl Serialize for SerializerHomebrew {
         let mut data = CookieJar {
             // Metadata fields
             location: "".to_string(),
+            layer: "".to_string(),
             probability: 0.0,
+            weight: 1.0,
             platform: "homebrew".to_string(),
             version: u64_ntohl_from_bytes(bytes[0..8].try_into().unwrap()),
             // num_cookies: u64_ntohl_from_bytes(bytes[8..16].try_into().unwrap()),
@@ -73,6 +80,7 @@ impl Serialize for SerializerHomebrew {
                 location: "".to_string(),
                 content: "".to_string(),
                 offset: u64_ntohl_from_bytes(bytes[i..i + 8].try_into().unwrap()),
+                metadata: BTreeMap::new(),
             });
         }
         // let num_cookies = u64_ntohl_from_bytes(bytes[8..16].try_into().unwrap());
@@ -107,10 +115,11 @@ impl Serialize for SerializerLinux {
         // padding
         bytes.extend_from_slice(&[0; 3]);
         //  offset fields
+        let has_offsets = has_offsets(data);
         let mut offset: u32 = 0;
         for cookie in &data.cookies {
-            if cookie.offset != 0 {
-                // If the offset is already set, use it
+            if has_offsets {
+                // If the offsets are already set, use them
                 bytes.extend_from_slice(&(cookie.offset as u32).to_be_bytes());
             } else {
                 // Otherwise, calculate the offset based on the current position
@@ -126,7 +135,9 @@ impl Serialize for SerializerLinux {
         let mut data = CookieJar {
             // Metadata fields
             location: "".to_string(),
+            layer: "".to_string(),
             probability: 0.0,
+            weight: 1.0,
             platform: "linux".to_string(),
             version: u32::from_be_bytes(bytes[0..4].try_into().unwrap()) as u64,
             // num_cookies: u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as u64,
@@ -144,6 +155,7 @@ impl Serialize for SerializerLinux {
                 location: "".to_string(),
                 content: "".to_string(),
                 offset: u32::from_be_bytes(bytes[i..i + 4].try_into().unwrap()) as u64,
+                metadata: BTreeMap::new(),
             });
         }
         let num_cookies = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as u64;
@@ -178,10 +190,11 @@ impl Serialize for SerializerFreeBSD {
         // padding
         bytes.extend_from_slice(&[0; 3]);
         //  offset fields
+        let has_offsets = has_offsets(data);
         let mut offset: u64 = 0;
         for cookie in &data.cookies {
-            if cookie.offset != 0 {
-                // If the offset is already set, use it
+            if has_offsets {
+                // If the offsets are already set, use them
                 bytes.extend_from_slice(&(cookie.offset as u64).to_be_bytes());
             } else {
                 // Otherwise, calculate the offset based on the current position
@@ -197,7 +210,9 @@ impl Serialize for SerializerFreeBSD {
         let mut data = CookieJar {
             // Metadata fields
             location: "".to_string(),
+            layer: "".to_string(),
             probability: 0.0,
+            weight: 1.0,
             platform: "freebsd".to_string(),
             version: u32::from_be_bytes(bytes[0..4].try_into().unwrap()) as u64,
             // num_cookies: u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as u64,
@@ -214,6 +229,7 @@ impl Serialize for SerializerFreeBSD {
                 location: "".to_string(),
                 content: "".to_string(),
                 offset: u64::from_be_bytes(bytes[i..i + 8].try_into().unwrap()),
+                metadata: BTreeMap::new(),
             });
         }
         let num_cookies = u32::from_be_bytes(bytes[4..8].try_into().unwrap()) as u64;
@@ -253,6 +269,36 @@ impl Serializer {
         }
     }
 
+    /// Check the sizes of the data file in the format, so `from_bytes()` can read it. A data
+    /// file which is empty, truncated or half-written is an error, instead of a panic.
+    pub fn check(bytes: &[u8], t: &SerializerType) -> Result<()> {
+        let (header_size, width) = match t {
+            SerializerType::Homebrew => (HEADER_SIZE_HOMEBREW, 8),
+            SerializerType::Linux => (HEADER_SIZE_LINUX, 4),
+            SerializerType::FreeBSD => (HEADER_SIZE_FREEBSD, 8),
+        };
+        // the header, the offsets and the file size at the end
+        if bytes.len() < header_size + width || !(bytes.len() - header_size).is_multiple_of(width) {
+            anyhow::bail!(
+                "Invalid data file: {} bytes do not make a {} data file",
+                bytes.len(),
+                Serializer::get_platform_by_type(t)
+            );
+        }
+        if *t != SerializerType::Homebrew {
+            let num_cookies = u32::from_be_bytes(bytes[4..8].try_into()?) as usize;
+            let num_offsets = (bytes.len() - header_size) / width - 1;
+            if num_cookies != num_offsets {
+                anyhow::bail!(
+                    "Invalid data file: {} strings in the header, but {} offsets",
+                    num_cookies,
+                    num_offsets
+                );
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_type_by_platform(platform: &str) -> SerializerType {
         match platform {
             "homebrew" => SerializerType::Homebrew,
@@ -271,6 +317,11 @@ impl Serializer {
     }
 
     pub fn get_type_by_bytes(bytes: &Vec<u8>) -> SerializerType {
+        // a short data file has zeros in place of the missing bytes
+        let mut header = [0u8; 36];
+        let len = bytes.len().min(header.len());
+        header[..len].copy_from_slice(&bytes[..len]);
+        let bytes = header;
         // Detect file format based on byte patterns
         if bytes[0..8] == [0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00] {
             // Homebrew format has strange version of 64-bit big-endian
@@ -315,6 +366,13 @@ impl Serializer {
     }
 }
 
+/// Checks whether the offsets of the cookies are already set, e.g. parsed from a text file.
+/// The first cookie is always at offset 0, so any non-zero offset means all of them are set,
+/// which keeps the offsets correct after the cookies are reordered.
+fn has_offsets(data: &CookieJar) -> bool {
+    data.cookies.iter().any(|c| c.offset != 0)
+}
+
 /// Converts a u64 value to network byte order (big-endian) and returns it as a byte array.
 /// This function mimics the behavior of the original C implementation's htonl() function.
 ///
@@ -564,6 +622,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_serializer_check() {
+        for (bytes, expected) in get_testcases_for_bytes().iter() {
+            let t = &expected.0;
+            assert!(Serializer::check(bytes, t).is_ok(), "{:?}: {:?}", t, bytes);
+            // truncated, or cut in the middle of an offset
+            for len in [0, 4, bytes.len() - 1, bytes.len() - 3] {
+                assert!(
+                    Serializer::check(&bytes[..len], t).is_err(),
+                    "{:?}: {} bytes",
+                    t,
+                    len
+                );
+            }
+        }
+        // an empty file is detected as the current platform, and cannot be read
+        let t = Serializer::get_type_by_bytes(&Vec::new());
+        assert!(Serializer::check(&[], &t).is_err());
+    }
+
     #[test]
     fn test_serializer_from_bytes() {
         let testcases = get_testcases_for_bytes();
@@ -624,7 +702,9 @@ mod tests {
                 given;
             let mut data = CookieJar {
                 location: "".to_string(),
+                layer: "".to_string(),
                 probability: 0.0,
+                weight: 1.0,
                 platform: Serializer::get_platform_by_type(t),
                 version: *version,
                 cookies: Vec::new(),
@@ -639,6 +719,7 @@ mod tests {
                     location: "".to_string(),
                     content: "".to_string(),
                     offset: *offset,
+                    metadata: BTreeMap::new(),
                 });
             }
             assert_eq!(
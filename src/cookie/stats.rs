@@ -0,0 +1,383 @@
+use std::io::Write;
+use std::str::FromStr;
+
+use anyhow::{Error, Result};
+use serde_json::{json, Value};
+
+use crate::cookie::{is_offensive_jar, CookieCabinet, CookieJar};
+
+/// Output formats of the statistics report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsFormat {
+    Table, // human readable table
+    Json,  // a single JSON object
+}
+
+impl FromStr for StatsFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "table" => Ok(StatsFormat::Table),
+            "json" => Ok(StatsFormat::Json),
+            _ => anyhow::bail!("Unknown stats format: {} (expected one of: table, json)", s),
+        }
+    }
+}
+
+/// Distribution of the cookie lengths.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LengthStats {
+    pub min: usize,
+    pub p25: usize,
+    pub median: usize,
+    pub p75: usize,
+    pub p90: usize,
+    pub p99: usize,
+    pub max: usize,
+    pub mean: f64,
+}
+
+impl LengthStats {
+    pub fn from_lengths(mut lengths: Vec<usize>) -> Self {
+        if lengths.is_empty() {
+            return Self::default();
+        }
+        lengths.sort_unstable();
+        Self {
+            min: lengths[0],
+            p25: percentile(&lengths, 25.0),
+            median: percentile(&lengths, 50.0),
+            p75: percentile(&lengths, 75.0),
+            p90: percentile(&lengths, 90.0),
+            p99: percentile(&lengths, 99.0),
+            max: lengths[lengths.len() - 1],
+            mean: lengths.iter().sum::<usize>() as f64 / lengths.len() as f64,
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        json!({
+            "min": self.min,
+            "p25": self.p25,
+            "median": self.median,
+            "p75": self.p75,
+            "p90": self.p90,
+            "p99": self.p99,
+            "max": self.max,
+            "mean": (self.mean * 100.0).round() / 100.0,
+        })
+    }
+}
+
+/// Counts and length distribution of a jar, or of the whole cabinet.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JarStats {
+    pub shelf: String,
+    pub jar: String,
+    pub cookies: usize,
+    pub short: usize,     // cookies which pass the -s cutoff
+    pub long: usize,      // cookies which pass the -l cutoff
+    pub offensive: usize, // cookies in offensive jars
+    pub bytes: LengthStats,
+    pub chars: LengthStats,
+}
+
+impl JarStats {
+    fn from_jars<'a>(
+        shelf: &str,
+        jar: &str,
+        jars: impl Iterator<Item = (&'a str, &'a CookieJar)>, // with the shelf of each jar
+        length: usize,
+    ) -> Self {
+        let mut stats = JarStats {
+            shelf: shelf.to_string(),
+            jar: jar.to_string(),
+            ..Default::default()
+        };
+        let mut bytes: Vec<usize> = Vec::new();
+        let mut chars: Vec<usize> = Vec::new();
+        for (shelf, jar) in jars {
+            // the same as `fortune -o` selects the jars
+            let offensive = is_offensive_jar(shelf, &jar.location);
+            for cookie in jar.iter() {
+                stats.cookies += 1;
+                // the same cutoff as -s and -l: len + 1 <= length, +1 for '\n'
+                if cookie.content.len() < length {
+                    stats.short += 1;
+                } else {
+                    stats.long += 1;
+                }
+                if offensive {
+                    stats.offensive += 1;
+                }
+                bytes.push(cookie.content.len());
+                chars.push(cookie.content.chars().count());
+            }
+        }
+        stats.bytes = LengthStats::from_lengths(bytes);
+        stats.chars = LengthStats::from_lengths(chars);
+        stats
+    }
+
+    fn to_value(&self) -> Value {
+        json!({
+            "shelf": self.shelf,
+            "jar": self.jar,
+            "cookies": self.cookies,
+            "short": self.short,
+            "long": self.long,
+            "offensive": self.offensive,
+            "normal": self.cookies - self.offensive,
+            "bytes": self.bytes.to_value(),
+            "chars": self.chars.to_value(),
+        })
+    }
+}
+
+/// Statistics report of the cookies in a cabinet.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    pub length: usize, // the -n cutoff of short and long cookies
+    pub jars: Vec<JarStats>,
+    pub total: JarStats,
+}
+
+impl Stats {
+    pub fn from_cabinet(cabinet: &CookieCabinet, length: usize) -> Self {
+        let jars = cabinet
+            .iter()
+            .flat_map(|shelf| {
+                shelf.iter().map(move |jar| {
+                    let jars = [(shelf.location.as_str(), jar)].into_iter();
+                    JarStats::from_jars(&shelf.location, &jar.location, jars, length)
+                })
+            })
+            .collect();
+        let total = JarStats::from_jars(
+            "",
+            "",
+            cabinet
+                .iter()
+                .flat_map(|shelf| shelf.iter().map(|jar| (shelf.location.as_str(), jar))),
+            length,
+        );
+        Self {
+            length,
+            jars,
+            total,
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        let mut total = self.total.to_value();
+        if let Some(total) = total.as_object_mut() {
+            total.remove("shelf");
+            total.remove("jar");
+            total.insert("jars".to_string(), json!(self.jars.len()));
+        }
+        json!({
+            "length": self.length,
+            "jars": self.jars.iter().map(|j| j.to_value()).collect::<Vec<Value>>(),
+            "total": total,
+        })
+    }
+
+    pub fn write<W: Write>(&self, format: StatsFormat, writer: &mut W) -> Result<()> {
+        match format {
+            StatsFormat::Json => {
+                serde_json::to_writer_pretty(&mut *writer, &self.to_value())?;
+                writeln!(writer)?;
+            }
+            StatsFormat::Table => self.write_table(writer)?,
+        }
+        Ok(())
+    }
+
+    fn write_table<W: Write>(&self, writer: &mut W) -> Result<()> {
+        let short = format!("<={}", self.length);
+        let long = format!(">{}", self.length);
+        writeln!(
+            writer,
+            "{:>8} {:>7} {:>7} {:>6} {:>6} {:>6} {:>6}  jar",
+            "cookies", short, long, "min", "median", "p90", "max"
+        )?;
+        let mut shelf = None;
+        for jar in self.jars.iter() {
+            if shelf != Some(&jar.shelf) {
+                writeln!(writer, "{}", jar.shelf)?;
+                shelf = Some(&jar.shelf);
+            }
+            writeln!(
+                writer,
+                "{:>8} {:>7} {:>7} {:>6} {:>6} {:>6} {:>6}  {}{}",
+                jar.cookies,
+                jar.short,
+                jar.long,
+                jar.bytes.min,
+                jar.bytes.median,
+                jar.bytes.p90,
+                jar.bytes.max,
+                jar.jar,
+                if jar.offensive > 0 {
+                    " (offensive)"
+                } else {
+                    ""
+                }
+            )?;
+        }
+        writeln!(writer)?;
+
+        let total = &self.total;
+        writeln!(
+            writer,
+            "Total: {} cookies in {} jars ({} normal, {} offensive)",
+            total.cookies,
+            self.jars.len(),
+            total.cookies - total.offensive,
+            total.offensive
+        )?;
+        writeln!(
+            writer,
+            "Cutoff (-n {}): {} short, {} long",
+            self.length, total.short, total.long
+        )?;
+        writeln!(
+            writer,
+            "{:<8} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>8}",
+            "length", "min", "p25", "median", "p75", "p90", "p99", "max", "mean"
+        )?;
+        for (name, lengths) in [("bytes", &total.bytes), ("chars", &total.chars)] {
+            writeln!(
+                writer,
+                "{:<8} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>6} {:>8.2}",
+                name,
+                lengths.min,
+                lengths.p25,
+                lengths.median,
+                lengths.p75,
+                lengths.p90,
+                lengths.p99,
+                lengths.max,
+                lengths.mean
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Get the percentile of the sorted values, by the nearest-rank method.
+fn percentile(sorted: &[usize], p: f64) -> usize {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cookie::CookieShelf;
+
+    fn get_cabinet() -> CookieCabinet {
+        let mut cabinet = CookieCabinet::default();
+        cabinet.push(CookieShelf::new("valley", 0.0));
+        cabinet.shelves[0]
+            .jars
+            .push(CookieJar::from_text("apple\n%\nbanana\n%\n蘋果\n%\n", "fruits", '%').unwrap());
+        cabinet.shelves[0]
+            .jars
+            .push(CookieJar::from_text("a rude joke\n%\n", "off/jokes", '%').unwrap());
+        cabinet
+    }
+
+    #[test]
+    fn test_stats_format_from_str() {
+        assert_eq!(StatsFormat::Table, "table".parse().unwrap());
+        assert_eq!(StatsFormat::Json, "json".parse().unwrap());
+        assert!("xml".parse::<StatsFormat>().is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<usize> = (1..=10).collect();
+        assert_eq!(1, percentile(&values, 0.0));
+        assert_eq!(3, percentile(&values, 25.0));
+        assert_eq!(5, percentile(&values, 50.0));
+        assert_eq!(9, percentile(&values, 90.0));
+        assert_eq!(10, percentile(&values, 99.0));
+        assert_eq!(7, percentile(&[7], 50.0));
+    }
+
+    #[test]
+    fn test_stats_from_cabinet() {
+        let stats = Stats::from_cabinet(&get_cabinet(), 6);
+        assert_eq!(2, stats.jars.len());
+
+        let fruits = &stats.jars[0];
+        assert_eq!(
+            ("valley", "fruits"),
+            (fruits.shelf.as_str(), fruits.jar.as_str())
+        );
+        assert_eq!(
+            (3, 1, 2, 0),
+            (fruits.cookies, fruits.short, fruits.long, fruits.offensive)
+        );
+        assert_eq!(
+            (5, 6, 6),
+            (fruits.bytes.min, fruits.bytes.median, fruits.bytes.max)
+        );
+        assert_eq!(
+            (2, 5, 6),
+            (fruits.chars.min, fruits.chars.median, fruits.chars.max)
+        );
+
+        let total = &stats.total;
+        assert_eq!(
+            (4, 1, 3, 1),
+            (total.cookies, total.short, total.long, total.offensive)
+        );
+        assert_eq!(5, total.bytes.min);
+        assert_eq!(11, total.bytes.max);
+        assert_eq!(7.0, total.bytes.mean);
+
+        // by the path under the shelf, the same as fortune -o
+        let mut cabinet = CookieCabinet::default();
+        cabinet.push(CookieShelf::new("embed:off", 0.0));
+        cabinet.shelves[0]
+            .jars
+            .push(CookieJar::from_text("a joke\n%\n", "off/jokes", '%').unwrap());
+        let stats = Stats::from_cabinet(&cabinet, 6);
+        assert_eq!(0, stats.jars[0].offensive);
+        assert_eq!(0, stats.total.offensive);
+    }
+
+    #[test]
+    fn test_stats_write() {
+        let stats = Stats::from_cabinet(&get_cabinet(), 6);
+
+        let mut output: Vec<u8> = Vec::new();
+        stats.write(StatsFormat::Json, &mut output).unwrap();
+        let value: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(6, value["length"]);
+        assert_eq!("off/jokes", value["jars"][1]["jar"]);
+        assert_eq!(1, value["jars"][1]["offensive"]);
+        assert_eq!(4, value["total"]["cookies"]);
+        assert_eq!(2, value["total"]["jars"]);
+        assert_eq!(3, value["total"]["normal"]);
+        assert_eq!(5, value["total"]["chars"]["median"]);
+
+        let mut output: Vec<u8> = Vec::new();
+        stats.write(StatsFormat::Table, &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(
+            output.contains("Total: 4 cookies in 2 jars (3 normal, 1 offensive)"),
+            "{}",
+            output
+        );
+        assert!(
+            output.contains("Cutoff (-n 6): 1 short, 3 long"),
+            "{}",
+            output
+        );
+        assert!(output.contains("off/jokes (offensive)"), "{}", output);
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use glob::{MatchOptions, Pattern};
+use log::debug;
+
+use crate::cookie::weights::WEIGHTS_FILE;
+
+/// Name of the gitignore-style file which excludes files from a fortune directory.
+pub const IGNORE_FILE: &str = ".fortuneignore";
+
+/// Walks a fortune directory to find the cookie files.
+///
+/// Dot files, dot directories, `.dat` and `.idx` files and `weights.toml` are always skipped.
+/// Files matching the `.fortuneignore` of any directory on their path are skipped as well.
+/// Symbolic links to files are read, while symbolic links to directories are only followed
+/// if `follow_symlinks` is set, and each directory is visited at most once.
+#[derive(Debug, Clone, Default)]
+pub struct Walker {
+    pub max_depth: Option<usize>, // Maximum depth of files, 1 for the files in the directory itself
+    pub follow_symlinks: bool,    // Follow symbolic links to directories
+}
+
+/// A rule of the `.fortuneignore` file.
+#[derive(Debug, Clone)]
+struct IgnoreRule {
+    base: PathBuf,    // Directory of the ignore file, relative to the root
+    pattern: Pattern, // Glob pattern of the rule
+    negated: bool,    // '!pattern' re-includes the matched files
+    dir_only: bool,   // 'pattern/' matches directories only
+    anchored: bool,   // '/pattern' or 'a/b' matches the path relative to the base
+}
+
+impl IgnoreRule {
+    fn parse(line: &str, base: &Path) -> Result<Option<IgnoreRule>> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(None);
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.trim_start_matches('/');
+        if line.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(IgnoreRule {
+            base: base.to_path_buf(),
+            pattern: Pattern::new(line)?,
+            negated,
+            dir_only,
+            anchored,
+        }))
+    }
+
+    /// Check whether the rule matches the path, which is relative to the root.
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Ok(path) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        if self.anchored {
+            let path = path.to_string_lossy().replace('\\', "/");
+            self.pattern.matches_with(&path, options)
+        } else {
+            path.file_name()
+                .map(|name| self.pattern.matches_with(&name.to_string_lossy(), options))
+                .unwrap_or(false)
+        }
+    }
+}
+
+impl Walker {
+    pub fn new(max_depth: Option<usize>, follow_symlinks: bool) -> Self {
+        Self {
+            max_depth,
+            follow_symlinks,
+        }
+    }
+
+    /// Find the cookie files under the given directory, sorted by path.
+    pub fn walk(&self, root: &Path) -> Result<Vec<PathBuf>> {
+        let mut files = Vec::new();
+        let mut rules = Vec::new();
+        let mut visited = HashSet::new();
+        if let Ok(canonical) = root.canonicalize() {
+            visited.insert(canonical);
+        }
+        self.walk_dir(root, root, 1, &mut rules, &mut visited, &mut files)?;
+        Ok(files)
+    }
+
+    fn walk_dir(
+        &self,
+        root: &Path,
+        dir: &Path,
+        depth: usize,
+        rules: &mut Vec<IgnoreRule>,
+        visited: &mut HashSet<PathBuf>,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        if self.max_depth.is_some_and(|max_depth| depth > max_depth) {
+            return Ok(());
+        }
+
+        // rules of the ignore file in this directory apply to the whole subtree
+        let num_rules = rules.len();
+        let ignore_file = dir.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            let base = dir.strip_prefix(root).unwrap_or(Path::new(""));
+            let content = std::fs::read_to_string(&ignore_file)
+                .with_context(|| format!("Failed to read {}", ignore_file.display()))?;
+            for line in content.lines() {
+                if let Some(rule) = IgnoreRule::parse(line, base).with_context(|| {
+                    format!("Invalid rule in {}: {}", ignore_file.display(), line)
+                })? {
+                    rules.push(rule);
+                }
+            }
+        }
+
+        let mut entries: Vec<_> = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?
+            .filter_map(|entry| entry.ok())
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            // filter out dot files and directories
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let is_symlink = file_type.is_symlink();
+            let Ok(metadata) = std::fs::metadata(&path) else {
+                debug!("Walker: skip broken link: {}", path.display());
+                continue;
+            };
+            let relative = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            if is_ignored(rules, &relative, metadata.is_dir()) {
+                debug!("Walker: skip ignored: {}", path.display());
+                continue;
+            }
+
+            if metadata.is_dir() {
+                if is_symlink && !self.follow_symlinks {
+                    debug!("Walker: skip symbolic link: {}", path.display());
+                    continue;
+                }
+                // protect from symbolic link loops
+                let canonical = path.canonicalize().unwrap_or(path.clone());
+                if !visited.insert(canonical) {
+                    debug!("Walker: skip visited directory: {}", path.display());
+                    continue;
+                }
+                self.walk_dir(root, &path, depth + 1, rules, visited, files)?;
+            } else if metadata.is_file() {
+                // filter out .dat files, search indexes and weights files
+                let extension = path.extension().unwrap_or_default();
+                if extension == "dat" || extension == "idx" || entry.file_name() == WEIGHTS_FILE {
+                    continue;
+                }
+                files.push(path);
+            }
+        }
+
+        rules.truncate(num_rules);
+        Ok(())
+    }
+}
+
+/// Check whether the path is ignored by the rules; the last matching rule wins.
+fn is_ignored(rules: &[IgnoreRule], path: &Path, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(path, is_dir))
+        .map(|rule| !rule.negated)
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Create a directory tree in the temporary directory for testing.
+    fn create_tree(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("fortune-rs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        for (path, content) in files.iter() {
+            let path = root.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+        root
+    }
+
+    fn walk(walker: &Walker, root: &Path) -> Vec<String> {
+        walker
+            .walk(root)
+            .unwrap()
+            .iter()
+            .map(|p| {
+                p.strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_walker_walk() {
+        let root = create_tree(
+            "walk",
+            &[
+                ("apple", ""),
+                ("apple.dat", ""),
+                ("apple.idx", ""),
+                (".hidden", ""),
+                (".git/config", ""),
+                ("fruits/banana", ""),
+                ("fruits/deep/cherry", ""),
+            ],
+        );
+
+        let testcases = [
+            (None, vec!["apple", "fruits/banana", "fruits/deep/cherry"]),
+            (Some(1), vec!["apple"]),
+            (Some(2), vec!["apple", "fruits/banana"]),
+            (Some(0), vec![]),
+        ];
+        for (max_depth, expected) in testcases.iter() {
+            let walker = Walker::new(*max_depth, false);
+            assert_eq!(
+                *expected,
+                walk(&walker, &root),
+                "max_depth: {:?}",
+                max_depth
+            );
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_walker_ignore_file() {
+        let root = create_tree(
+            "ignore",
+            &[
+                (
+                    ".fortuneignore",
+                    "# build files\nREADME*\nLICENSE\n/Makefile\nbuild/\n*.txt\n!keep.txt\n",
+                ),
+                ("README.md", ""),
+                ("LICENSE", ""),
+                ("Makefile", ""),
+                ("apple", ""),
+                ("notes.txt", ""),
+                ("keep.txt", ""),
+                ("build/output", ""),
+                ("fruits/Makefile", ""),
+                ("fruits/README", ""),
+                ("fruits/banana", ""),
+                ("fruits/.fortuneignore", "banana\n"),
+                ("veggies/banana", ""),
+            ],
+        );
+
+        assert_eq!(
+            vec!["apple", "fruits/Makefile", "keep.txt", "veggies/banana"],
+            walk(&Walker::default(), &root)
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_walker_symlinks() {
+        let root = create_tree("symlinks", &[("apple", ""), ("fruits/banana", "")]);
+        std::os::unix::fs::symlink(root.join("fruits"), root.join("linked")).unwrap();
+        std::os::unix::fs::symlink(root.join("apple"), root.join("linked-apple")).unwrap();
+        // a loop back to the root
+        std::os::unix::fs::symlink(&root, root.join("fruits/loop")).unwrap();
+
+        assert_eq!(
+            vec!["apple", "fruits/banana", "linked-apple"],
+            walk(&Walker::new(None, false), &root)
+        );
+        assert_eq!(
+            vec!["apple", "fruits/banana", "linked-apple"],
+            walk(&Walker::new(None, true), &root),
+            "should visit each directory only once"
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
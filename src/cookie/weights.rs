@@ -0,0 +1,126 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+
+use crate::cookie::compress;
+
+/// Name of the file which gives the relative weights of the files and subdirectories
+/// in a fortune directory.
+pub const WEIGHTS_FILE: &str = "weights.toml";
+
+/// Relative weights of the jars under a fortune directory.
+///
+/// Each directory may have a `weights.toml`, which maps the names of its files and
+/// subdirectories to relative weights, e.g. `fortunes = 3`. The entries not listed have
+/// the weight of 1, and the weight of a jar is multiplied along its path.
+#[derive(Debug, Default)]
+pub struct JarWeights {
+    root: PathBuf,
+    dirs: HashMap<PathBuf, BTreeMap<String, f64>>, // Parsed weights of each directory
+}
+
+impl JarWeights {
+    pub fn new(root: &Path) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            dirs: HashMap::new(),
+        }
+    }
+
+    /// Get the weight of the given file under the root directory.
+    pub fn weight_of(&mut self, path: &Path) -> Result<f64> {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path).to_path_buf();
+        let mut weight = 1.0;
+        let mut dir = self.root.clone();
+        for component in relative.components() {
+            let name = component.as_os_str().to_string_lossy();
+            let weights = self.weights_of_dir(&dir)?;
+            // a compressed file can be weighted by its name without the suffix
+            weight *= weights
+                .get(name.as_ref())
+                .or_else(|| weights.get(compress::strip_suffix(&name)))
+                .copied()
+                .unwrap_or(1.0);
+            dir.push(component);
+        }
+        Ok(weight)
+    }
+
+    fn weights_of_dir(&mut self, dir: &Path) -> Result<&BTreeMap<String, f64>> {
+        if !self.dirs.contains_key(dir) {
+            let file = dir.join(WEIGHTS_FILE);
+            let weights = if file.is_file() {
+                let content = std::fs::read_to_string(&file)
+                    .with_context(|| format!("Failed to read {}", file.display()))?;
+                parse(&content).with_context(|| format!("Invalid weights in {}", file.display()))?
+            } else {
+                BTreeMap::new()
+            };
+            self.dirs.insert(dir.to_path_buf(), weights);
+        }
+        Ok(&self.dirs[dir])
+    }
+}
+
+/// Parse the content of a `weights.toml` file.
+pub fn parse(content: &str) -> Result<BTreeMap<String, f64>> {
+    let table: toml::Table = content.parse()?;
+    let mut weights = BTreeMap::new();
+    for (name, value) in table {
+        let weight = match value {
+            toml::Value::Integer(i) => i as f64,
+            toml::Value::Float(f) => f,
+            _ => anyhow::bail!("weight of '{}' is not a number: {}", name, value),
+        };
+        if !weight.is_finite() || weight < 0.0 {
+            anyhow::bail!("weight of '{}' must be a non-negative number", name);
+        }
+        weights.insert(name, weight);
+    }
+    Ok(weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let weights = parse("# boost the short ones\napple = 3\n\"sub dir\" = 0.5\n").unwrap();
+        assert_eq!(
+            BTreeMap::from([("apple".to_string(), 3.0), ("sub dir".to_string(), 0.5)]),
+            weights
+        );
+
+        assert!(parse("apple = \"high\"").is_err());
+        assert!(parse("apple = -1").is_err());
+        assert!(parse("apple = nan").is_err());
+        assert!(parse("apple = ").is_err());
+    }
+
+    #[test]
+    fn test_jar_weights_weight_of() {
+        let root = std::env::temp_dir().join(format!("fortune-rs-weights-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("fruits/citrus")).unwrap();
+        std::fs::write(root.join(WEIGHTS_FILE), "apple = 3\nfruits = 2\n").unwrap();
+        std::fs::write(root.join("fruits").join(WEIGHTS_FILE), "banana = 0.5\n").unwrap();
+
+        let testcases = [
+            ("apple", 3.0),
+            ("apple.gz", 3.0),
+            ("orange", 1.0),
+            ("fruits/banana", 1.0),
+            ("fruits/cherry", 2.0),
+            ("fruits/citrus/lemon", 2.0),
+        ];
+        let mut weights = JarWeights::new(&root);
+        for (path, expected) in testcases.iter() {
+            let weight = weights.weight_of(&root.join(path)).unwrap();
+            assert_eq!(*expected, weight, "{}", path);
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
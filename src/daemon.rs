@@ -0,0 +1,387 @@
+use std::fs::DirBuilder;
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use argh::FromArgs;
+use env_logger::Env;
+use log::debug;
+use serde_json::{json, Value};
+
+use crate::cookie::{walk::Walker, Cookie, CookieCabinet, WeightMode};
+use crate::serve::{cookie_value, filter_args, Server};
+use crate::Args;
+
+#[derive(FromArgs)]
+/// Keep the fortunes loaded, and answer `fortune --client` over a Unix domain socket.
+pub struct DaemonArgs {
+    /// path of the socket (default: $XDG_RUNTIME_DIR/fortune.sock)
+    #[argh(option)]
+    socket: Option<String>,
+
+    /// seconds between the checks for changed sources (default: 2)
+    #[argh(option, default = "2")]
+    reload_interval: u64,
+
+    /// how the weights are given: percent (default), relative
+    #[argh(option)]
+    weights: Option<String>,
+
+    /// maximum depth of the fortune files to load from directories
+    #[argh(option)]
+    max_depth: Option<usize>,
+
+    /// follow symbolic links to directories
+    #[argh(switch)]
+    follow_symlinks: bool,
+
+    /// enable additional debugging output
+    #[argh(switch, short = 'D')]
+    debug: bool,
+
+    /// [[n%] file/directory/all]
+    #[argh(positional)]
+    paths: Vec<String>,
+}
+
+/// The socket path used when none is given, private to the user: in `$XDG_RUNTIME_DIR`,
+/// or else in a directory of the user in the temporary directory, e.g. `/tmp/fortune-$USER`.
+pub fn default_socket() -> Result<PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join("fortune.sock")),
+        _ => {
+            let user = std::env::var("USER").unwrap_or_default();
+            let dir = std::env::temp_dir().join(format!("fortune-{}", user));
+            private_dir(&dir)?;
+            Ok(dir.join("fortune.sock"))
+        }
+    }
+}
+
+/// Create the directory only the user can access, or check that it is so,
+/// so that nobody else can put a socket there in place of the daemon.
+fn private_dir(dir: &Path) -> Result<()> {
+    match DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => {
+            return Err(e).with_context(|| format!("Failed to create {}", dir.display()))
+        }
+        _ => {}
+    }
+    // a private directory of another user cannot be entered, so neither bound nor connected to
+    let metadata = std::fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.permissions().mode() & 0o077 != 0 {
+        anyhow::bail!("Not a private directory: {}", dir.display());
+    }
+    Ok(())
+}
+
+pub fn run(args: DaemonArgs) -> Result<()> {
+    if args.debug {
+        env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
+    }
+
+    let server = Server::load(
+        &args.paths,
+        args.weights.as_deref(),
+        &Walker::new(args.max_depth, args.follow_symlinks),
+        Duration::from_secs(args.reload_interval),
+    )?;
+
+    let socket = match args.socket {
+        Some(socket) => PathBuf::from(socket),
+        None => default_socket()?,
+    };
+    if socket.exists() {
+        // a socket nobody answers is left over from a daemon which did not exit cleanly
+        if UnixStream::connect(&socket).is_ok() {
+            anyhow::bail!("A daemon is already running on {}", socket.display());
+        }
+        std::fs::remove_file(&socket)
+            .with_context(|| format!("Failed to remove {}", socket.display()))?;
+    }
+    let listener = UnixListener::bind(&socket)
+        .with_context(|| format!("Failed to listen on {}", socket.display()))?;
+    println!("Listening on {}", socket.display());
+    std::io::stdout().flush()?;
+
+    // each request is handled by its own thread, so a slow client does not hold up the others
+    let server = Arc::new(server);
+    for stream in listener.incoming() {
+        let server = server.clone();
+        std::thread::spawn(move || {
+            let result = stream
+                .map_err(anyhow::Error::from)
+                .and_then(|stream| handle(&server, stream));
+            if let Err(e) = result {
+                debug!("Failed to handle the request: {:#}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Answer one request: a line with the JSON array of the `fortune` arguments,
+/// e.g. `["-s","-m","apple","--","fruits"]`. The response is a line with the selected cookies,
+/// `{"cookies":[...]}`, or the reason why none could be selected, `{"error":"..."}`.
+fn handle(server: &Server, mut stream: UnixStream) -> Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match select(server, &line) {
+        Ok(cookies) => json!({ "cookies": cookies.iter().map(cookie_value).collect::<Vec<_>>() }),
+        Err(e) => json!({ "error": format!("{:#}", e) }),
+    };
+    debug!("{} => {}", line.trim(), response);
+    writeln!(stream, "{}", response)?;
+    Ok(())
+}
+
+/// All matching cookies with -m, otherwise one random cookie (none if there is nothing to choose).
+fn select(server: &Server, line: &str) -> Result<Vec<Cookie>> {
+    let tokens: Vec<String> = serde_json::from_str(line).context("Invalid request")?;
+    let args = filter_args(&tokens)?;
+    server.reload_if_changed()?;
+    let cabinet = server.cabinet();
+    let mut selection = server.select(&cabinet, &args)?;
+    // the client resolves its weights to percentages, whatever the weight mode is
+    if !args.paths.is_empty() {
+        selection = server.reweigh(selection, &args.paths, WeightMode::Percent)?;
+    }
+    if !args.pattern.is_empty() {
+        return Ok(selection
+            .iter()
+            .flat_map(|shelf| shelf.iter())
+            .flat_map(|jar| jar.iter())
+            .cloned()
+            .collect());
+    }
+    if selection.num_of_jars() == 0 {
+        return Ok(Vec::new());
+    }
+    selection.calculate_prob(args.equal_size);
+    Ok(selection
+        .choose(&mut rand::thread_rng())
+        .into_iter()
+        .cloned()
+        .collect())
+}
+
+/// Ask the daemon for the cookies selected by the arguments.
+/// Returns None when the daemon is not available or does not serve the given paths,
+/// so the fortunes should be loaded locally.
+pub fn ask(args: &Args) -> Option<Vec<Cookie>> {
+    // the daemon walks the directories its own way
+    if args.max_depth.is_some() || args.follow_symlinks {
+        debug!("--max-depth and --follow-symlinks are not sent to the daemon");
+        return None;
+    }
+    let socket = match &args.socket {
+        Some(socket) => PathBuf::from(socket),
+        None => match default_socket() {
+            Ok(socket) => socket,
+            Err(e) => {
+                debug!("No daemon socket: {:#}", e);
+                return None;
+            }
+        },
+    };
+    let tokens = match filter_tokens(args) {
+        Ok(tokens) => tokens,
+        // the invalid weights are reported by the local loading
+        Err(e) => {
+            debug!("Failed to prepare the request: {:#}", e);
+            return None;
+        }
+    };
+    match request(&socket, &tokens) {
+        Ok(cookies) => Some(cookies),
+        Err(e) => {
+            debug!("daemon on {} is not available: {:#}", socket.display(), e);
+            None
+        }
+    }
+}
+
+fn request(socket: &PathBuf, tokens: &[String]) -> Result<Vec<Cookie>> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    writeln!(stream, "{}", serde_json::to_string(tokens)?)?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response: Value = serde_json::from_str(&line).context("Invalid response")?;
+    if let Some(error) = response["error"].as_str() {
+        anyhow::bail!("{}", error);
+    }
+    let cookies = response["cookies"].as_array().context("Invalid response")?;
+    Ok(cookies.iter().map(parse_cookie).collect())
+}
+
+fn parse_cookie(value: &Value) -> Cookie {
+    Cookie {
+        location: value["jar"].as_str().unwrap_or_default().to_string(),
+        content: value["content"].as_str().unwrap_or_default().to_string(),
+        offset: value["offset"].as_u64().unwrap_or_default(),
+        metadata: value["metadata"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(k, v)| (k.clone(), v.as_str().unwrap_or_default().to_string()))
+            .collect(),
+    }
+}
+
+/// The arguments which select the fortunes, to be selected the same way by the daemon.
+/// The paths are sent after `--` with their percentages, and as absolute paths if they exist,
+/// so the daemon can tell whether it serves them.
+fn filter_tokens(args: &Args) -> Result<Vec<String>> {
+    let switches = [
+        ("-a", args.all),
+        ("-c", args.show_file),
+        ("-e", args.equal_size),
+        ("-i", args.ignore_case),
+        ("-l", args.long_only),
+        ("-o", args.offensive),
+        ("-s", args.short_only),
+        ("--any", args.any),
+    ];
+    let mut tokens: Vec<String> = switches
+        .iter()
+        .filter(|(_, on)| *on)
+        .map(|(flag, _)| flag.to_string())
+        .collect();
+    tokens.extend(["-n".to_string(), args.length.to_string()]);
+    for pattern in args.pattern.iter() {
+        tokens.extend(["-m".to_string(), pattern.clone()]);
+    }
+    for pattern in args.exclude_pattern.iter() {
+        tokens.extend(["--exclude-pattern".to_string(), pattern.clone()]);
+    }
+    if let Some(form) = &args.normalize {
+        tokens.extend(["--normalize".to_string(), form.clone()]);
+    }
+    if !args.paths.is_empty() {
+        let weight_mode = match &args.weights {
+            Some(mode) => mode.parse::<WeightMode>()?,
+            None => WeightMode::default(),
+        };
+        tokens.push("--".to_string());
+        for shelf in CookieCabinet::from_string_list(&args.paths, weight_mode)?.iter() {
+            if shelf.probability > 0.0 {
+                tokens.push(format!("{}%", shelf.probability));
+            }
+            let path = std::fs::canonicalize(&shelf.location)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|_| shelf.location.clone());
+            tokens.push(path);
+        }
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_tokens() {
+        let args = Args::from_args(
+            &["fortune"],
+            &[
+                "--client",
+                "-s",
+                "-i",
+                "-m",
+                "apple",
+                "--exclude-pattern",
+                "pie",
+                "fruits",
+            ],
+        )
+        .unwrap();
+        let tokens = filter_tokens(&args).unwrap();
+        assert_eq!(
+            vec![
+                "-i",
+                "-s",
+                "-n",
+                "160",
+                "-m",
+                "apple",
+                "--exclude-pattern",
+                "pie",
+                "--",
+                "fruits"
+            ],
+            tokens
+        );
+        // the daemon accepts what the client sends
+        let parsed = filter_args(&tokens).unwrap();
+        assert_eq!(args.pattern, parsed.pattern);
+        assert_eq!(args.exclude_pattern, parsed.exclude_pattern);
+        assert!(parsed.short_only && parsed.ignore_case);
+        assert_eq!(vec!["fruits"], parsed.paths);
+
+        // the weights are sent as percentages, and the existing paths as absolute paths
+        let args = Args::from_args(
+            &["fortune"],
+            &["--weights", "relative", "3", "tests/data", "fruits"],
+        )
+        .unwrap();
+        let data = std::fs::canonicalize("tests/data").unwrap();
+        assert_eq!(
+            vec![
+                "-n",
+                "160",
+                "--",
+                "75%",
+                &data.to_string_lossy(),
+                "25%",
+                "fruits"
+            ],
+            filter_tokens(&args).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_private_dir() {
+        let dir = std::env::temp_dir().join(format!("fortune-rs-private-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let private = dir.join("private");
+        private_dir(&private).unwrap();
+        let mode = std::fs::metadata(&private).unwrap().permissions().mode();
+        assert_eq!(0o700, mode & 0o777);
+        // the existing private directory is used again
+        private_dir(&private).unwrap();
+
+        // e.g. a directory put in place by someone else
+        let shared = dir.join("shared");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777)).unwrap();
+        assert!(private_dir(&shared).is_err());
+        let link = dir.join("link");
+        std::os::unix::fs::symlink(&private, &link).unwrap();
+        assert!(private_dir(&link).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_cookie() {
+        let cookie = Cookie {
+            location: "fruits".to_string(),
+            content: "Apple pie.".to_string(),
+            offset: 0,
+            metadata: [("author".to_string(), "Anonymous".to_string())].into(),
+        };
+        let parsed = parse_cookie(&cookie_value(&cookie));
+        assert_eq!(cookie.location, parsed.location);
+        assert_eq!(cookie.content, parsed.content);
+        assert_eq!(cookie.offset, parsed.offset);
+        assert_eq!(cookie.metadata, parsed.metadata);
+    }
+}
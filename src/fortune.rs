@@ -1,12 +1,29 @@
+#[cfg(feature = "tui")]
+mod browse;
 pub mod cookie;
+#[cfg(unix)]
+mod daemon;
+mod serve;
 
 use argh::FromArgs;
 use cookie::{
+    completions::{self, Completions},
+    dedupe::Deduper,
     embed::{Embedded, EMBED_PREFIX},
-    Cookie, CookieCabinet, CookieSieve,
+    error::{self, ArgError},
+    export::{ExportFormat, Exporter},
+    layer,
+    normalize::Normalization,
+    search::Query,
+    stats::{Stats, StatsFormat},
+    walk::Walker,
+    Cookie, CookieCabinet, CookieSieve, Predicate, WeightMode,
 };
 use env_logger::Env;
 use log::debug;
+#[cfg(feature = "unicode")]
+use regex::Regex;
+#[cfg(not(feature = "unicode"))]
 use regex_lite::Regex;
 
 const MIN_WAIT_TIME: u64 = 6;
@@ -43,9 +60,21 @@ struct Args {
     #[argh(switch, short = 'l')]
     long_only: bool,
 
-    /// print out all fortunes which match the pattern
+    /// print out all fortunes which match the pattern (repeatable, all must match)
     #[argh(option, short = 'm')]
-    pattern: Option<String>,
+    pattern: Vec<String>,
+
+    /// skip the fortunes which match the pattern (repeatable)
+    #[argh(option)]
+    exclude_pattern: Vec<String>,
+
+    /// match any of the -m patterns, instead of all of them
+    #[argh(switch)]
+    any: bool,
+
+    /// normalize the patterns and fortunes before matching: nfc, nfkc
+    #[argh(option)]
+    normalize: Option<String>,
 
     /// set the longest fortune length considered to be "short"
     #[argh(option, short = 'n', default = "160")]
@@ -67,6 +96,50 @@ struct Args {
     #[argh(switch, short = 'w')]
     wait: bool,
 
+    /// list the embedded fortune packs
+    #[argh(switch)]
+    list_packs: bool,
+
+    /// print the shell completion script for: bash, zsh, fish
+    #[argh(option)]
+    completions: Option<String>,
+
+    /// print the names of the sources one per line, for the completion scripts
+    #[argh(switch, hidden_help)]
+    complete_sources: bool,
+
+    /// export the selected fortunes as: json, ndjson, csv
+    #[argh(option)]
+    export: Option<String>,
+
+    /// show statistics of the selected fortunes as: table, json
+    #[argh(option)]
+    stats: Option<String>,
+
+    /// report the duplicated and near-duplicated fortunes with their locations
+    #[argh(switch)]
+    duplicates: bool,
+
+    /// maximum depth of the fortune files to load from directories
+    #[argh(option)]
+    max_depth: Option<usize>,
+
+    /// follow symbolic links to directories
+    #[argh(switch)]
+    follow_symlinks: bool,
+
+    /// how the weights are given: percent (default), relative
+    #[argh(option)]
+    weights: Option<String>,
+
+    /// ask the running `fortune daemon`, and load the fortunes locally if it is not available
+    #[argh(switch)]
+    client: bool,
+
+    /// path of the daemon socket for --client (default: $XDG_RUNTIME_DIR/fortune.sock)
+    #[argh(option)]
+    socket: Option<String>,
+
     /// [[n%] file/directory/all]
     #[argh(positional)]
     paths: Vec<String>,
@@ -79,28 +152,153 @@ fn show_cookie(cookie: &Cookie, show_file: bool) {
     println!("{}", cookie.content);
 }
 
-fn generate_filters(args: &Args) -> CookieSieve {
+fn show_packs() {
+    for entry in Embedded::list() {
+        let name = entry.trim_end_matches('/');
+        let depth = name.matches('/').count();
+        let name = &entry[name.rfind('/').map(|i| i + 1).unwrap_or(0)..];
+        println!("{}{}", "    ".repeat(depth), name);
+    }
+}
+
+pub(crate) fn generate_filters(
+    args: &Args,
+    normalization: Option<Normalization>,
+) -> Result<CookieSieve, ArgError> {
     let mut filters = CookieSieve::default();
     let length = args.length;
     if args.short_only {
-        filters.add_filter(move |q| q.len() + 1 <= length); // +1 for '\n'
+        filters.add(Predicate::new(&format!("length <= {}", length), move |q| {
+            q.len() < length // len + 1 <= length, +1 for '\n'
+        }));
     } else if args.long_only {
-        filters.add_filter(move |q| q.len() + 1 > length); // +1 for '\n'
+        filters.add(Predicate::new(&format!("length > {}", length), move |q| {
+            q.len() + 1 > length // +1 for '\n'
+        }));
     }
-    if args.pattern.is_some() {
-        let re = if args.ignore_case {
-            Regex::new(&format!("(?i){}", args.pattern.as_ref().unwrap())).unwrap()
+    let patterns = args
+        .pattern
+        .iter()
+        .map(|p| pattern_predicate(p, args.ignore_case, normalization))
+        .collect::<Result<Vec<Predicate>, ArgError>>()?;
+    if !patterns.is_empty() {
+        filters.add(if args.any {
+            Predicate::Any(patterns)
         } else {
-            Regex::new(args.pattern.as_ref().unwrap()).unwrap()
-        };
-        filters.add_filter(move |q| re.is_match(q));
+            Predicate::All(patterns)
+        });
+    }
+    for pattern in args.exclude_pattern.iter() {
+        let predicate = pattern_predicate(pattern, args.ignore_case, normalization)?;
+        filters.add(Predicate::Not(Box::new(predicate)));
+    }
+    debug!("filters: {}", filters);
+    Ok(filters)
+}
+
+/// Compile the pattern as a predicate, described as `/pattern/` or `/pattern/i`.
+/// With the normalization, both the pattern and the fortunes are normalized before matching.
+fn pattern_predicate(
+    pattern: &str,
+    ignore_case: bool,
+    normalization: Option<Normalization>,
+) -> Result<Predicate, ArgError> {
+    let pattern = match normalization {
+        Some(form) => form.apply(pattern),
+        None => pattern.to_string(),
+    };
+    let re = if ignore_case {
+        Regex::new(&format!("(?i){}", pattern))
+    } else {
+        Regex::new(&pattern)
     }
-    filters
+    .map_err(|e| error::bad_pattern(&pattern, regex_reason(&e.to_string())))?;
+    let description = format!("/{}/{}", pattern, if ignore_case { "i" } else { "" });
+    Ok(match normalization {
+        Some(form) => Predicate::new(&description, move |q| re.is_match(&form.apply(q))),
+        None => Predicate::new(&description, move |q| re.is_match(q)),
+    })
 }
 
-fn main() -> anyhow::Result<()> {
-    let args: Args = argh::from_env();
+/// The reason of a regex error, without the pattern and the caret that the error shows itself,
+/// e.g. `unclosed group`.
+fn regex_reason(message: &str) -> &str {
+    let last = message.lines().last().unwrap_or(message);
+    last.strip_prefix("error: ").unwrap_or(last)
+}
 
+/// Set up the shelves for loading, and point the missing locations to the embedded data.
+pub(crate) fn prepare_shelves(
+    cabinet: &mut CookieCabinet,
+    walker: &Walker,
+    query: &Query,
+) -> anyhow::Result<()> {
+    for shelf in cabinet.shelves.iter_mut() {
+        shelf.walker = walker.clone();
+        shelf.query = query.clone();
+        //  if the shelf location is not point to embedded data and not exists, then check if it exists in embedded data
+        if !shelf.location.starts_with(EMBED_PREFIX) && !std::fs::exists(&shelf.location)? {
+            if Embedded::exists(&shelf.location) || layer::exists(&shelf.location) {
+                // update shelf location if necessary
+                shelf.location = Embedded::format_path(&shelf.location);
+            } else {
+                anyhow::bail!("{} not found.", shelf.location);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse the arguments of a subcommand, e.g. `fortune serve`, the same as `argh::from_env()`.
+fn parse_subcommand<T: FromArgs>(command: &str, args: &[String]) -> T {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    T::from_args(&[command], &args).unwrap_or_else(|early_exit| {
+        std::process::exit(match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                0
+            }
+            Err(()) => {
+                eprintln!(
+                    "{}\nRun {} --help for more information.",
+                    early_exit.output, command
+                );
+                1
+            }
+        })
+    })
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let result = match args.get(1).map(String::as_str) {
+        Some("serve") => serve::run(parse_subcommand("fortune serve", &args[2..])),
+        #[cfg(unix)]
+        Some("daemon") => daemon::run(parse_subcommand("fortune daemon", &args[2..])),
+        #[cfg(feature = "tui")]
+        Some("browse") => browse::run(parse_subcommand("fortune browse", &args[2..])),
+        #[cfg(not(feature = "tui"))]
+        Some("browse") => Err(anyhow::anyhow!(
+            "fortune browse is not built in, rebuild with: cargo build --features tui"
+        )),
+        _ => run(argh::from_env()),
+    };
+    if let Err(e) = result {
+        // argument errors have their own exit codes
+        match e.downcast_ref::<ArgError>() {
+            Some(arg_error) => {
+                eprintln!("fortune: {}", arg_error);
+                std::process::exit(arg_error.exit_code());
+            }
+            None => {
+                eprintln!("Error: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+fn run(args: Args) -> anyhow::Result<()> {
     // Debug output if requested
     if args.debug {
         env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
@@ -108,29 +306,91 @@ fn main() -> anyhow::Result<()> {
         debug!("args: {:?}", std::env::args().collect::<Vec<_>>());
     }
 
-    if args.no_utf8_translate {
-        anyhow::bail!("-u is not supported yet.");
+    // --list-packs: list embedded packs
+    if args.list_packs {
+        show_packs();
+        return Ok(());
     }
 
-    let normal = args.all || !args.offensive;
-    let offensive = args.all || args.offensive;
+    // --completions: the sources are listed by --complete-sources when completing
+    if let Some(shell) = &args.completions {
+        let completions =
+            Completions::from_args::<Args>("fortune", Some("fortune --complete-sources"));
+        print!("{}", completions.generate(shell.parse()?));
+        return Ok(());
+    }
+    if args.complete_sources {
+        for name in completions::source_names() {
+            println!("{}", name);
+        }
+        return Ok(());
+    }
 
-    let mut cabinet = CookieCabinet::from_string_list(&args.paths)?;
+    if args.no_utf8_translate {
+        anyhow::bail!("-u is not supported yet.");
+    }
 
-    for shelf in cabinet.shelves.iter_mut() {
-        //  if the shelf location is not point to embedded data and not exists, then check if it exists in embedded data
-        if !shelf.location.starts_with(EMBED_PREFIX) && !std::fs::exists(&shelf.location)? {
-            if Embedded::exists(&shelf.location) {
-                // update shelf location if necessary
-                shelf.location = Embedded::format_path(&shelf.location);
+    // --client: the daemon only selects fortunes, the other modes are always run locally
+    let is_selecting =
+        args.export.is_none() && args.stats.is_none() && !args.duplicates && !args.list_files;
+    if args.client && is_selecting {
+        #[cfg(unix)]
+        if let Some(cookies) = daemon::ask(&args) {
+            return if args.pattern.is_empty() {
+                match cookies.first() {
+                    Some(cookie) => show_fortune(cookie, &args),
+                    None => anyhow::bail!("Not found any fortune cookies"),
+                }
             } else {
-                anyhow::bail!("{} not found.", shelf.location);
-            }
+                show_matches(cookies.iter(), &args)
+            };
         }
+        #[cfg(not(unix))]
+        debug!("--client is only supported on Unix");
     }
 
+    let normal = args.all || !args.offensive;
+    let offensive = args.all || args.offensive;
+
+    let weight_mode = match &args.weights {
+        Some(mode) => mode.parse::<WeightMode>()?,
+        None => WeightMode::default(),
+    };
+    let mut cabinet = CookieCabinet::from_string_list(&args.paths, weight_mode)?;
+
+    let normalization = match &args.normalize {
+        Some(form) => Some(form.parse::<Normalization>()?),
+        None => None,
+    };
+
+    let walker = Walker::new(args.max_depth, args.follow_symlinks);
+    // -m: the search indexes narrow the cookies before the pattern is matched.
+    // The indexes are built from the text as is, so they are not used with --normalize.
+    let queries = args
+        .pattern
+        .iter()
+        .map(|p| Query::from_pattern(p))
+        .collect();
+    let query = if normalization.is_some() {
+        Query::All
+    } else if args.any {
+        Query::or(queries)
+    } else {
+        Query::and(queries)
+    };
+    prepare_shelves(&mut cabinet, &walker, &query)?;
+
+    let export_format = match &args.export {
+        Some(format) => Some(format.parse::<ExportFormat>()?),
+        None => None,
+    };
+    let stats_format = match &args.stats {
+        Some(format) => Some(format.parse::<StatsFormat>()?),
+        None => None,
+    };
+
     // Create filters based on command-line arguments
-    let filters = generate_filters(&args);
+    let filters = generate_filters(&args, normalization)?;
 
     // Collect all fortune files
     cabinet.load(normal, offensive)?;
@@ -140,37 +400,35 @@ fn main() -> anyhow::Result<()> {
         cabinet.filter(&filters)?;
     }
 
+    // --export: write all selected cookies to stdout
+    if let Some(format) = export_format {
+        Exporter::export(&cabinet, format, &mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+
+    // --stats: show statistics of all selected cookies, with the -n cutoff
+    if let Some(format) = stats_format {
+        Stats::from_cabinet(&cabinet, args.length).write(format, &mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+
+    // --duplicates: report the duplicated cookies
+    if args.duplicates {
+        Deduper::report(&Deduper::find(&cabinet), &mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+
     // -m pattern matching
     //  1. if -m is given, show all matching cookies
     //  2. output cookie file name in '\n%\n' delimiter format to stderr
     //  3. output the cookie in '\n%\n' delimiter format to stdout
-    if args.pattern.is_some() {
-        let mut found = false;
-        for shelf in cabinet.iter() {
-            for jar in shelf.jars.iter() {
-                let cookies: Vec<&Cookie> = jar
-                    .cookies
-                    .iter()
-                    .filter(|cookie| filters.filter(&cookie.content))
-                    .collect();
-                if !cookies.is_empty() {
-                    found = true;
-                    eprintln!("({})\n%", jar.location);
-                    for cookie in cookies.iter() {
-                        println!("{}\n%", cookie.content);
-                    }
-                }
-            }
-        }
-
-        if found {
-            return Ok(());
-        } else {
-            anyhow::bail!(
-                "No matching fortune cookies for pattern: {}",
-                args.pattern.unwrap()
-            );
-        }
+    if !args.pattern.is_empty() {
+        let cookies = cabinet
+            .iter()
+            .flat_map(|shelf| shelf.jars.iter())
+            .flat_map(|jar| jar.cookies.iter())
+            .filter(|cookie| filters.filter(&cookie.content));
+        return show_matches(cookies, &args);
     }
 
     // return exit code 1 if empty cookies
@@ -185,13 +443,29 @@ fn main() -> anyhow::Result<()> {
         for shelf in cabinet.iter() {
             eprintln!("{:5.2}% {}", shelf.probability, shelf.location);
             for jar in shelf.jars.iter() {
-                eprintln!("    {:5.2}% {}", jar.probability, jar.location);
+                let mut line = format!("    {:5.2}% {}", jar.probability, jar.location);
+                if jar.weight != 1.0 {
+                    line.push_str(&format!(" (weight: {})", jar.weight));
+                }
+                if !jar.layer.is_empty() {
+                    line.push_str(&format!(" [{}]", jar.layer));
+                }
+                eprintln!("{}", line);
             }
         }
         return Ok(());
     }
 
-    let cookie: &Cookie = cabinet.choose(&mut rand::thread_rng()).unwrap();
+    // e.g. all the jars are weighted zero by weights.toml
+    let cookie: &Cookie = match cabinet.choose(&mut rand::thread_rng()) {
+        Some(cookie) => cookie,
+        None => anyhow::bail!("Not found any fortune cookies"),
+    };
+    show_fortune(cookie, &args)
+}
+
+/// Show the fortune, and wait (-w) based on its length.
+fn show_fortune(cookie: &Cookie, args: &Args) -> anyhow::Result<()> {
     show_cookie(cookie, args.show_file);
     if args.wait {
         let wait_time = std::cmp::max(
@@ -201,6 +475,25 @@ fn main() -> anyhow::Result<()> {
         debug!("Wait time: {}s", wait_time);
         std::thread::sleep(std::time::Duration::from_secs(wait_time));
     }
+    Ok(())
+}
 
+/// Show all matching cookies of -m, each file name to stderr and the cookies to stdout,
+/// both in the '\n%\n' delimiter format.
+fn show_matches<'a>(cookies: impl Iterator<Item = &'a Cookie>, args: &Args) -> anyhow::Result<()> {
+    let mut location: Option<&str> = None;
+    for cookie in cookies {
+        if location != Some(cookie.location.as_str()) {
+            location = Some(&cookie.location);
+            eprintln!("({})\n%", cookie.location);
+        }
+        println!("{}\n%", cookie.content);
+    }
+    if location.is_none() {
+        anyhow::bail!(
+            "No matching fortune cookies for pattern: {}",
+            args.pattern.join(if args.any { " | " } else { " & " })
+        );
+    }
     Ok(())
 }
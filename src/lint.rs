@@ -0,0 +1,130 @@
+pub mod cookie;
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use argh::FromArgs;
+use cookie::atomic;
+use cookie::compress::Compression;
+use cookie::lint::{Check, Linter};
+use cookie::walk::Walker;
+
+#[derive(FromArgs)]
+/// Check the fortune cookie files for problems.
+struct Args {
+    /// change delimiting character from '%' to specified character
+    #[argh(option, short = 'c')]
+    delimch: Option<char>,
+
+    /// fix the safe problems in place: line endings, delimiters, empty cookies
+    #[argh(switch)]
+    fix: bool,
+
+    /// maximum length of a line in characters (default: 80)
+    #[argh(option, default = "80")]
+    max_line_length: usize,
+
+    /// do not report the given check, e.g. 'tab' (repeatable)
+    #[argh(option)]
+    allow: Vec<String>,
+
+    /// only show the summary
+    #[argh(switch, short = 's')]
+    silent: bool,
+
+    /// cookie files or directories to check
+    #[argh(positional)]
+    paths: Vec<String>,
+}
+
+fn main() {
+    match run(argh::from_env()) {
+        Ok(true) => {}
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Lint all files, and return whether they are clean.
+fn run(args: Args) -> Result<bool> {
+    if args.paths.is_empty() {
+        anyhow::bail!("No cookie files or directories given.");
+    }
+    let linter = Linter {
+        delim: args.delimch.unwrap_or('%'),
+        max_line_length: args.max_line_length,
+        allowed: args
+            .allow
+            .iter()
+            .map(|c| c.parse::<Check>())
+            .collect::<Result<Vec<Check>>>()?,
+    };
+
+    // directories are walked with the same rules as fortune
+    let walker = Walker::default();
+    let mut files: Vec<PathBuf> = Vec::new();
+    for path in args.paths.iter() {
+        let path = Path::new(path);
+        if path.is_dir() {
+            files.extend(walker.walk(path)?);
+        } else if path.is_file() {
+            files.push(path.to_path_buf());
+        } else {
+            anyhow::bail!("{} not found.", path.display());
+        }
+    }
+
+    let mut num_problems = 0;
+    let mut num_fixed = 0;
+    let mut num_dirty_files = 0;
+    for file in files.iter() {
+        let name = file.to_string_lossy();
+        let mut bytes = std::fs::read(file)?;
+        let compression = Compression::detect(&name, &bytes);
+        if compression != Compression::None {
+            // compressed files are checked, but never rewritten
+            bytes = compression.decompress(&bytes)?;
+        } else if args.fix {
+            let problems = linter.lint(&bytes);
+            let is_fixable = problems.iter().any(|p| p.check.is_fixable())
+                && problems.iter().all(|p| p.check != Check::InvalidUtf8);
+            if is_fixable {
+                let fixed = linter.fix(&String::from_utf8_lossy(&bytes))?;
+                atomic::write_file(file, fixed.as_bytes())?;
+                num_fixed += problems.iter().filter(|p| p.check.is_fixable()).count();
+                bytes = fixed.into_bytes();
+            }
+        }
+
+        let problems = linter.lint(&bytes);
+        if !problems.is_empty() {
+            num_dirty_files += 1;
+            num_problems += problems.len();
+        }
+        if !args.silent {
+            for problem in problems.iter() {
+                println!("{}:{}: {}", name, problem.line, problem);
+            }
+        }
+    }
+
+    if args.fix {
+        println!(
+            "Fixed {} problem{}",
+            num_fixed,
+            if num_fixed == 1 { "" } else { "s" }
+        );
+    }
+    println!(
+        "{} problem{} in {} of {} file{}",
+        num_problems,
+        if num_problems == 1 { "" } else { "s" },
+        num_dirty_files,
+        files.len(),
+        if files.len() == 1 { "" } else { "s" }
+    );
+    Ok(num_problems == 0)
+}
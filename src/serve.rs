@@ -0,0 +1,686 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use argh::FromArgs;
+use env_logger::Env;
+use log::debug;
+use serde_json::{json, Value};
+
+use crate::cookie::{
+    embed::EMBED_PREFIX, is_offensive_jar, normalize::Normalization, search::Query, walk::Walker,
+    Cookie, CookieCabinet, CookieSelection, WeightMode,
+};
+use crate::{generate_filters, prepare_shelves, Args};
+
+/// The `fortune` switches which select the fortunes, and can be given per request.
+const FILTER_SWITCHES: [&str; 8] = ["a", "c", "e", "i", "l", "o", "s", "any"];
+/// The `fortune` options which select the fortunes, and can be given per request.
+const FILTER_OPTIONS: [&str; 4] = ["m", "n", "exclude-pattern", "normalize"];
+
+#[derive(FromArgs)]
+/// Serve fortunes over HTTP. The sources are loaded once, and reloaded when they change.
+pub struct ServeArgs {
+    /// address to listen on (default: 127.0.0.1:8080)
+    #[argh(option, default = "String::from(\"127.0.0.1:8080\")")]
+    listen: String,
+
+    /// seconds between the checks for changed sources (default: 2)
+    #[argh(option, default = "2")]
+    reload_interval: u64,
+
+    /// how the weights are given: percent (default), relative
+    #[argh(option)]
+    weights: Option<String>,
+
+    /// maximum depth of the fortune files to load from directories
+    #[argh(option)]
+    max_depth: Option<usize>,
+
+    /// follow symbolic links to directories
+    #[argh(switch)]
+    follow_symlinks: bool,
+
+    /// enable additional debugging output
+    #[argh(switch, short = 'D')]
+    debug: bool,
+
+    /// [[n%] file/directory/all]
+    #[argh(positional)]
+    paths: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    Json,
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn new(format: Format, status: u16, text: String, value: Value) -> Self {
+        match format {
+            Format::Text => Response {
+                status,
+                content_type: "text/plain; charset=utf-8",
+                body: text,
+            },
+            Format::Json => Response {
+                status,
+                content_type: "application/json",
+                body: format!("{}\n", value),
+            },
+        }
+    }
+
+    fn error(format: Format, status: u16, message: &str) -> Self {
+        Response::new(
+            format,
+            status,
+            format!("{}\n", message),
+            json!({ "error": message }),
+        )
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let reason = match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            _ => "Internal Server Error",
+        };
+        let mut bytes = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            reason,
+            self.content_type,
+            self.body.len()
+        )
+        .into_bytes();
+        bytes.extend_from_slice(self.body.as_bytes());
+        bytes
+    }
+}
+
+/// Size and modification time of each source file, to find out the changed sources.
+type Sources = Vec<(PathBuf, SystemTime, u64)>;
+
+/// The loaded sources, shared by the requests which are handled concurrently.
+pub(crate) struct Server {
+    shelves: CookieCabinet,  // the shelves before loading, to reload from
+    weight_mode: WeightMode, // how the weights of the requests are given
+    cabinet: RwLock<Arc<CookieCabinet>>, // replaced as a whole when reloaded
+    sources: Mutex<Sources>,
+    reload_interval: Duration,
+    checked: Mutex<Instant>,
+}
+
+impl Server {
+    /// Load the sources, which are given the same as the `fortune` command line.
+    pub(crate) fn load(
+        paths: &[String],
+        weights: Option<&str>,
+        walker: &Walker,
+        reload_interval: Duration,
+    ) -> Result<Self> {
+        let weight_mode = match weights {
+            Some(mode) => mode.parse::<WeightMode>()?,
+            None => WeightMode::default(),
+        };
+        let mut shelves = CookieCabinet::from_string_list(paths, weight_mode)?;
+        prepare_shelves(&mut shelves, walker, &Query::All)?;
+        Server::new(shelves, weight_mode, reload_interval)
+    }
+
+    fn new(
+        shelves: CookieCabinet,
+        weight_mode: WeightMode,
+        reload_interval: Duration,
+    ) -> Result<Self> {
+        let server = Server {
+            cabinet: RwLock::new(Arc::new(CookieCabinet::default())),
+            sources: Mutex::new(Sources::new()),
+            shelves,
+            weight_mode,
+            reload_interval,
+            checked: Mutex::new(Instant::now()),
+        };
+        server.reload(&mut server.sources.lock().unwrap())?;
+        Ok(server)
+    }
+
+    /// The cabinet as currently loaded, which is not changed by the reloads.
+    pub(crate) fn cabinet(&self) -> Arc<CookieCabinet> {
+        self.cabinet.read().unwrap().clone()
+    }
+
+    /// Load all the jars, both normal and offensive, which are selected per request.
+    fn reload(&self, sources: &mut Sources) -> Result<()> {
+        let found = find_sources(&self.shelves)?;
+        let mut cabinet = self.shelves.clone();
+        cabinet.load(true, true)?;
+        debug!(
+            "Server::reload(): {} cookies in {} jars",
+            cabinet.num_of_cookies(),
+            cabinet.num_of_jars()
+        );
+        *self.cabinet.write().unwrap() = Arc::new(cabinet);
+        *sources = found;
+        Ok(())
+    }
+
+    pub(crate) fn reload_if_changed(&self) -> Result<()> {
+        // the requests meanwhile wait for the check, and are served the reloaded cabinet
+        let mut checked = self.checked.lock().unwrap();
+        if checked.elapsed() < self.reload_interval {
+            return Ok(());
+        }
+        *checked = Instant::now();
+        let mut sources = self.sources.lock().unwrap();
+        if find_sources(&self.shelves)? != *sources {
+            self.reload(&mut sources)?;
+        }
+        Ok(())
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line)?;
+        // the headers are not used
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                break;
+            }
+        }
+        let response = self.respond(&request_line);
+        debug!("{} => {}", request_line.trim(), response.status);
+        stream.write_all(&response.to_bytes())?;
+        Ok(())
+    }
+
+    fn respond(&self, request_line: &str) -> Response {
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or("/");
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let mut params = parse_query(query);
+
+        let format = match params.iter().position(|(k, _)| k == "format") {
+            Some(i) => match params.remove(i).1.as_str() {
+                "text" => Format::Text,
+                "json" => Format::Json,
+                f => {
+                    let message = format!("Unknown format: {} (expected one of: text, json)", f);
+                    return Response::error(Format::Text, 400, &message);
+                }
+            },
+            None => Format::Text,
+        };
+        let weights = params
+            .iter()
+            .position(|(k, _)| k == "weights")
+            .map(|i| params.remove(i).1);
+        if method != "GET" {
+            return Response::error(format, 405, "Only GET requests are supported");
+        }
+        if let Err(e) = self.reload_if_changed() {
+            return Response::error(format, 500, &format!("Failed to reload: {:#}", e));
+        }
+        match self.route(path, &params, weights.as_deref(), format) {
+            Ok(response) => response,
+            Err(e) => Response::error(format, 400, &format!("{:#}", e)),
+        }
+    }
+
+    fn route(
+        &self,
+        path: &str,
+        params: &[(String, String)],
+        weights: Option<&str>,
+        format: Format,
+    ) -> Result<Response> {
+        let path = path.trim_end_matches('/');
+        let cabinet = self.cabinet();
+        // a cookie by its ID, regardless of the filters
+        if let Some(id) = path.strip_prefix("/fortune/") {
+            let id = percent_decode(id);
+            let cookie = id.rsplit_once('/').and_then(|(jar, offset)| {
+                let offset = offset.parse::<u64>().ok()?;
+                cabinet
+                    .iter()
+                    .flat_map(|shelf| shelf.iter())
+                    .filter(|j| j.location == jar)
+                    .flat_map(|j| j.iter())
+                    .find(|c| c.offset == offset)
+            });
+            return Ok(match cookie {
+                Some(cookie) => Response::new(
+                    format,
+                    200,
+                    format!("{}\n", cookie.content),
+                    cookie_value(cookie),
+                ),
+                None => Response::error(format, 404, &format!("No fortune cookie: {}", id)),
+            });
+        }
+
+        let args = request_args(params)?;
+        let mut selection = self.select(&cabinet, &args)?;
+        if let Some(weights) = weights {
+            let items: Vec<String> = weights.split_whitespace().map(String::from).collect();
+            if items.is_empty() {
+                anyhow::bail!(
+                    "Missing sources of the weights, e.g. weights=30%25+/path/to/fortunes"
+                );
+            }
+            selection = self.reweigh(selection, &items, self.weight_mode)?;
+        }
+        match path {
+            "" | "/fortune" => {
+                selection.calculate_prob(args.equal_size);
+                // e.g. no jar is left, or all are weighted zero by weights.toml
+                let cookie = match selection.choose(&mut rand::thread_rng()) {
+                    Some(cookie) => cookie,
+                    None => {
+                        return Ok(Response::error(
+                            format,
+                            404,
+                            "Not found any fortune cookies",
+                        ))
+                    }
+                };
+                let mut text = String::new();
+                if args.show_file {
+                    text.push_str(&format!("({})\n%\n", cookie.location));
+                }
+                text.push_str(&format!("{}\n", cookie.content));
+                Ok(Response::new(format, 200, text, cookie_value(cookie)))
+            }
+            "/search" => {
+                if args.pattern.is_empty() {
+                    anyhow::bail!("Missing pattern, e.g. /search?m=PATTERN");
+                }
+                let mut text = String::new();
+                let mut values = Vec::new();
+                for jar in selection.iter().flat_map(|shelf| shelf.iter()) {
+                    if args.show_file {
+                        text.push_str(&format!("({})\n%\n", jar.jar.location));
+                    }
+                    for cookie in jar.iter() {
+                        text.push_str(&format!("{}\n%\n", cookie.content));
+                        values.push(cookie_value(cookie));
+                    }
+                }
+                Ok(Response::new(format, 200, text, Value::Array(values)))
+            }
+            "/files" => {
+                selection.calculate_prob(args.equal_size);
+                let mut text = String::new();
+                let mut values = Vec::new();
+                for shelf in selection.iter() {
+                    let location = &shelf.shelf.location;
+                    text.push_str(&format!("{:5.2}% {}\n", shelf.probability, location));
+                    let mut jars = Vec::new();
+                    for jar in shelf.iter() {
+                        let location = &jar.jar.location;
+                        text.push_str(&format!("    {:5.2}% {}\n", jar.probability, location));
+                        jars.push(json!({
+                            "jar": location,
+                            "probability": jar.probability,
+                            "weight": jar.jar.weight,
+                            "cookies": jar.num_of_cookies(),
+                        }));
+                    }
+                    values.push(json!({
+                        "shelf": shelf.shelf.location,
+                        "probability": shelf.probability,
+                        "jars": jars,
+                    }));
+                }
+                Ok(Response::new(format, 200, text, Value::Array(values)))
+            }
+            _ => Ok(Response::error(
+                format,
+                404,
+                &format!("Unknown route: {}", path),
+            )),
+        }
+    }
+
+    /// Select the jars and cookies of the cabinet by the request, by reference,
+    /// which leaves the shared cabinet as is.
+    pub(crate) fn select<'a>(
+        &self,
+        cabinet: &'a CookieCabinet,
+        args: &Args,
+    ) -> Result<CookieSelection<'a>> {
+        let normalization = match &args.normalize {
+            Some(form) => Some(form.parse::<Normalization>()?),
+            None => None,
+        };
+        let filters = generate_filters(args, normalization)?;
+        let normal = args.all || !args.offensive;
+        let offensive = args.all || args.offensive;
+
+        let mut selection = cabinet.select(&filters);
+        for shelf in selection.shelves.iter_mut() {
+            // an explicitly given file is always selected, the same as the command line
+            let location = &shelf.shelf.location;
+            shelf.jars.retain(|j| {
+                &j.jar.location == location
+                    || if is_offensive_jar(location, &j.jar.location) {
+                        offensive
+                    } else {
+                        normal
+                    }
+            });
+        }
+        selection.shelves.retain(|s| !s.jars.is_empty());
+        Ok(selection)
+    }
+
+    /// Weigh the served sources of a request the same as the command line, e.g.
+    /// `30% /path/a /path/b`. The sources not listed are left out.
+    pub(crate) fn reweigh<'a>(
+        &self,
+        selection: CookieSelection<'a>,
+        items: &[String],
+        mode: WeightMode,
+    ) -> Result<CookieSelection<'a>> {
+        let requested = CookieCabinet::from_string_list(items, mode)?;
+        let mut shelves = Vec::new();
+        for shelf in requested.iter() {
+            let location = match self
+                .shelves
+                .iter()
+                .find(|s| is_same_source(&s.location, &shelf.location))
+            {
+                Some(served) => &served.location,
+                None => anyhow::bail!("Not a served source: {}", shelf.location),
+            };
+            // the sources without any selected cookies are skipped, the same as the filters
+            if let Some(selected) = selection.iter().find(|s| &s.shelf.location == location) {
+                let mut selected = selected.clone();
+                selected.probability = shelf.probability;
+                shelves.push(selected);
+            }
+        }
+        Ok(CookieSelection { shelves })
+    }
+}
+
+/// Whether the locations are the same source, e.g. a relative and an absolute path of a file.
+fn is_same_source(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    if a.starts_with(EMBED_PREFIX) || b.starts_with(EMBED_PREFIX) {
+        return false;
+    }
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+pub fn run(args: ServeArgs) -> Result<()> {
+    if args.debug {
+        env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
+    }
+
+    let server = Server::load(
+        &args.paths,
+        args.weights.as_deref(),
+        &Walker::new(args.max_depth, args.follow_symlinks),
+        Duration::from_secs(args.reload_interval),
+    )?;
+
+    let listener = TcpListener::bind(&args.listen)
+        .with_context(|| format!("Failed to listen on {}", args.listen))?;
+    println!("Listening on http://{}", listener.local_addr()?);
+    std::io::stdout().flush()?;
+
+    // each request is handled by its own thread, and they all share the loaded cabinet
+    let server = Arc::new(server);
+    for stream in listener.incoming() {
+        let server = server.clone();
+        std::thread::spawn(move || {
+            let result = stream
+                .map_err(anyhow::Error::from)
+                .and_then(|stream| server.handle(stream));
+            if let Err(e) = result {
+                debug!("Failed to handle the request: {:#}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Convert the query parameters to the `fortune` arguments, e.g. `?s&m=apple` => `-s -m apple`.
+fn request_args(params: &[(String, String)]) -> Result<Args> {
+    let mut tokens: Vec<String> = Vec::new();
+    for (key, value) in params.iter() {
+        let flag = if key.len() == 1 {
+            format!("-{}", key)
+        } else {
+            format!("--{}", key)
+        };
+        if FILTER_SWITCHES.contains(&key.as_str()) {
+            match value.as_str() {
+                "" | "1" | "true" => tokens.push(flag),
+                "0" | "false" => {}
+                _ => anyhow::bail!("Invalid value of '{}': {}", key, value),
+            }
+        } else if FILTER_OPTIONS.contains(&key.as_str()) {
+            tokens.push(flag);
+            tokens.push(value.to_string());
+        } else {
+            anyhow::bail!("Unknown parameter: {}", key);
+        }
+    }
+    filter_args(&tokens)
+}
+
+/// Parse the `fortune` arguments of a request, which may only select the fortunes.
+/// The sources after `--`, e.g. `-s -- 30% /path/a /path/b`, are the paths of the arguments.
+pub(crate) fn filter_args(tokens: &[String]) -> Result<Args> {
+    let mut i = 0;
+    while i < tokens.len() && tokens[i] != "--" {
+        let name = tokens[i]
+            .strip_prefix("--")
+            .or_else(|| tokens[i].strip_prefix('-').filter(|n| n.len() == 1))
+            .unwrap_or_default();
+        if FILTER_OPTIONS.contains(&name) {
+            i += 1;
+        } else if !FILTER_SWITCHES.contains(&name) {
+            anyhow::bail!("Unsupported argument: {}", tokens[i]);
+        }
+        i += 1;
+    }
+    let tokens: Vec<&str> = tokens.iter().map(String::as_str).collect();
+    Args::from_args(&["fortune"], &tokens).map_err(|e| anyhow::anyhow!("{}", e.output.trim()))
+}
+
+pub(crate) fn cookie_value(cookie: &Cookie) -> Value {
+    json!({
+        "id": format!("{}/{}", cookie.location, cookie.offset),
+        "jar": cookie.location,
+        "offset": cookie.offset,
+        "content": cookie.content,
+        "metadata": cookie.metadata,
+    })
+}
+
+/// Find the source files of the shelves in the filesystem. The embedded data never changes.
+fn find_sources(shelves: &CookieCabinet) -> Result<Sources> {
+    let mut files: Vec<PathBuf> = Vec::new();
+    for shelf in shelves.iter() {
+        let path = Path::new(&shelf.location);
+        if shelf.location.starts_with(EMBED_PREFIX) {
+            continue;
+        } else if path.is_dir() {
+            files.extend(shelf.walker.walk(path)?);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files
+        .into_iter()
+        .filter_map(|f| {
+            let metadata = std::fs::metadata(&f).ok()?;
+            Some((f, metadata.modified().ok()?, metadata.len()))
+        })
+        .collect())
+}
+
+/// Parse the query string into the decoded key-value pairs, e.g. `m=a+b&s` => `[(m, a b), (s, )]`.
+fn parse_query(query: &str) -> Vec<(String, String)> {
+    query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (key, value) = p.split_once('=').unwrap_or((p, ""));
+            (percent_decode(key), percent_decode(value))
+        })
+        .collect()
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded: Vec<u8> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = bytes
+                    .get(i + 1..i + 3)
+                    .and_then(|h| std::str::from_utf8(h).ok());
+                match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        let testcases = [
+            ("", vec![]),
+            ("s", vec![("s", "")]),
+            ("m=a+b&s=1", vec![("m", "a b"), ("s", "1")]),
+            ("m=%E5%AD%B8%2F&i", vec![("m", "學/"), ("i", "")]),
+            ("m=100%", vec![("m", "100%")]),
+            ("m=%zz", vec![("m", "%zz")]),
+        ];
+        for (query, expected) in testcases.iter() {
+            let expected: Vec<(String, String)> = expected
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            assert_eq!(expected, parse_query(query), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_request_args() {
+        let params = parse_query("s&i=true&o=0&m=apple&m=pie&n=100&any");
+        let args = request_args(&params).unwrap();
+        assert!(args.short_only && args.ignore_case && args.any);
+        assert!(!args.offensive);
+        assert_eq!(vec!["apple", "pie"], args.pattern);
+        assert_eq!(100, args.length);
+        assert!(args.paths.is_empty());
+
+        assert!(request_args(&parse_query("export=json")).is_err());
+        assert!(request_args(&parse_query("s=yes")).is_err());
+        assert!(request_args(&parse_query("n=many")).is_err());
+    }
+
+    #[test]
+    fn test_route_weights() {
+        let paths = ["tests/data".to_string(), "tests/data2".to_string()];
+        let walker = Walker::default();
+        let interval = Duration::from_secs(60);
+        let testcases = [
+            (
+                None,
+                "weights=30%25+tests/data+tests/data2",
+                vec!["30.00% tests/data", "70.00% tests/data2"],
+            ),
+            (None, "weights=tests/data2&e", vec!["100.00% tests/data2"]),
+            (
+                Some("relative"),
+                "weights=1+tests/data+3+tests/data2",
+                vec!["25.00% tests/data", "75.00% tests/data2"],
+            ),
+        ];
+        for (mode, query, expected) in testcases.iter() {
+            let server = Server::load(&paths, *mode, &walker, interval).unwrap();
+            let mut params = parse_query(query);
+            let weights = params.remove(0).1;
+            let response = server
+                .route("/files", &params, Some(&weights), Format::Text)
+                .unwrap();
+            assert_eq!(200, response.status, "{}", query);
+            let lines: Vec<&str> = response.body.lines().map(str::trim).collect();
+            for line in expected.iter() {
+                assert!(lines.contains(line), "{}\n{}", query, response.body);
+            }
+            // the sources not listed are left out
+            let sources = lines.iter().filter(|l| l.contains("% tests/data")).count();
+            assert_eq!(expected.len(), sources, "{}\n{}", query, response.body);
+        }
+
+        // the weights follow the command line, and only the served sources can be weighed
+        let server = Server::load(&paths, None, &walker, interval).unwrap();
+        for weights in ["30% tests/data", "tests/data3", "50%", ""] {
+            assert!(
+                server
+                    .route("/fortune", &[], Some(weights), Format::Text)
+                    .is_err(),
+                "{}",
+                weights
+            );
+        }
+    }
+
+    #[test]
+    fn test_filter_args() {
+        let tokens =
+            |args: &str| -> Vec<String> { args.split_whitespace().map(String::from).collect() };
+        let args = filter_args(&tokens("-s -m -apple --exclude-pattern pie --any")).unwrap();
+        assert!(args.short_only && args.any);
+        assert_eq!(vec!["-apple"], args.pattern);
+        assert_eq!(vec!["pie"], args.exclude_pattern);
+
+        assert!(filter_args(&tokens("-s /etc")).is_err());
+        assert!(filter_args(&tokens("--export json")).is_err());
+        assert!(filter_args(&tokens("-sm apple")).is_err());
+        assert!(filter_args(&tokens("-m")).is_err());
+    }
+}
@@ -1,33 +1,51 @@
 pub mod cookie;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use argh::FromArgs;
-use cookie::serializer::Serializer;
+use cookie::atomic;
+use cookie::collate::Collation;
+use cookie::completions::Completions;
+use cookie::compress;
+use cookie::datfile::{dat_path, order, read_dat, rebuild};
+use cookie::dedupe::Deduper;
+use cookie::import::{FieldMapping, Importer, InputFormat};
+use cookie::search::SearchIndex;
+use cookie::serializer::{Serializer, SerializerType};
+use cookie::walk::Walker;
 use cookie::CookieJar;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use std::collections::BTreeMap;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How often the watched directory is checked for changed cookie files.
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(FromArgs)]
 /// Create a data file for the fortune program.
 struct Args {
-    /// input file containing strings separated by delimiter
-    #[argh(positional)]
-    infile: String,
+    /// input file containing strings separated by delimiter, and optionally the output data
+    /// file (default: infile.dat); or the directories with -R
+    #[argh(positional, arg_name = "file")]
+    files: Vec<String>,
 
-    /// output data file (default: infile.dat)
-    #[argh(positional)]
-    outfile: Option<String>,
+    /// build the data files of all the cookie files under the directories
+    #[argh(switch, short = 'R')]
+    recursive: bool,
 
     /// change delimiting character from '%' to specified character
     #[argh(option, short = 'c')]
     delimch: Option<char>,
 
+    /// parse the comment lines of the strings, e.g. '%% author: Confucius', as metadata
+    #[argh(switch, short = 'C')]
+    cflag: bool,
+
     /// silent mode - do not show summary of data processed
     #[argh(switch, short = 's')]
     sflag: bool,
 
-    /// order the strings in alphabetical order
+    /// order the strings in alphabetical order, skipping the leading non-alphanumeric characters
     #[argh(switch, short = 'o')]
     oflag: bool,
 
@@ -35,6 +53,11 @@ struct Args {
     #[argh(switch, short = 'i')]
     iflag: bool,
 
+    /// order the strings by the collation of the locale, e.g. de, sv, zh (implies -o, needs the
+    /// collate feature)
+    #[argh(option)]
+    collate: Option<String>,
+
     /// randomize the order of the strings
     #[argh(switch, short = 'r')]
     rflag: bool,
@@ -47,22 +70,93 @@ struct Args {
     #[argh(switch, short = 'l')]
     lflag: bool,
 
+    /// drop the duplicated strings (ignoring whitespace, case and punctuation)
+    #[argh(switch)]
+    dedupe: bool,
+
+    /// also build the search index (infile.idx), which speeds up 'fortune -m'
+    #[argh(switch)]
+    index: bool,
+
     /// platform to use for serialization: homebrew, linux, freebsd
     #[argh(option)]
     platform: Option<String>,
+
+    /// input format: text, json, csv, yaml, markdown-list, lines (default: text)
+    #[argh(option)]
+    input_format: Option<String>,
+
+    /// field (or column) of the cookie text in json/csv/yaml input (default: text)
+    #[argh(option)]
+    text_field: Option<String>,
+
+    /// field (or column) of the author in json/csv/yaml input
+    #[argh(option)]
+    author_field: Option<String>,
+
+    /// field (or column) of the tags in json/csv/yaml input
+    #[argh(option)]
+    tag_field: Option<String>,
+
+    /// watch the cookie files in the directory, and rebuild their data files when they change
+    #[argh(option)]
+    watch: Option<String>,
+
+    /// print the shell completion script for: bash, zsh, fish
+    #[argh(option)]
+    completions: Option<String>,
 }
 
 /// Main function that processes fortune cookie files.
 /// Handles command line arguments and orchestrates the file processing.
 fn main() -> Result<()> {
     // Parse command-line arguments
-    let args = argh::from_env::<Args>();
-    let infile = args.infile.trim_end_matches(".dat").to_string();
-    let outfile = args
-        .outfile
-        .unwrap_or_else(|| format!("{}.dat", args.infile.trim_end_matches(".dat")));
+    let mut args = argh::from_env::<Args>();
+    // --collate is an order of the strings, which is not applied without -o otherwise
+    args.oflag |= args.collate.is_some();
+
+    // --completions: the input file is not needed
+    if let Some(shell) = &args.completions {
+        let completions = Completions::from_args::<Args>("strfile", None);
+        print!("{}", completions.generate(shell.parse()?));
+        return Ok(());
+    }
+
+    // --watch: keep the existing data files up to date, until interrupted
+    if let Some(dir) = &args.watch {
+        return watch(Path::new(dir), &args);
+    }
+
+    // -R: build the data files of whole directory trees
+    if args.recursive {
+        return index_dirs(&args);
+    }
+
+    let (input, output) = match args.files.as_slice() {
+        [input] => (input.clone(), None),
+        [input, output] => (input.clone(), Some(output.clone())),
+        [] => anyhow::bail!("No input file given."),
+        _ => anyhow::bail!("Too many files given: {}", args.files.join(" ")),
+    };
+    let input_format = args
+        .input_format
+        .as_deref()
+        .unwrap_or("text")
+        .parse::<InputFormat>()?;
+    let infile = input.trim_end_matches(".dat").to_string();
+    let outfile = output.unwrap_or_else(|| {
+        if input_format == InputFormat::Text {
+            // e.g. fortunes.gz => fortunes.dat
+            let infile = compress::strip_suffix(&input);
+            format!("{}.dat", infile.trim_end_matches(".dat"))
+        } else {
+            // e.g. quotes.json => quotes.dat
+            format!("{}.dat", Path::new(&input).with_extension("").display())
+        }
+    });
     let delimch = args.delimch.unwrap_or('%');
     let platform = args.platform.unwrap_or_else(|| "".to_string());
+    let collation = Collation::new(args.collate.as_deref(), args.iflag)?;
 
     // If -l flag is set, load and display data file
     if args.lflag {
@@ -73,26 +167,60 @@ fn main() -> Result<()> {
     }
 
     // Parse input cookie file
-    let mut jar = CookieJar::from_text_file(&infile, delimch)?;
+    let mut jar = if input_format == InputFormat::Text {
+        CookieJar::from_text_file(&infile, delimch)?
+    } else {
+        // Import the records, and write them as a normalized cookie file next to the data file
+        let textfile = outfile.trim_end_matches(".dat").to_string();
+        if Path::new(&textfile) == Path::new(&input) {
+            anyhow::bail!(
+                "Error: the cookie file would overwrite the input file: {}",
+                textfile
+            );
+        }
+        let mapping = FieldMapping {
+            text: args.text_field.unwrap_or_else(|| "text".to_string()),
+            author: args.author_field,
+            tags: args.tag_field,
+        };
+        let content =
+            std::fs::read_to_string(&input).with_context(|| format!("Failed to read {}", input))?;
+        let jar = Importer::import(&content, input_format, &mapping, &textfile, delimch)?;
+        atomic::write_file(Path::new(&textfile), jar.to_text().as_bytes())?;
+        if !args.sflag {
+            println!("'{}' created", textfile);
+        }
+        jar
+    };
 
-    // Apply ordering if -o flag is set
-    if args.oflag {
-        jar.cookies.sort_by(|a, b| {
-            if args.iflag {
-                a.content.to_lowercase().cmp(&b.content.to_lowercase())
-            } else {
-                a.content.cmp(&b.content)
-            }
-        });
-        jar.flags |= cookie::FLAGS_ORDERED;
+    // Parse the comment lines if -C flag is set
+    if args.cflag {
+        jar.parse_comments();
     }
 
-    // Randomize if -r flag is set
-    if args.rflag {
-        jar.cookies.shuffle(&mut thread_rng());
-        jar.flags |= cookie::FLAGS_RANDOMIZED;
+    // Build the search index of the cookie file, in the order which fortune reads it
+    if args.index {
+        let textfile = if input_format == InputFormat::Text {
+            infile.clone()
+        } else {
+            outfile.trim_end_matches(".dat").to_string()
+        };
+        let path = SearchIndex::from_jar(&jar).write(&textfile)?;
+        if !args.sflag {
+            println!("'{}' created", path);
+        }
     }
 
+    // Drop duplicates if --dedupe flag is set
+    let num_duplicates = if args.dedupe {
+        Deduper::dedupe_jar(&mut jar)
+    } else {
+        0
+    };
+
+    // Apply ordering if -o flag is set, or randomize if -r flag is set
+    order(&mut jar, args.oflag.then_some(&collation), args.rflag);
+
     // Set rotated flag if -x flag is set
     if args.xflag {
         jar.flags |= cookie::FLAGS_ROTATED;
@@ -100,13 +228,7 @@ fn main() -> Result<()> {
 
     // Write output data file
     let bytes = Serializer::to_bytes(&jar, &Serializer::get_type_by_platform(&platform));
-    let mut f = std::fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(&outfile)
-        .expect(format!("Error opening output file: {}", outfile).as_str());
-    f.write_all(&bytes).unwrap();
+    atomic::write_file(Path::new(&outfile), &bytes)?;
 
     // Display summary unless -s flag is set
     if !args.sflag {
@@ -116,6 +238,13 @@ fn main() -> Result<()> {
         } else {
             println!("There were {} strings", jar.cookies.len());
         }
+        if args.dedupe {
+            println!(
+                "Dropped {} duplicate string{}",
+                num_duplicates,
+                if num_duplicates == 1 { "" } else { "s" }
+            );
+        }
         println!(
             "Longest string: {} byte{}",
             jar.max_length,
@@ -130,3 +259,187 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Watch the cookie files which have data files under the directory, and rebuild the data
+/// file whenever its cookie file changes, or is newer than the data file when first seen.
+fn watch(dir: &Path, args: &Args) -> Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!("{} is not a directory.", dir.display());
+    }
+    let silent = args.sflag;
+    let collation = Collation::new(args.collate.as_deref(), args.iflag)?;
+    let walker = Walker::default();
+    let mut seen: BTreeMap<PathBuf, (SystemTime, u64)> = BTreeMap::new();
+    if !silent {
+        println!("Watching {}", dir.display());
+        std::io::stdout().flush()?;
+    }
+    loop {
+        for file in walker.walk(dir)? {
+            let datfile = dat_path(&file);
+            // the files may be removed or replaced meanwhile, and are checked again later
+            let (Ok(meta), Ok(dat_meta)) = (std::fs::metadata(&file), std::fs::metadata(&datfile))
+            else {
+                continue;
+            };
+            let stamp = (meta.modified()?, meta.len());
+            let is_changed = match seen.insert(file.clone(), stamp) {
+                Some(previous) => previous != stamp,
+                // the times may be equal within the granularity of the file system
+                None => dat_meta.modified()? <= stamp.0,
+            };
+            if !is_changed {
+                continue;
+            }
+            match rebuild(&file, &datfile, &collation, args.oflag) {
+                Ok(jar) if !silent => println!(
+                    "'{}' updated, with {} strings",
+                    datfile.display(),
+                    jar.cookies.len()
+                ),
+                Ok(_) => {}
+                // e.g. a data file which is being written, it is skipped until the next change
+                Err(e) => eprintln!("Error: {}: {:#}", file.display(), e),
+            }
+            std::io::stdout().flush()?;
+        }
+        std::thread::sleep(WATCH_INTERVAL);
+    }
+}
+
+/// Build the data file of each cookie file under the directories, found the same way as
+/// `fortune` loads them, and skip those which are up to date.
+fn index_dirs(args: &Args) -> Result<()> {
+    if args.files.is_empty() {
+        anyhow::bail!("No directory given.");
+    }
+    if args.lflag || args.watch.is_some() || args.input_format.is_some() {
+        anyhow::bail!("-R cannot be used with -l, --watch or --input-format.");
+    }
+    let delimch = args.delimch.unwrap_or('%');
+    let format = Serializer::get_type_by_platform(args.platform.as_deref().unwrap_or_default());
+    let collation = Collation::new(args.collate.as_deref(), args.iflag)?;
+    let mut flags = 0;
+    if args.oflag {
+        flags |= cookie::FLAGS_ORDERED;
+    }
+    if args.rflag {
+        flags |= cookie::FLAGS_RANDOMIZED;
+    }
+    if args.xflag {
+        flags |= cookie::FLAGS_ROTATED;
+    }
+    if args.cflag {
+        flags |= cookie::FLAGS_COMMENTS;
+    }
+
+    // the data file does not tell whether it was built with these, so it is always built again
+    let is_forced = args.iflag || args.dedupe || args.collate.is_some();
+
+    let walker = Walker::default();
+    let (mut num_created, mut num_skipped, mut num_failed, mut num_strings) = (0, 0, 0, 0);
+    for dir in args.files.iter().map(Path::new) {
+        if !dir.is_dir() {
+            anyhow::bail!("{} is not a directory.", dir.display());
+        }
+        for file in walker.walk(dir)? {
+            let datfile = dat_path(&file);
+            if !is_forced && is_up_to_date(&file, &datfile, delimch, flags, &format, args.index) {
+                num_skipped += 1;
+                continue;
+            }
+            match index_file(&file, &datfile, delimch, &collation, args, &format) {
+                Ok(jar) => {
+                    num_created += 1;
+                    num_strings += jar.cookies.len();
+                }
+                Err(e) => {
+                    num_failed += 1;
+                    eprintln!("Error: {}: {:#}", file.display(), e);
+                }
+            }
+        }
+    }
+
+    if !args.sflag {
+        println!(
+            "{} data file{} created, {} up to date",
+            num_created,
+            if num_created == 1 { "" } else { "s" },
+            num_skipped
+        );
+        if num_strings == 1 {
+            println!("There was 1 string");
+        } else {
+            println!("There were {} strings", num_strings);
+        }
+    }
+    if num_failed > 0 {
+        anyhow::bail!(
+            "Failed to build {} data file{}.",
+            num_failed,
+            if num_failed == 1 { "" } else { "s" }
+        );
+    }
+    Ok(())
+}
+
+/// Whether the data file is newer than the cookie file, and was built the same way. A data
+/// file which cannot be read, e.g. an empty or truncated one, is out of date.
+fn is_up_to_date(
+    file: &Path,
+    datfile: &Path,
+    delimch: char,
+    flags: u64,
+    format: &SerializerType,
+    index: bool,
+) -> bool {
+    let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    let (Some(source), Some(built)) = (modified(file), modified(datfile)) else {
+        return false;
+    };
+    if built < source {
+        return false;
+    }
+    if index && SearchIndex::read(&file.to_string_lossy()).is_none() {
+        return false;
+    }
+    match read_dat(datfile) {
+        Ok((jar, old_format)) => {
+            let mask = cookie::FLAGS_ORDERED
+                | cookie::FLAGS_RANDOMIZED
+                | cookie::FLAGS_ROTATED
+                | cookie::FLAGS_COMMENTS;
+            jar.delim == delimch && jar.flags & mask == flags && old_format == *format
+        }
+        Err(_) => false,
+    }
+}
+
+/// Build the data file of the cookie file with the flags of the arguments.
+fn index_file(
+    file: &Path,
+    datfile: &Path,
+    delimch: char,
+    collation: &Collation,
+    args: &Args,
+    format: &SerializerType,
+) -> Result<CookieJar> {
+    let textfile = file.to_string_lossy();
+    let mut jar = CookieJar::from_text_file(&textfile, delimch)?;
+    if args.cflag {
+        jar.parse_comments();
+    }
+    if args.index {
+        SearchIndex::from_jar(&jar).write(&textfile)?;
+    }
+    if args.dedupe {
+        Deduper::dedupe_jar(&mut jar);
+    }
+    order(&mut jar, args.oflag.then_some(collation), args.rflag);
+    if args.xflag {
+        jar.flags |= cookie::FLAGS_ROTATED;
+    }
+    atomic::write_file(datfile, &Serializer::to_bytes(&jar, format))?;
+    Ok(jar)
+}
@@ -2,7 +2,8 @@ use assert_cmd::Command;
 use ctor::ctor;
 use env_logger::Env;
 use log::info;
-use std::process::Command as StdCommand;
+use std::path::{Path, PathBuf};
+use std::process::{Command as StdCommand, ExitStatus};
 
 const TEST_DATA_PATH: &str = "tests/data";
 
@@ -18,6 +19,88 @@ fn setup() {
     // info!("[current directory]: {:?}", std::env::current_dir().unwrap());
 }
 
+/// Run our binary with the arguments, split by whitespace, and the environment variables.
+/// Returns the exit status, stdout and stderr.
+fn run(bin: &str, args: &str, env: &[(&str, &str)]) -> (ExitStatus, String, String) {
+    let output = Command::cargo_bin(bin)
+        .unwrap()
+        .args(args.split_whitespace())
+        .envs(env.iter().copied())
+        .output()
+        .expect("msg: failed to execute our implementation");
+    (
+        output.status,
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+/// Assert that each of the expected lines is a line of the output, ignoring the indentation.
+fn assert_lines(command: &str, output: &str, expected: &[&str]) {
+    let lines: Vec<&str> = output.lines().map(str::trim).collect();
+    for line in expected.iter() {
+        assert!(
+            lines.contains(line),
+            "`{}`\n[my_output]:\n{}\n cannot find '{}'",
+            command,
+            output,
+            line
+        );
+    }
+}
+
+/// Assert that each of the expected strings is somewhere in the output.
+fn assert_contains(command: &str, output: &str, expected: &[&str]) {
+    for expected in expected.iter() {
+        assert!(
+            output.contains(expected),
+            "`{}`\n[my_output]:\n{}\n cannot find '{}'",
+            command,
+            output,
+            expected
+        );
+    }
+}
+
+/// A temporary directory of a test, which is removed when the test ends, even if it fails.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("fortune-rs-{}-{}", name, std::process::id()));
+        // left over from an earlier run which was killed
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<std::ffi::OsStr> for TempDir {
+    fn as_ref(&self) -> &std::ffi::OsStr {
+        self.0.as_os_str()
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn test_fortune_flag_m() {
     info!(
@@ -38,13 +121,7 @@ fn test_fortune_flag_m() {
         let ref_stderr = String::from_utf8(ref_output.stderr).unwrap();
 
         // Get our implementation output
-        let output = Command::cargo_bin("fortune")
-            .unwrap()
-            .args(args.split_whitespace().collect::<Vec<&str>>())
-            .output()
-            .expect("msg: failed to execute our implementation");
-        let my_stdout = String::from_utf8(output.stdout).unwrap();
-        let my_stderr = String::from_utf8(output.stderr).unwrap();
+        let (_, my_stdout, my_stderr) = run("fortune", &args, &[]);
 
         // Compare the two outputs
         let my_num_cookies = my_stdout.matches("\n%").count();
@@ -107,13 +184,7 @@ fn test_fortune_flag_i_and_m() {
         let ref_stderr = String::from_utf8(ref_output.stderr).unwrap();
 
         // Get our implementation output
-        let output = Command::cargo_bin("fortune")
-            .unwrap()
-            .args(args.split_whitespace().collect::<Vec<&str>>())
-            .output()
-            .expect("msg: failed to execute our implementation");
-        let my_stdout = String::from_utf8(output.stdout).unwrap();
-        let my_stderr = String::from_utf8(output.stderr).unwrap();
+        let (_, my_stdout, my_stderr) = run("fortune", &args, &[]);
 
         // Compare the two outputs
         let my_num_cookies = my_stdout.matches("\n%").count();
@@ -173,14 +244,7 @@ fn test_fortune_flag_l_and_n() {
     let ref_stdout = String::from_utf8(ref_output.stdout).unwrap();
     let ref_stderr = String::from_utf8(ref_output.stderr).unwrap();
 
-    let output = Command::cargo_bin("fortune")
-        .unwrap()
-        .args(args.split_whitespace().collect::<Vec<&str>>())
-        .output()
-        .expect("msg: failed to execute our implementation");
-
-    let my_stdout = String::from_utf8(output.stdout).unwrap();
-    let my_stderr = String::from_utf8(output.stderr).unwrap();
+    let (_, my_stdout, my_stderr) = run("fortune", &args, &[]);
 
     assert_eq!(
         ref_stdout, my_stdout,
@@ -206,14 +270,7 @@ fn test_fortune_flag_s_and_n() {
     let ref_stdout = String::from_utf8(ref_output.stdout).unwrap();
     let ref_stderr = String::from_utf8(ref_output.stderr).unwrap();
 
-    let output = Command::cargo_bin("fortune")
-        .unwrap()
-        .args(args.split_whitespace().collect::<Vec<&str>>())
-        .output()
-        .expect("msg: failed to execute our implementation");
-
-    let my_stdout = String::from_utf8(output.stdout).unwrap();
-    let my_stderr = String::from_utf8(output.stderr).unwrap();
+    let (_, my_stdout, my_stderr) = run("fortune", &args, &[]);
 
     assert_eq!(
         ref_stdout, my_stdout,
@@ -238,14 +295,7 @@ fn test_fortune_flag_f() {
     let ref_stdout = String::from_utf8(ref_output.stdout).unwrap();
     let ref_stderr = String::from_utf8(ref_output.stderr).unwrap();
 
-    let output = Command::cargo_bin("fortune")
-        .unwrap()
-        .args(args.split_whitespace().collect::<Vec<&str>>())
-        .output()
-        .expect("msg: failed to execute our implementation");
-
-    let my_stdout = String::from_utf8(output.stdout).unwrap();
-    let my_stderr = String::from_utf8(output.stderr).unwrap();
+    let (_, my_stdout, my_stderr) = run("fortune", &args, &[]);
 
     assert_eq!(
         ref_stdout, my_stdout,
@@ -305,14 +355,7 @@ fn test_fortune_flag_f_and_e() {
     let ref_stdout = String::from_utf8(ref_output.stdout).unwrap();
     let ref_stderr = String::from_utf8(ref_output.stderr).unwrap();
 
-    let output = Command::cargo_bin("fortune")
-        .unwrap()
-        .args(args.split_whitespace().collect::<Vec<&str>>())
-        .output()
-        .expect("msg: failed to execute our implementation");
-
-    let my_stdout = String::from_utf8(output.stdout).unwrap();
-    let my_stderr = String::from_utf8(output.stderr).unwrap();
+    let (_, my_stdout, my_stderr) = run("fortune", &args, &[]);
 
     assert_eq!(
         ref_stdout, my_stdout,
@@ -368,14 +411,7 @@ fn test_fortune_flag_f_and_e() {
 #[test]
 fn test_fortune_flag_c_and_o() {
     let args = format!("-c -o {}", TEST_DATA_PATH);
-    let output = Command::cargo_bin("fortune")
-        .unwrap()
-        .args(args.split_whitespace().collect::<Vec<&str>>())
-        .output()
-        .expect("msg: failed to execute our implementation");
-
-    let my_stdout = String::from_utf8(output.stdout).unwrap();
-    let my_stderr = String::from_utf8(output.stderr).unwrap();
+    let (_, my_stdout, my_stderr) = run("fortune", &args, &[]);
 
     let msg = format!(
         "`fortune {}`\n[my_stdout]:\n{}\n[my_stderr]:\n{}",
@@ -454,6 +490,32 @@ fn test_fortune_flag_probs() {
                 "23.81% dog",
             ],
         ),
+        (
+            "-f 30% tests/data tests/data2",
+            [
+                "30.00% tests/data",
+                "13.64% apple",
+                "2.73% one",
+                "13.64% orange",
+                "0.00% zero",
+                "70.00% tests/data2",
+                "35.00% cat",
+                "35.00% dog",
+            ],
+        ),
+        (
+            "-f --weights relative 3 tests/data 1 tests/data2",
+            [
+                "75.00% tests/data",
+                "34.09% apple",
+                "6.82% one",
+                "34.09% orange",
+                "0.00% zero",
+                "25.00% tests/data2",
+                "12.50% cat",
+                "12.50% dog",
+            ],
+        ),
         (
             "-f -e tests/data tests/data2",
             [
@@ -470,46 +532,23 @@ fn test_fortune_flag_probs() {
     ];
 
     for (args, expected) in testcases.iter() {
-        let output = Command::cargo_bin("fortune")
-            .unwrap()
-            .args(args.split_whitespace().collect::<Vec<&str>>())
-            .output()
-            .expect("msg: failed to execute our implementation");
-
-        // let my_stdout = String::from_utf8(output.stdout).unwrap();
-        let my_stderr = String::from_utf8(output.stderr).unwrap();
+        let (_, _, my_stderr) = run("fortune", args, &[]);
 
-        // Compare the two outputs
-        let my_lines: Vec<&str> = my_stderr.lines().map(|l| l.trim()).collect::<Vec<&str>>();
-        for expected_line in expected.iter() {
-            assert!(
-                my_lines.contains(expected_line),
-                "`fortune {}`\n[expected_stderr]:\n{}\n[my_stderr]:\n{}",
-                args,
-                expected.join("\n"),
-                my_stderr
-            );
-        }
+        assert_lines(&format!("fortune {}", args), &my_stderr, expected);
     }
 }
 
 #[test]
 fn test_fortune_embed() {
     let args = "-c";
-    let output = Command::cargo_bin("fortune")
-        .unwrap()
-        .args(args.split_whitespace().collect::<Vec<&str>>())
-        .output()
-        .expect("msg: failed to execute our implementation");
+    let (_, my_stdout, my_stderr) = run("fortune", args, &[]);
 
-    let my_stdout = String::from_utf8(output.stdout).unwrap();
-    let my_stderr = String::from_utf8(output.stderr).unwrap();
     let msg = format!(
         "`fortune {}`\n[my_stdout]:\n{}\n[my_stderr]:\n{}",
         args, my_stdout, my_stderr
     );
     assert!(
-        my_stdout.len() > 0,
+        !my_stdout.is_empty(),
         "{}\n - expected: non-empty stdout, got: empty",
         msg
     );
@@ -520,14 +559,7 @@ fn test_fortune_embed() {
 fn test_fortune_flag_d() {
     let args = format!("-D {}", TEST_DATA_PATH);
 
-    let output = Command::cargo_bin("fortune")
-        .unwrap()
-        .args(args.split_whitespace().collect::<Vec<&str>>())
-        .output()
-        .expect("msg: failed to execute our implementation");
-
-    let my_stdout = String::from_utf8(output.stdout).unwrap();
-    let my_stderr = String::from_utf8(output.stderr).unwrap();
+    let (_, my_stdout, my_stderr) = run("fortune", &args, &[]);
 
     let msg = format!(
         "`fortune {}`\n[my_stdout]:\n{}\n[my_stderr]:\n{}",
@@ -535,12 +567,12 @@ fn test_fortune_flag_d() {
     );
 
     assert!(
-        my_stdout.len() > 0,
+        !my_stdout.is_empty(),
         "{}\n - expected: non-empty stdout, got: empty",
         msg
     );
     assert!(
-        my_stderr.len() > 0,
+        !my_stderr.is_empty(),
         "{}\n - expected: non-empty stderr, got: empty",
         msg
     );
@@ -554,18 +586,14 @@ fn test_fortune_flag_d() {
 
 #[test]
 fn test_strfile() {
-    let args = format!("tests/data/apple tests/data/apple.dat");
+    let args = "tests/data/apple tests/data/apple.dat".to_string();
     let ref_output = StdCommand::new("strfile")
         .args(args.split_whitespace().collect::<Vec<&str>>())
         .output()
         .expect("msg: failed to execute reference implementation");
     //  read the output "tests/data/apple.dat" in bytes
     let ref_bytes = std::fs::read("tests/data/apple.dat").unwrap();
-    let my_output = Command::cargo_bin("strfile")
-        .unwrap()
-        .args(args.split_whitespace().collect::<Vec<&str>>())
-        .output()
-        .expect("msg: failed to execute our implementation");
+    let (_, my_stdout, my_stderr) = run("strfile", &args, &[]);
     let my_bytes = std::fs::read("tests/data/apple.dat").unwrap();
 
     let msg = format!(
@@ -573,23 +601,108 @@ fn test_strfile() {
         args,
         String::from_utf8(ref_output.stdout).unwrap(),
         String::from_utf8(ref_output.stderr).unwrap(),
-        String::from_utf8(my_output.stdout).unwrap(),
-        String::from_utf8(my_output.stderr).unwrap()
+        my_stdout,
+        my_stderr
     );
     assert_eq!(ref_bytes, my_bytes, "{}", msg);
 }
 
+#[test]
+fn test_strfile_flag_o_and_i() {
+    let dir = TempDir::new("order");
+    let input = dir.join("mixed");
+    // the same strings as test_collation_bsd
+    std::fs::write(
+        &input,
+        "\"Zebra,\" he said.\n%\napple\n%\n...and then\n%\nBanana\n%\n  -- 42\n%\nbanana split\n%\nApple\n%\n",
+    )
+    .unwrap();
+    let (ref_dat, my_dat) = (dir.join("ref.dat"), dir.join("my.dat"));
+    // the orders of test_collation_bsd, of a single file and of the directories alike
+    let testcases = [
+        (
+            "-o",
+            vec![
+                "  -- 42",
+                "Apple",
+                "Banana",
+                "\"Zebra,\" he said.",
+                "...and then",
+                "apple",
+                "banana split",
+            ],
+        ),
+        (
+            "-o -i",
+            vec![
+                "  -- 42",
+                "...and then",
+                "apple",
+                "Apple",
+                "Banana",
+                "banana split",
+                "\"Zebra,\" he said.",
+            ],
+        ),
+    ];
+    let nested = dir.join("nested");
+    std::fs::create_dir_all(&nested).unwrap();
+    std::fs::copy(&input, nested.join("mixed")).unwrap();
+    for (options, expected) in testcases.iter() {
+        Command::cargo_bin("strfile")
+            .unwrap()
+            .args(options.split_whitespace())
+            .args(["-s", "--platform", "linux"])
+            .arg(&input)
+            .arg(&my_dat)
+            .assert()
+            .success();
+        assert_eq!(*expected, dat_strings(&input, &my_dat), "{}", options);
+        Command::cargo_bin("strfile")
+            .unwrap()
+            .args(options.split_whitespace())
+            .args(["-s", "-R", "--platform", "linux"])
+            .arg(&nested)
+            .assert()
+            .success();
+        let (textfile, datfile) = (nested.join("mixed"), nested.join("mixed.dat"));
+        assert_eq!(
+            *expected,
+            dat_strings(&textfile, &datfile),
+            "-R {}",
+            options
+        );
+    }
+
+    for (options, _) in testcases.iter() {
+        let ref_output = StdCommand::new("strfile")
+            .args(options.split_whitespace())
+            .arg(&input)
+            .arg(&ref_dat)
+            .output()
+            .expect("msg: failed to execute reference implementation");
+        let (_, _, my_stderr) = run(
+            "strfile",
+            &format!("{} {} {}", options, input.display(), my_dat.display()),
+            &[],
+        );
+        let msg = format!(
+            "`strfile {}`, \n[ref_stderr]:\n{}\n[my_stderr]:\n{}",
+            options,
+            String::from_utf8_lossy(&ref_output.stderr),
+            my_stderr
+        );
+        let ref_bytes = std::fs::read(&ref_dat).expect(&msg);
+        let my_bytes = std::fs::read(&my_dat).expect(&msg);
+        assert_eq!(ref_bytes, my_bytes, "{}", msg);
+    }
+}
+
 #[test]
 fn test_strfile_flags_l() {
-    let args = format!("-l tests/data/apple");
+    let args = "-l tests/data/apple".to_string();
 
-    let output = Command::cargo_bin("strfile")
-        .unwrap()
-        .args(args.split_whitespace().collect::<Vec<&str>>())
-        .output()
-        .expect("msg: failed to execute our implementation");
-    let my_stdout = String::from_utf8(output.stdout).unwrap();
-    let my_stderr = String::from_utf8(output.stderr).unwrap();
+    let (_, my_stdout, my_stderr) = run("strfile", &args, &[]);
 
     let msg = format!(
         "`strfile {}`, \n[my_stdout]:\n{}\n[my_stderr]:\n{}",
@@ -605,3 +718,1171 @@ fn test_strfile_flags_l() {
     assert!(my_stdout.contains("file_size: 202"), "{}", msg);
     assert!(my_stdout.contains("flags: []"), "{}", msg);
 }
+
+#[test]
+fn test_fortune_flag_list_packs() {
+    let args = "--list-packs";
+    let (_, my_stdout, my_stderr) = run("fortune", args, &[]);
+
+    let msg = format!(
+        "`fortune {}`\n[my_stdout]:\n{}\n[my_stderr]:\n{}",
+        args, my_stdout, my_stderr
+    );
+
+    let my_lines: Vec<&str> = my_stdout.lines().collect::<Vec<&str>>();
+    for expected_line in ["en/", "    fortunes", "zh/", "    lunyu"] {
+        assert!(
+            my_lines.contains(&expected_line),
+            "{}\n cannot find '{}'",
+            msg,
+            expected_line
+        );
+    }
+}
+
+#[test]
+fn test_fortune_layers() {
+    let args = "-f en";
+    let (_, my_stdout, my_stderr) = run(
+        "fortune",
+        args,
+        &[
+            ("XDG_DATA_HOME", "tests/data3"),
+            ("XDG_DATA_DIRS", "tests/notfound"),
+        ],
+    );
+
+    let msg = format!(
+        "`fortune {}`\n[my_stdout]:\n{}\n[my_stderr]:\n{}",
+        args, my_stdout, my_stderr
+    );
+
+    // the data directory shadows 'en/fortunes' and adds 'en/extra' to the embedded pack
+    let layer = std::path::Path::new("tests/data3").join("fortune-rs");
+    let expected_lines = [
+        "100.00% embed:en".to_string(),
+        format!("66.67% en/fortunes [{}]", layer.display()),
+        format!("33.33% en/extra [{}]", layer.display()),
+    ];
+    assert_eq!(expected_lines.len(), my_stderr.lines().count(), "{}", msg);
+    let expected_lines: Vec<&str> = expected_lines.iter().map(String::as_str).collect();
+    assert_lines(&format!("fortune {}", args), &my_stderr, &expected_lines);
+}
+
+#[test]
+fn test_strfile_input_format() {
+    let dir = TempDir::new("import");
+
+    for format in ["json", "csv"] {
+        let infile = dir.join(format!("quotes.{}", format));
+        std::fs::copy(format!("tests/import/quotes.{}", format), &infile).unwrap();
+        let args = format!(
+            "--input-format {} --text-field quote --author-field by --tag-field tags {}",
+            format,
+            infile.display()
+        );
+        let (status, my_stdout, my_stderr) = run("strfile", &args, &[]);
+        let msg = format!(
+            "`strfile {}`, \n[my_stdout]:\n{}\n[my_stderr]:\n{}",
+            args, my_stdout, my_stderr
+        );
+        assert!(status.success(), "{}", msg);
+        assert!(my_stdout.contains("There were 3 strings"), "{}", msg);
+
+        let text = std::fs::read_to_string(dir.join("quotes")).unwrap();
+        assert_eq!(
+            "%% author: Alice\n%% tags: fruit, red\nApples are red.\n\t\t-- Alice\n%\n\
+             %% author: Bob\nOranges are orange.\nAnd round.\n\t\t-- Bob\n%\n\
+             Bananas are yellow.\n%\n",
+            text,
+            "{}",
+            msg
+        );
+
+        let args = format!("-l {}", dir.join("quotes").display());
+        let (_, my_stdout, _) = run("strfile", &args, &[]);
+        assert!(my_stdout.contains("num_cookies: 3"), "{}", my_stdout);
+        assert!(my_stdout.contains("flags: [COMMENTS]"), "{}", my_stdout);
+    }
+}
+
+#[test]
+fn test_fortune_export() {
+    let testcases = [
+        ("--export ndjson tests/data", 11),
+        ("--export ndjson -a tests/data", 12),
+        ("--export ndjson -o tests/data", 1),
+        ("--export ndjson -s -n 40 tests/data", 5),
+        ("--export ndjson -l -n 40 tests/data", 6),
+        ("--export ndjson -m Orange tests/data", 1),
+    ];
+
+    for (args, expected_num_cookies) in testcases.iter() {
+        let (status, my_stdout, my_stderr) = run("fortune", args, &[]);
+        let msg = format!(
+            "`fortune {}`\n[my_stdout]:\n{}\n[my_stderr]:\n{}",
+            args, my_stdout, my_stderr
+        );
+        assert!(status.success(), "{}", msg);
+        assert_eq!(*expected_num_cookies, my_stdout.lines().count(), "{}", msg);
+        for line in my_stdout.lines() {
+            for key in [
+                "\"shelf\":",
+                "\"jar\":",
+                "\"offset\":",
+                "\"length\":",
+                "\"content\":",
+            ] {
+                assert!(line.contains(key), "{}\n cannot find {}", msg, key);
+            }
+        }
+    }
+
+    let args = "--export csv -m Orange tests/data";
+    let (_, my_stdout, _) = run("fortune", args, &[]);
+    assert_eq!(
+        "shelf,jar,layer,offset,length,content,metadata\n\
+         tests/data,orange,,194,44,Oranges are the sunshine of the fruit world.,\n",
+        my_stdout,
+        "`fortune {}`",
+        args
+    );
+}
+
+#[test]
+fn test_fortune_compressed() {
+    use std::io::Write;
+
+    let dir = TempDir::new("compressed");
+
+    let content = std::fs::read("tests/data/apple").unwrap();
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&content).unwrap();
+    std::fs::write(dir.join("apple.gz"), encoder.finish().unwrap()).unwrap();
+    let content = std::fs::read("tests/data/orange").unwrap();
+    let bytes = ruzstd::encoding::compress_to_vec(
+        content.as_slice(),
+        ruzstd::encoding::CompressionLevel::Fastest,
+    );
+    std::fs::write(dir.join("orange.zst"), bytes).unwrap();
+
+    let args = format!("-f {}", dir.display());
+    let (_, _, my_stderr) = run("fortune", &args, &[]);
+    assert_lines(
+        &format!("fortune {}", args),
+        &my_stderr,
+        &["50.00% apple", "50.00% orange"],
+    );
+
+    // indexing the compressed file should give the same index as the uncompressed one
+    std::fs::copy("tests/data/apple", dir.join("plain")).unwrap();
+    for infile in ["apple.gz", "plain"] {
+        let args = format!("{}", dir.join(infile).display());
+        let (status, my_stdout, _) = run("strfile", &args, &[]);
+        assert!(status.success(), "`strfile {}`\n{}", args, my_stdout);
+        assert!(my_stdout.contains("There were 5 strings"), "{}", my_stdout);
+    }
+    assert_eq!(
+        std::fs::read(dir.join("plain.dat")).unwrap(),
+        std::fs::read(dir.join("apple.dat")).unwrap(),
+        "strfile should index the uncompressed content"
+    );
+}
+
+#[test]
+fn test_fortune_traversal() {
+    let dir = TempDir::new("traversal");
+    std::fs::create_dir_all(dir.join("citrus")).unwrap();
+    std::fs::copy("tests/data/apple", dir.join("apple")).unwrap();
+    std::fs::copy("tests/data/orange", dir.join("citrus/orange")).unwrap();
+    std::fs::write(dir.join("README"), "Not a fortune file\n").unwrap();
+    std::fs::write(dir.join(".fortuneignore"), "# docs\nREADME\n").unwrap();
+
+    let testcases = [
+        ("", vec!["50.00% apple", "50.00% citrus/orange"], "README"),
+        ("--max-depth 1", vec!["100.00% apple"], "orange"),
+    ];
+    for (options, expected_lines, unexpected) in testcases.iter() {
+        let args = format!("-f {} {}", options, dir.display());
+        let (_, _, my_stderr) = run("fortune", &args, &[]);
+        assert_lines(&format!("fortune {}", args), &my_stderr, expected_lines);
+        assert!(
+            !my_stderr.contains(unexpected),
+            "`fortune {}`\n[my_stderr]:\n{}\n should not contain '{}'",
+            args,
+            my_stderr,
+            unexpected
+        );
+    }
+}
+
+#[test]
+fn test_fortune_arg_errors() {
+    let testcases = [
+        (vec!["abc%", "tests/data"], 3, "invalid weight 'abc%'"),
+        (
+            vec!["--", "-5%", "tests/data"],
+            4,
+            "weight cannot be negative",
+        ),
+        (
+            vec!["tests/data", "50%"],
+            5,
+            "weight '50%' is not followed by a file or directory",
+        ),
+        (vec!["-m", "a(b", "tests/data"], 6, "invalid pattern 'a(b'"),
+        (
+            vec!["60%", "tests/data", "50%", "tests/data2"],
+            7,
+            "probabilities sum to 110% > 100%",
+        ),
+        (
+            vec!["60%", "tests/data", "30%", "tests/data2"],
+            7,
+            "no place to put residual probability (10%)",
+        ),
+        (
+            vec!["60%", "tests/data", "40%", "tests/data2", "tests/data3"],
+            7,
+            "no probability left to put in residual files",
+        ),
+        (
+            vec![
+                "--weights",
+                "relative",
+                "0",
+                "tests/data",
+                "0",
+                "tests/data2",
+            ],
+            7,
+            "all weights are zero",
+        ),
+    ];
+
+    for (args, code, expected) in testcases.iter() {
+        let output = Command::cargo_bin("fortune")
+            .unwrap()
+            .args(args)
+            .output()
+            .expect("msg: failed to execute our implementation");
+        let my_stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(
+            Some(*code),
+            output.status.code(),
+            "`fortune {}`\n[my_stderr]:\n{}",
+            args.join(" "),
+            my_stderr
+        );
+        assert!(
+            my_stderr.contains(expected),
+            "`fortune {}`\n[my_stderr]:\n{}\n cannot find '{}'",
+            args.join(" "),
+            my_stderr,
+            expected
+        );
+    }
+
+    // the position of the error in the pattern should be shown
+    let (_, _, my_stderr) = run("fortune", "-m a(b tests/data", &[]);
+    assert!(my_stderr.contains("     ^ at position 1"), "{}", my_stderr);
+    // only once, without the display of the regex engine
+    assert_eq!(1, my_stderr.matches('^').count(), "{}", my_stderr);
+    assert_eq!(3, my_stderr.lines().count(), "{}", my_stderr);
+}
+
+#[test]
+fn test_fortune_weights() {
+    let dir = TempDir::new("weights");
+    std::fs::create_dir_all(dir.join("numbers")).unwrap();
+    std::fs::copy("tests/data/apple", dir.join("apple")).unwrap();
+    std::fs::copy("tests/data/orange", dir.join("orange")).unwrap();
+    std::fs::copy("tests/data/one", dir.join("numbers/one")).unwrap();
+    std::fs::write(dir.join("weights.toml"), "apple = 3\nnumbers = 2\n").unwrap();
+
+    let testcases = [
+        (
+            "-f",
+            [
+                "68.18% apple (weight: 3)",
+                "22.73% orange",
+                "9.09% numbers/one (weight: 2)",
+            ],
+        ),
+        (
+            "-f -e",
+            [
+                "50.00% apple (weight: 3)",
+                "16.67% orange",
+                "33.33% numbers/one (weight: 2)",
+            ],
+        ),
+    ];
+    for (options, expected) in testcases.iter() {
+        let args = format!("{} {}", options, dir.display());
+        let (_, _, my_stderr) = run("fortune", &args, &[]);
+        assert_lines(&format!("fortune {}", args), &my_stderr, expected);
+        assert!(!my_stderr.contains("weights.toml"), "{}", my_stderr);
+    }
+
+    // a file weighted zero is never chosen, and nothing is left without the others
+    std::fs::create_dir_all(dir.join("zero")).unwrap();
+    std::fs::copy("tests/data/apple", dir.join("zero/apple")).unwrap();
+    std::fs::write(dir.join("zero/weights.toml"), "apple = 0\n").unwrap();
+    let (status, _, my_stderr) = run("fortune", &format!("{}", dir.join("zero").display()), &[]);
+    assert_eq!(Some(1), status.code(), "{}", my_stderr);
+    assert!(
+        my_stderr.contains("Not found any fortune cookies"),
+        "{}",
+        my_stderr
+    );
+    let orange = std::fs::read_to_string("tests/data/orange").unwrap();
+    for _ in 0..10 {
+        let (status, my_stdout, _) = run(
+            "fortune",
+            &format!(
+                "{} {}",
+                dir.join("zero").display(),
+                dir.join("orange").display()
+            ),
+            &[],
+        );
+        assert!(status.success(), "{}", my_stdout);
+        assert!(orange.contains(my_stdout.trim()), "{}", my_stdout);
+    }
+
+    // a number naming an existing file is the file, not a weight
+    std::fs::copy("tests/data/one", dir.join("10")).unwrap();
+    let args = "-f --weights relative 3 10 1 apple";
+    let output = Command::cargo_bin("fortune")
+        .unwrap()
+        .current_dir(&dir)
+        .args(args.split_whitespace().collect::<Vec<&str>>())
+        .output()
+        .expect("msg: failed to execute our implementation");
+    let my_stderr = String::from_utf8(output.stderr).unwrap();
+    assert_lines(
+        &format!("fortune {}", args),
+        &my_stderr,
+        &["75.00% 10", "25.00% apple"],
+    );
+}
+
+#[test]
+fn test_fortune_stats() {
+    let args = "--stats json -n 30 tests/data tests/data2";
+    let (status, my_stdout, _) = run("fortune", args, &[]);
+    assert!(status.success(), "`fortune {}`", args);
+    let stats: serde_json::Value = serde_json::from_str(&my_stdout).unwrap();
+    assert_eq!(30, stats["length"]);
+    assert_eq!(6, stats["jars"].as_array().unwrap().len());
+    assert_eq!("apple", stats["jars"][0]["jar"]);
+    assert_eq!(5, stats["jars"][0]["cookies"]);
+    assert_eq!(21, stats["total"]["cookies"]);
+    assert_eq!(
+        21,
+        stats["total"]["short"].as_u64().unwrap() + stats["total"]["long"].as_u64().unwrap()
+    );
+
+    let args = "--stats table tests/data tests/data2";
+    let (_, my_stdout, _) = run("fortune", args, &[]);
+    assert_contains(
+        &format!("fortune {}", args),
+        &my_stdout,
+        &[
+            "Total: 21 cookies in 6 jars (21 normal, 0 offensive)",
+            "Cutoff (-n 160): 21 short, 0 long",
+        ],
+    );
+}
+
+#[test]
+fn test_fortune_duplicates_and_strfile_dedupe() {
+    let dir = TempDir::new("dedupe");
+    std::fs::write(
+        dir.join("bsd"),
+        "Apple a day.\n%\nKeep it simple.\n%\nApple a day.\n%\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("extras"), "keep it  SIMPLE!\n%\nBanana.\n%\n").unwrap();
+
+    let args = format!("--duplicates {}", dir.display());
+    let (_, my_stdout, _) = run("fortune", &args, &[]);
+    assert_contains(
+        &format!("fortune {}", args),
+        &my_stdout,
+        &[
+            "exact duplicate (2): Apple a day.",
+            "near duplicate (2): Keep it simple.",
+            ": bsd @ 0",
+            ": extras @ 0",
+            "Found 1 exact and 1 near duplicate groups",
+        ],
+    );
+
+    let args = format!("--dedupe {}", dir.join("bsd").display());
+    let (_, my_stdout, _) = run("strfile", &args, &[]);
+    assert!(my_stdout.contains("There were 2 strings"), "{}", my_stdout);
+    assert!(
+        my_stdout.contains("Dropped 1 duplicate string"),
+        "{}",
+        my_stdout
+    );
+}
+
+#[test]
+fn test_fortune_lint() {
+    let dir = TempDir::new("lint");
+    std::fs::write(dir.join("clean"), "apple\n%\nbanana\n%\n").unwrap();
+    std::fs::write(dir.join("messy"), "apple\r\n%  \n%\nbanana\tsplit\n").unwrap();
+
+    let args = format!("{}", dir.display());
+    let (status, my_stdout, _) = run("fortune-lint", &args, &[]);
+    assert_eq!(Some(1), status.code());
+    assert_contains(
+        &format!("fortune-lint {}", args),
+        &my_stdout,
+        &[
+            "messy:1: mixed-line-endings",
+            "messy:2: delimiter-whitespace",
+            "messy:3: empty-cookie",
+            "messy:4: tab",
+            "messy:4: missing-delimiter",
+            "in 1 of 2 files",
+        ],
+    );
+
+    // only the tab is left after fixing
+    let args = format!("--fix {}", dir.display());
+    let (status, my_stdout, _) = run("fortune-lint", &args, &[]);
+    assert_eq!(Some(1), status.code());
+    assert!(
+        my_stdout.contains("1 problem in 1 of 2 files"),
+        "{}",
+        my_stdout
+    );
+    assert_eq!(
+        "apple\n%\nbanana\tsplit\n%\n",
+        std::fs::read_to_string(dir.join("messy")).unwrap()
+    );
+
+    let args = format!("--allow tab {}", dir.display());
+    let (status, _, _) = run("fortune-lint", &args, &[]);
+    assert_eq!(Some(0), status.code());
+}
+
+#[test]
+fn test_fortune_search_index() {
+    let dir = TempDir::new("index");
+    let file = dir.join("fruits");
+    std::fs::write(&file, "Apple pie.\n%\nBanana split.\n%\nPineapple.\n%\n").unwrap();
+
+    let args = format!("--index {}", file.display());
+    let (_, my_stdout, _) = run("strfile", &args, &[]);
+    assert!(my_stdout.contains("fruits.idx' created"), "{}", my_stdout);
+
+    // the index narrows the cookies, and the stale index is ignored
+    for (content, expected) in [
+        (None, "Apple pie.\n%\nPineapple.\n%\n"),
+        (
+            Some("Apple pie.\n%\nApple crumble.\n%\n"),
+            "Apple pie.\n%\nApple crumble.\n%\n",
+        ),
+    ] {
+        if let Some(content) = content {
+            std::fs::write(&file, content).unwrap();
+        }
+        let args = format!("-D -i -m APPLE {}", dir.display());
+        let (_, my_stdout, my_stderr) = run("fortune", &args, &[]);
+        assert_eq!(expected, my_stdout, "`fortune {}`\n{}", args, my_stderr);
+        assert_eq!(
+            content.is_none(),
+            my_stderr.contains("candidates: 3 => 2"),
+            "`fortune {}`\n{}",
+            args,
+            my_stderr
+        );
+    }
+}
+
+#[test]
+fn test_fortune_boolean_patterns() {
+    let testcases = [
+        (
+            "-m orange -m peel tests/data",
+            vec!["Life is like an orange", "The best oranges"],
+            vec!["An orange a day", "Apples are red"],
+        ),
+        (
+            "--any -m doctor -m sweet tests/data",
+            vec!["An orange a day", "Apples are very sweet"],
+            vec!["Life is like an orange", "Apples are red"],
+        ),
+        (
+            "-i -m orange --exclude-pattern peel tests/data",
+            vec!["An orange a day", "Oranges are the sunshine"],
+            vec!["Life is like an orange", "The best oranges"],
+        ),
+    ];
+    for (args, expected, unexpected) in testcases.iter() {
+        let (_, my_stdout, _) = run("fortune", args, &[]);
+        assert_contains(&format!("fortune {}", args), &my_stdout, expected);
+        for u in unexpected.iter() {
+            assert!(
+                !my_stdout.contains(u),
+                "`fortune {}`\n[my_stdout]:\n{}\n should not find '{}'",
+                args,
+                my_stdout,
+                u
+            );
+        }
+    }
+
+    // -D describes the query
+    let args = "-D --any -i -m doctor -m sweet --exclude-pattern apple -s tests/data";
+    let (_, _, my_stderr) = run("fortune", args, &[]);
+    assert_contains(
+        &format!("fortune {}", args),
+        &my_stderr,
+        &["filters: length <= 160 AND (/doctor/i OR /sweet/i) AND NOT /apple/i"],
+    );
+}
+
+#[test]
+fn test_fortune_normalize() {
+    let dir = TempDir::new("normalize");
+    std::fs::write(
+        dir.join("words"),
+        "A \u{fb01}ne day.\n%\nCafe\u{301} au lait.\n%\nＦｕｌｌ width.\n%\n",
+    )
+    .unwrap();
+
+    let testcases = [
+        ("-m fine", ""),
+        ("--normalize nfkc -m fine", "A \u{fb01}ne day.\n%\n"),
+        ("--normalize nfkc -m Full", "Ｆｕｌｌ width.\n%\n"),
+        ("-m Café", ""),
+        ("--normalize nfc -m Café", "Cafe\u{301} au lait.\n%\n"),
+    ];
+    for (args, expected) in testcases.iter() {
+        let args = format!("{} {}", args, dir.display());
+        let (_, my_stdout, _) = run("fortune", &args, &[]);
+        assert_eq!(*expected, my_stdout, "`fortune {}`", args);
+    }
+}
+
+#[cfg(feature = "unicode")]
+#[test]
+fn test_fortune_unicode_regex() {
+    let dir = TempDir::new("unicode");
+    std::fs::write(
+        dir.join("words"),
+        "學而時習之，不亦說乎？\n%\nКОТ и пёс.\n%\nÉCOLE.\n%\n",
+    )
+    .unwrap();
+
+    let testcases = [
+        ("-m \\p{Han}+", "學而時習之，不亦說乎？\n%\n"),
+        ("-i -m кот", "КОТ и пёс.\n%\n"),
+        ("-i -m école", "ÉCOLE.\n%\n"),
+    ];
+    for (args, expected) in testcases.iter() {
+        let args = format!("{} {}", args, dir.display());
+        let (_, my_stdout, _) = run("fortune", &args, &[]);
+        assert_eq!(*expected, my_stdout, "`fortune {}`", args);
+    }
+}
+
+/// Send a GET request to the server, and return the status code and the body.
+fn http_get(addr: &str, target: &str) -> (u16, String) {
+    use std::io::{Read, Write};
+    let mut stream = std::net::TcpStream::connect(addr).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, addr).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+    (status, body.to_string())
+}
+
+/// Wait until the condition holds, checking it every now and then, or fail after a while.
+fn wait_until(what: &str, mut condition: impl FnMut() -> bool) {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    while !condition() {
+        assert!(std::time::Instant::now() < deadline, "timed out: {}", what);
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+/// The strings of the cookie file in the order of its data file, which is of the linux format.
+fn dat_strings(textfile: &std::path::Path, datfile: &std::path::Path) -> Vec<String> {
+    let text = std::fs::read_to_string(textfile).unwrap();
+    let bytes = std::fs::read(datfile).unwrap();
+    let word = |i: usize| u32::from_be_bytes(bytes[i * 4..i * 4 + 4].try_into().unwrap()) as usize;
+    // the header is 6 words, and the number of strings is the second
+    (0..word(1))
+        .map(|i| {
+            let start = word(6 + i);
+            let end = text[start..]
+                .find("\n%\n")
+                .map_or(text.len(), |e| start + e);
+            text[start..end].to_string()
+        })
+        .collect()
+}
+
+/// Kill the server process when the test ends, even if it fails.
+struct ServerGuard(std::process::Child);
+
+impl Drop for ServerGuard {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn test_fortune_serve() {
+    use std::io::BufRead;
+    let dir = TempDir::new("serve");
+    std::fs::create_dir_all(dir.join("off")).unwrap();
+    std::fs::write(dir.join("fruits"), "Apple pie.\n%\nBanana split.\n%\n").unwrap();
+    std::fs::write(dir.join("off/jokes"), "A rude joke.\n%\n").unwrap();
+
+    let mut server = ServerGuard(
+        StdCommand::new(assert_cmd::cargo::cargo_bin("fortune"))
+            .args(["serve", "--listen", "127.0.0.1:0", "--reload-interval", "0"])
+            .arg(&dir)
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .expect("msg: failed to start the server"),
+    );
+    let mut line = String::new();
+    std::io::BufReader::new(server.0.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    let addr = line
+        .trim()
+        .trim_start_matches("Listening on http://")
+        .to_string();
+
+    let testcases = [
+        ("/fortune?m=Apple", 200, "Apple pie.\n"),
+        (
+            "/fortune?m=Apple&m=split",
+            404,
+            "Not found any fortune cookies\n",
+        ),
+        ("/fortune?o", 200, "A rude joke.\n"),
+        ("/fortune/fruits/13", 200, "Banana split.\n"),
+        ("/fortune/fruits/5", 404, "No fortune cookie: fruits/5\n"),
+        (
+            "/search?any&m=apple&m=SPLIT&i",
+            200,
+            "Apple pie.\n%\nBanana split.\n%\n",
+        ),
+        (
+            "/search?a&m=joke&c",
+            200,
+            "(off/jokes)\n%\nA rude joke.\n%\n",
+        ),
+        (
+            "/search?s",
+            400,
+            "Missing pattern, e.g. /search?m=PATTERN\n",
+        ),
+        ("/files?e", 200, "100.00% "),
+        ("/fortune?export=json", 400, "Unknown parameter: export\n"),
+        ("/fortune?m=a(b", 400, "invalid pattern"),
+        ("/nowhere", 404, "Unknown route: /nowhere\n"),
+    ];
+    for (target, status, expected) in testcases.iter() {
+        let (my_status, my_body) = http_get(&addr, target);
+        assert_eq!(*status, my_status, "GET {}\n{}", target, my_body);
+        assert!(
+            my_body.starts_with(expected) || my_body.contains(expected),
+            "GET {}\n[my_body]:\n{}\n cannot find '{}'",
+            target,
+            my_body,
+            expected
+        );
+    }
+
+    let (_, body) = http_get(&addr, "/fortune?m=Banana&format=json");
+    let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!("fruits/13", value["id"]);
+    assert_eq!("Banana split.", value["content"]);
+
+    // the changed sources are reloaded
+    std::fs::write(dir.join("fruits"), "Cherry tart.\n%\n").unwrap();
+    let (status, body) = http_get(&addr, "/search?m=.&format=json");
+    assert_eq!(200, status);
+    let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!("Cherry tart.", value[0]["content"]);
+    assert_eq!(1, value.as_array().unwrap().len());
+
+    drop(server);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_fortune_daemon() {
+    use std::io::BufRead;
+    let dir = TempDir::new("daemon");
+    std::fs::create_dir_all(dir.join("served/off")).unwrap();
+    std::fs::create_dir_all(dir.join("local")).unwrap();
+    std::fs::write(
+        dir.join("served/fruits"),
+        "Apple pie.\n%\nBanana split.\n%\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("served/off/jokes"), "A rude joke.\n%\n").unwrap();
+    std::fs::write(dir.join("local/fruits"), "Cherry tart.\n%\n").unwrap();
+    let socket = dir.join("fortune.sock");
+
+    let mut daemon = ServerGuard(
+        StdCommand::new(assert_cmd::cargo::cargo_bin("fortune"))
+            .arg("daemon")
+            .arg("--socket")
+            .arg(&socket)
+            .arg(dir.join("served"))
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .expect("msg: failed to start the daemon"),
+    );
+    let mut line = String::new();
+    std::io::BufReader::new(daemon.0.stdout.take().unwrap())
+        .read_line(&mut line)
+        .unwrap();
+    assert!(line.starts_with("Listening on "), "{}", line);
+
+    // an idle connection does not hold up the others
+    {
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+        let _idle = UnixStream::connect(&socket).unwrap();
+        let start = std::time::Instant::now();
+        let mut stream = UnixStream::connect(&socket).unwrap();
+        writeln!(stream, r#"["-m","Apple"]"#).unwrap();
+        let mut response = String::new();
+        std::io::BufReader::new(&stream)
+            .read_line(&mut response)
+            .unwrap();
+        assert!(response.contains("Apple pie."), "{}", response);
+        assert!(start.elapsed() < std::time::Duration::from_secs(4));
+    }
+
+    // the daemon answers for the sources it serves, with their weights
+    let testcases = [
+        ("-m Apple", "Apple pie.\n%\n"),
+        (
+            "-m apple -m SPLIT -i --any",
+            "Apple pie.\n%\nBanana split.\n%\n",
+        ),
+        ("-o", "A rude joke.\n"),
+        ("-c -m Banana", "Banana split.\n%\n"),
+        ("-m Banana 100%", "Banana split.\n%\n"),
+        ("-m Banana --weights relative 3", "Banana split.\n%\n"),
+    ];
+    for (args, expected) in testcases.iter() {
+        let (_, my_stdout, my_stderr) = run(
+            "fortune",
+            &format!(
+                "--client --socket {} {} {}",
+                socket.display(),
+                args,
+                dir.join("served").display()
+            ),
+            &[],
+        );
+        assert_eq!(
+            *expected, my_stdout,
+            "`fortune --client {}`\n[my_stderr]:\n{}",
+            args, my_stderr
+        );
+    }
+
+    // no matches is an answer as well
+    let (status, _, my_stderr) = run(
+        "fortune",
+        &format!(
+            "--client --socket {} -m Cherry {}",
+            socket.display(),
+            dir.join("served").display()
+        ),
+        &[],
+    );
+    assert!(!status.success());
+    assert!(my_stderr.contains("No matching fortune cookies"));
+
+    // the sources the daemon does not serve, and the options of loading, are loaded locally
+    for args in [vec![], vec!["--max-depth", "1"], vec!["--follow-symlinks"]] {
+        Command::cargo_bin("fortune")
+            .unwrap()
+            .arg("--client")
+            .arg("--socket")
+            .arg(&socket)
+            .args(&args)
+            .arg(dir.join("local"))
+            .assert()
+            .success()
+            .stdout("Cherry tart.\n");
+    }
+    let (status, _, _) = run(
+        "fortune",
+        &format!(
+            "--client --socket {} -m Apple --max-depth 0 {}",
+            socket.display(),
+            dir.join("served").display()
+        ),
+        &[],
+    );
+    assert!(!status.success());
+
+    // without the daemon, the fortunes are loaded locally
+    Command::cargo_bin("fortune")
+        .unwrap()
+        .arg("--client")
+        .arg("--socket")
+        .arg(dir.join("nowhere.sock"))
+        .arg(dir.join("local"))
+        .assert()
+        .success()
+        .stdout("Cherry tart.\n");
+
+    // a second daemon on the same socket is refused
+    let (status, _, my_stderr) = run(
+        "fortune",
+        &format!(
+            "daemon --socket {} {}",
+            socket.display(),
+            dir.join("served").display()
+        ),
+        &[],
+    );
+    assert!(!status.success());
+    assert!(my_stderr.contains("already running"));
+
+    drop(daemon);
+}
+
+#[test]
+fn test_completions() {
+    let testcases = [
+        ("fortune", "bash", "--pattern"),
+        (
+            "fortune",
+            "bash",
+            "$(fortune --complete-sources 2>/dev/null)",
+        ),
+        (
+            "fortune",
+            "zsh",
+            "'*'{-a,--all}'[choose from all lists of maxims",
+        ),
+        ("fortune", "fish", "complete -c fortune -s m -l pattern -x"),
+        ("strfile", "bash", "--dedupe"),
+        ("strfile", "zsh", "'*:file:_files'"),
+        ("strfile", "fish", "complete -c strfile -s o -l oflag"),
+    ];
+    for (command, shell, expected) in testcases.iter() {
+        let args = format!("--completions {}", shell);
+        let (status, my_stdout, _) = run(command, &args, &[]);
+        assert!(status.success());
+        assert_contains(&format!("{} {}", command, args), &my_stdout, &[expected]);
+    }
+
+    let (status, _, _) = run("fortune", "--completions tcsh", &[]);
+    assert!(!status.success());
+
+    // the sources are listed when completing, not when the script is generated
+    let (status, my_stdout, _) = run("fortune", "--complete-sources", &[]);
+    assert!(status.success());
+    let names: Vec<&str> = my_stdout.lines().collect();
+    assert!(names.contains(&"en/fortunes"), "{}", my_stdout);
+    // every completed name is a source that fortune accepts
+    for name in names.iter() {
+        let (status, _, my_stderr) = run("fortune", &format!("-f {}", name), &[]);
+        assert!(status.success(), "`fortune -f {}`\n{}", name, my_stderr);
+    }
+
+    // the value of another option is not taken for --completions
+    let (status, my_stdout, my_stderr) = run(
+        "strfile",
+        "-c % --platform --completions tests/data/nowhere",
+        &[],
+    );
+    assert!(!status.success());
+    assert!(my_stdout.is_empty());
+    assert!(my_stderr.contains("tests/data/nowhere"));
+}
+
+#[cfg(not(feature = "tui"))]
+#[test]
+fn test_fortune_browse_without_tui() {
+    let (status, _, my_stderr) = run("fortune", "browse", &[]);
+    assert!(!status.success());
+    assert!(my_stderr.contains("--features tui"));
+}
+
+#[test]
+fn test_strfile_watch() {
+    use std::io::BufRead;
+    let dir = TempDir::new("watch");
+    let fruits = dir.join("fruits");
+    std::fs::write(&fruits, "Cherry tart.\n%\nApple pie.\n%\n").unwrap();
+    std::fs::write(dir.join("plain"), "No data file.\n%\n").unwrap();
+    let (status, _, _) = run(
+        "strfile",
+        &format!("-s -o -x --platform linux {}", fruits.display()),
+        &[],
+    );
+    assert!(status.success());
+
+    // the data files are ordered ignoring case, which the data file does not keep
+    let mut watcher = ServerGuard(
+        StdCommand::new(assert_cmd::cargo::cargo_bin("strfile"))
+            .arg("--watch")
+            .arg(&dir)
+            .arg("-i")
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .expect("msg: failed to start the watcher"),
+    );
+    let mut stdout = std::io::BufReader::new(watcher.0.stdout.take().unwrap());
+    let mut line = String::new();
+    stdout.read_line(&mut line).unwrap();
+    assert!(line.starts_with("Watching "), "{}", line);
+
+    let datfile = dir.join("fruits.dat");
+    let dat_info = || {
+        let (_, my_stdout, _) = run("strfile", &format!("-l {}", fruits.display()), &[]);
+        my_stdout.to_string()
+    };
+
+    // the data file is rebuilt with the flags it had
+    std::fs::write(
+        &fruits,
+        "Cherry tart.\n%\nApple pie.\n%\nbanana split.\n%\n",
+    )
+    .unwrap();
+    wait_until("the data file of 3 strings", || {
+        dat_info().contains("num_cookies: 3")
+    });
+    line.clear();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(
+        format!("'{}' updated, with 3 strings\n", datfile.display()),
+        line
+    );
+
+    let my_stdout = dat_info();
+    assert!(
+        my_stdout.contains("flags: [ORDERED, ROTATED]"),
+        "{}",
+        my_stdout
+    );
+    let (_, my_stdout, _) = run("fortune", &format!("-m . {}", fruits.display()), &[]);
+    assert!(my_stdout.contains("banana split."));
+    assert_eq!(
+        vec!["Apple pie.", "banana split.", "Cherry tart."],
+        dat_strings(&fruits, &datfile)
+    );
+
+    // a broken data file is skipped, and the watcher keeps running
+    std::fs::write(dir.join("broken.dat"), "").unwrap();
+    std::fs::write(dir.join("broken"), "Half written.\n%\n").unwrap();
+    std::fs::write(&fruits, "Cherry tart.\n%\nApple pie.\n%\n").unwrap();
+    wait_until("the data file of 2 strings", || {
+        dat_info().contains("num_cookies: 2")
+    });
+    line.clear();
+    stdout.read_line(&mut line).unwrap();
+    assert_eq!(
+        format!("'{}' updated, with 2 strings\n", datfile.display()),
+        line
+    );
+    assert!(watcher.0.try_wait().unwrap().is_none());
+
+    // only the cookie files which have a data file are watched
+    assert!(!dir.join("plain.dat").exists());
+    drop(watcher);
+}
+
+#[test]
+fn test_strfile_recursive() {
+    let dir = TempDir::new("recursive");
+    std::fs::create_dir_all(dir.join("nested")).unwrap();
+    std::fs::write(dir.join("fruits"), "Cherry tart.\n%\nApple pie.\n%\n").unwrap();
+    std::fs::write(dir.join("nested/veg"), "Carrot cake.\n%\n").unwrap();
+    std::fs::write(dir.join(".hidden"), "Not a cookie file.\n%\n").unwrap();
+
+    let strfile = |args: &str| {
+        let (status, my_stdout, my_stderr) =
+            run("strfile", &format!("{} {}", args, dir.display()), &[]);
+        let my_stdout = my_stdout.to_string();
+        assert!(
+            status.success(),
+            "`strfile {}`\n[my_stdout]:\n{}\n[my_stderr]:\n{}",
+            args,
+            my_stdout,
+            my_stderr
+        );
+        my_stdout
+    };
+
+    let testcases = [
+        (
+            "-R -o",
+            "2 data files created, 0 up to date\nThere were 3 strings\n",
+        ),
+        (
+            "-R -o",
+            "0 data files created, 2 up to date\nThere were 0 strings\n",
+        ),
+        // built again with the other flags
+        (
+            "-R -r",
+            "2 data files created, 0 up to date\nThere were 3 strings\n",
+        ),
+        ("-R -r -s", ""),
+    ];
+    for (args, expected) in testcases.iter() {
+        assert_eq!(*expected, strfile(args), "`strfile {}`", args);
+    }
+    assert!(dir.join("fruits.dat").exists());
+    assert!(dir.join("nested/veg.dat").exists());
+    assert!(!dir.join(".hidden.dat").exists());
+
+    // a changed cookie file is built again
+    std::fs::write(dir.join("nested/veg"), "Carrot cake.\n%\nPea soup.\n%\n").unwrap();
+    std::fs::File::options()
+        .write(true)
+        .open(dir.join("nested/veg"))
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() + std::time::Duration::from_secs(60))
+        .unwrap();
+    assert_eq!(
+        "1 data file created, 1 up to date\nThere were 2 strings\n",
+        strfile("-R -r")
+    );
+    let (_, my_stdout, _) = run(
+        "strfile",
+        &format!("-l {}", dir.join("nested/veg").display()),
+        &[],
+    );
+    assert!(my_stdout.contains("num_cookies: 2"), "{}", my_stdout);
+    assert!(my_stdout.contains("flags: [RANDOM]"), "{}", my_stdout);
+
+    // a broken data file is built again, even if it is newer than the cookie file
+    std::fs::File::options()
+        .write(true)
+        .open(dir.join("nested/veg"))
+        .unwrap()
+        .set_modified(std::time::SystemTime::now() - std::time::Duration::from_secs(60))
+        .unwrap();
+    std::fs::write(dir.join("fruits.dat"), "").unwrap();
+    assert_eq!(
+        "1 data file created, 1 up to date\nThere were 2 strings\n",
+        strfile("-R -r")
+    );
+    // the options which are not kept in the data file build all of them again
+    assert_eq!(
+        "2 data files created, 0 up to date\nThere were 4 strings\n",
+        strfile("-R -r --dedupe")
+    );
+}
+
+#[cfg(not(feature = "collate"))]
+#[test]
+fn test_strfile_collate_without_feature() {
+    let (status, _, my_stderr) = run(
+        "strfile",
+        "-o --collate de tests/data/apple /nonexistent/apple.dat",
+        &[],
+    );
+    assert!(!status.success());
+    assert!(my_stderr.contains("--features collate"));
+}
+
+#[cfg(feature = "collate")]
+#[test]
+fn test_strfile_collate() {
+    let dir = TempDir::new("collate");
+    let input = dir.join("fruits");
+    std::fs::write(&input, "zebra\n%\nÄpfel\n%\napple\n%\n").unwrap();
+
+    // --collate orders the strings without -o as well
+    Command::cargo_bin("strfile")
+        .unwrap()
+        .args(["-s", "--collate", "de", "--platform", "linux"])
+        .arg(&input)
+        .assert()
+        .success();
+    let datfile = dir.join("fruits.dat");
+    assert_eq!(
+        vec!["Äpfel", "apple", "zebra"],
+        dat_strings(&input, &datfile)
+    );
+    let (_, my_stdout, _) = run("strfile", &format!("-l {}", input.display()), &[]);
+    assert!(my_stdout.contains("flags: [ORDERED]"), "{}", my_stdout);
+
+    // the same for the directories
+    Command::cargo_bin("strfile")
+        .unwrap()
+        .args(["-s", "-R", "--collate", "sv", "--platform", "linux"])
+        .arg(&dir)
+        .assert()
+        .success();
+    assert_eq!(
+        vec!["apple", "zebra", "Äpfel"],
+        dat_strings(&input, &datfile)
+    );
+}
+
+#[test]
+fn test_strfile_write_error() {
+    let (status, _, my_stderr) = run("strfile", "tests/data/apple /nonexistent/apple.dat", &[]);
+    // an error with the path, instead of a panic
+    assert_eq!(Some(1), status.code(), "{}", my_stderr);
+    assert!(
+        my_stderr.contains("Failed to write /nonexistent/apple.dat"),
+        "{}",
+        my_stderr
+    );
+
+    // a truncated data file is an error with the path as well
+    let dir = TempDir::new("truncated");
+    let datfile = dir.join("apple.dat");
+    let bytes = std::fs::read("tests/data/apple.dat").unwrap();
+    std::fs::write(&datfile, &bytes[..bytes.len() - 3]).unwrap();
+    let (status, _, my_stderr) = run(
+        "strfile",
+        &format!("-l {}", dir.join("apple").display()),
+        &[],
+    );
+    assert_eq!(Some(1), status.code(), "{}", my_stderr);
+    assert!(
+        my_stderr.contains(&datfile.display().to_string()),
+        "{}",
+        my_stderr
+    );
+}
+
+#[test]
+fn test_strfile_comments() {
+    let dir = TempDir::new("comments");
+    let file = dir.join("fruits");
+    std::fs::write(&file, "%% author: Bob\n%% a note\nApple pie.\n%\n").unwrap();
+    let fortune = || {
+        let (_, my_stdout, _) = run("fortune", &format!("-m Apple {}", file.display()), &[]);
+        my_stdout.to_string()
+    };
+
+    // the comments are a part of the cookie, unless the data file has the comments flag
+    assert_eq!("%% author: Bob\n%% a note\nApple pie.\n%\n", fortune());
+    let (status, _, _) = run("strfile", &format!("-s -C {}", file.display()), &[]);
+    assert!(status.success());
+    assert_eq!("%% a note\nApple pie.\n%\n", fortune());
+}